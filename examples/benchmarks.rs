@@ -1,3 +1,5 @@
+#![allow(clippy::iter_nth_zero, clippy::println_empty_string, clippy::redundant_field_names, clippy::needless_borrow, clippy::uninlined_format_args)]
+
 use std::fs;
 use std::path::PathBuf;
 use std::fmt::Debug;
@@ -6,6 +8,8 @@ use std::str::FromStr;
 use std::time::Instant;
 
 use dary::DoubleArray;
+use dary::DoubleArrayBuilder;
+use dary::Layout;
 use dary::Trie;
 
 use rand::{Rng, thread_rng};
@@ -37,6 +41,26 @@ fn run(bits: u32) {
 	println!("");
 	println!("benchmark 2 start");
 	sub_2(&keys);
+	println!("");
+	println!("benchmark 3 start");
+	sub_3(&keys);
+	println!("");
+	println!("benchmark 4 start");
+	sub_4(&keys);
+
+	// ひらがなと漢字からなる日本語のキー
+	let mut rng = thread_rng();
+	let mut japanese_keys: Vec<String> = Vec::new();
+	for _ in 0..len {
+		let key_len = rng.gen_range(2, 9);
+		japanese_keys.push((0..key_len).map(|_| {
+			let code = if rng.gen_bool(0.5) { 0x3041 + rng.gen_range(0, 83) } else { 0x4E00 + rng.gen_range(0, 3000) };
+			std::char::from_u32(code).unwrap()
+		}).collect());
+	}
+	println!("");
+	println!("benchmark 5 start (japanese keys)");
+	sub_4(&japanese_keys);
 }
 
 
@@ -117,6 +141,55 @@ fn sub_2(keys: &[String]) {
 	fs::remove_file(path).unwrap();
 }
 
+fn sub_3(keys: &[String]) {
+	for &layout in &[Layout::Separate, Layout::Interleaved, Layout::Compact] {
+		// DoubleArray構築
+		let mut trie: Trie<u32> = Trie::new();
+		for (i, key) in keys.iter().enumerate() {
+			trie.set(&key, i as u32);
+		}
+		let start = Instant::now();
+		let double_array: DoubleArray<u32> = DoubleArrayBuilder::new().layout(layout).build(trie).unwrap();
+		println!("build double array ({:?}): {} sec", layout, get_duration(start));
+
+		// DoubleArrayダンプ
+		let mut path: PathBuf = env::current_dir().unwrap();
+		path.push("benchmarks_sub_3.dic");
+		let double_array = double_array.dump(path.to_str().unwrap()).unwrap();
+		let size = fs::metadata(&path).unwrap().len();
+
+		// 検索
+		let start = Instant::now();
+		for (i, key) in keys.iter().enumerate() {
+			assert!(double_array.get(&key).unwrap().contains(&(i as u32)));
+		}
+		println!("get all data ({:?}, {} bytes): {} sec", layout, size, get_duration(start));
+
+		fs::remove_file(path).unwrap();
+	}
+}
+
+fn sub_4(keys: &[String]) {
+	let max_threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+	let mut threads = 1;
+	while threads <= max_threads {
+		// DoubleArray構築
+		let mut trie: Trie<u32> = Trie::new();
+		for (i, key) in keys.iter().enumerate() {
+			trie.set(&key, i as u32);
+		}
+		let start = Instant::now();
+		let double_array: DoubleArray<u32> = DoubleArrayBuilder::new().threads(threads).build(trie).unwrap();
+		println!("build double array ({} threads): {} sec", threads, get_duration(start));
+
+		// 検索
+		for (i, key) in keys.iter().enumerate() {
+			assert!(double_array.get(&key).unwrap().contains(&(i as u32)));
+		}
+		threads *= 2;
+	}
+}
+
 fn get_duration(start: Instant) -> f64 {
 	let dur = start.elapsed();
 	dur.as_nanos() as f64 / 1_000_000_000.0
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::io;
+
+use crate::format::invalid_data;
+
+/// 1バイトで表す符号の数
+/// 0xF0 以降は複数バイトの符号の先頭バイトに使う
+const SINGLE_CODES: u32 = 0xF0;
+
+/// 2バイトの符号の先頭バイトの数 (0xF0..=0xFB)
+const DOUBLE_LEADS: u32 = 12;
+
+/// 3バイトの符号の先頭バイト
+const TRIPLE_LEAD: u8 = 0xFC;
+
+/// 4バイトの符号の先頭バイト
+const QUADRUPLE_LEAD: u8 = 0xFD;
+
+/// 遷移のラベルの単位
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Alphabet {
+    /// キーのUTF-8のバイト列をそのままラベルにする
+    #[default]
+    Byte,
+    /// キーの文字を、出現頻度の高い順に小さい符号を割り当てた符号表で変換してからラベルにする
+    /// 頻度の高い240文字は1遷移、次の2880文字は2遷移で表すので、日本語のキーの遷移が少なくなる
+    CodePoint,
+}
+
+/// 文字と符号の対応表
+/// 符号は文字の出現頻度の順位で、順位は1〜4バイトの可変長のバイト列にしてラベルにする。
+/// 符号のバイト列は 0xFE, 0xFF を含まないので、終端のラベルや未使用の要素を表すラベルと衝突しない。
+///
+/// | 順位                | バイト列                                         |
+/// |---------------------|--------------------------------------------------|
+/// | 0 〜 239            | 順位                                             |
+/// | 240 〜 3119         | 0xF0 + (順位 - 240) / 240, (順位 - 240) % 240     |
+/// | 3120 〜 60719       | 0xFC, 240進数2桁                                  |
+/// | 60720 〜            | 0xFD, 240進数3桁                                  |
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CodeTable {
+    /// 順位ごとの文字
+    chars: Vec<char>,
+    /// 文字から順位を引く表
+    ranks: HashMap<char, u32>,
+}
+
+impl CodeTable {
+    /// キーの文字の出現回数から符号表を作る
+    /// 出現回数が同じ文字は文字コードの順に並べるので、同じキーからは同じ符号表になる
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - 辞書のキー
+    pub(crate) fn from_keys<'a, I: IntoIterator<Item = &'a str>>(keys: I) -> Self {
+        let mut counts: HashMap<char, usize> = HashMap::new();
+        for key in keys {
+            for c in key.chars() {
+                *counts.entry(c).or_insert(0) += 1;
+            }
+        }
+        let mut counts: Vec<(char, usize)> = counts.into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        Self::from_chars(counts.into_iter().map(|(c, _)| c).collect())
+    }
+
+    /// 順位の順に並んだ文字から符号表を作る
+    fn from_chars(chars: Vec<char>) -> Self {
+        let ranks = chars.iter().enumerate().map(|(rank, &c)| (c, rank as u32)).collect();
+        CodeTable { chars, ranks }
+    }
+
+    /// 文字の符号をbufに追加する。符号表にない文字の場合はfalseを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `c`   - 文字
+    /// * `buf` - 符号を追加するバイト列
+    pub(crate) fn encode_char(&self, c: char, buf: &mut Vec<u8>) -> bool {
+        let rank = match self.ranks.get(&c) {
+            Some(&rank) => rank,
+            None => return false,
+        };
+        if rank < SINGLE_CODES {
+            buf.push(rank as u8);
+            return true;
+        }
+        let mut rank = rank - SINGLE_CODES;
+        if rank < DOUBLE_LEADS * SINGLE_CODES {
+            buf.push((SINGLE_CODES + rank / SINGLE_CODES) as u8);
+            buf.push((rank % SINGLE_CODES) as u8);
+            return true;
+        }
+        rank -= DOUBLE_LEADS * SINGLE_CODES;
+        let (lead, digits) = if rank < SINGLE_CODES * SINGLE_CODES {
+            (TRIPLE_LEAD, 2)
+        } else {
+            rank -= SINGLE_CODES * SINGLE_CODES;
+            (QUADRUPLE_LEAD, 3)
+        };
+        buf.push(lead);
+        for i in (0..digits).rev() {
+            buf.push((rank / SINGLE_CODES.pow(i) % SINGLE_CODES) as u8);
+        }
+        true
+    }
+
+    /// キーを符号のバイト列に変換する。符号表にない文字を含む場合はNoneを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - キー
+    pub(crate) fn encode(&self, key: &str) -> Option<Vec<u8>> {
+        let mut buf: Vec<u8> = Vec::with_capacity(key.len());
+        for c in key.chars() {
+            if !self.encode_char(c, &mut buf) {
+                return None;
+            }
+        }
+        Some(buf)
+    }
+
+    /// 符号の先頭バイトから、符号のバイト数を返す
+    ///
+    /// # Arguments
+    ///
+    /// * `lead` - 符号の先頭バイト
+    pub(crate) fn code_len(lead: u8) -> usize {
+        match lead {
+            TRIPLE_LEAD    => 3,
+            QUADRUPLE_LEAD => 4,
+            _ if (lead as u32) < SINGLE_CODES => 1,
+            _ => 2,
+        }
+    }
+
+    /// 1文字分の符号を文字に変換する。符号表にない符号の場合はNoneを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - 1文字分の符号
+    pub(crate) fn decode_char(&self, code: &[u8]) -> Option<char> {
+        let digits = |bytes: &[u8]| bytes.iter().fold(0, |rank, &b| rank * SINGLE_CODES + b as u32);
+        let rank = match code.len() {
+            1 => code[0] as u32,
+            2 => SINGLE_CODES + (code[0] as u32 - SINGLE_CODES) * SINGLE_CODES + code[1] as u32,
+            3 => SINGLE_CODES + DOUBLE_LEADS * SINGLE_CODES + digits(&code[1..]),
+            _ => SINGLE_CODES + DOUBLE_LEADS * SINGLE_CODES + SINGLE_CODES * SINGLE_CODES + digits(&code[1..]),
+        };
+        self.chars.get(rank as usize).copied()
+    }
+
+    /// 符号のバイト列をキーに変換する
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - 符号のバイト列
+    pub(crate) fn decode(&self, bytes: &[u8]) -> Option<String> {
+        let mut key = String::with_capacity(bytes.len() * 3);
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let len = Self::code_len(bytes[pos]);
+            key.push(self.decode_char(bytes.get(pos..(pos + len))?)?);
+            pos += len;
+        }
+        Some(key)
+    }
+
+    /// 辞書ファイルのセクションに格納するバイト列
+    /// 順位の順に文字コードをu32 (リトルエンディアン) で並べる
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        self.chars.iter().flat_map(|&c| (c as u32).to_le_bytes()).collect()
+    }
+
+    /// セクションのバイト列から符号表を復元する
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - セクションのバイト列
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, io::Error> {
+        if !bytes.len().is_multiple_of(4) {
+            return Err(invalid_data("invalid code table section"));
+        }
+        let chars = bytes.chunks(4)
+            .map(|b| char::from_u32(u32::from_le_bytes([b[0], b[1], b[2], b[3]])))
+            .collect::<Option<Vec<char>>>()
+            .ok_or_else(|| invalid_data("invalid code table section"))?;
+        let table = Self::from_chars(chars);
+        if table.ranks.len() != table.chars.len() {
+            return Err(invalid_data("duplicate characters in code table section"));
+        }
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_table() {
+        let table = CodeTable::from_keys(vec!["すもも", "もも", "a"]);
+        // 出現回数の多い順、同じ回数なら文字コードの順
+        assert_eq!(vec!['も', 'a', 'す'], table.chars);
+        assert_eq!(Some(vec![2, 0, 0]), table.encode("すもも"));
+        assert_eq!(None, table.encode("うち"));
+        assert_eq!(Some(String::from("もすa")), table.decode(&[0, 2, 1]));
+    }
+
+    #[test]
+    fn test_code_len() {
+        // すべての順位の境界で符号化と復元が一致する
+        let chars: Vec<char> = (0..0x11000).filter_map(char::from_u32).collect();
+        let table = CodeTable::from_chars(chars.clone());
+        for &rank in [0, 239, 240, 3119, 3120, 60719, 60720, chars.len() - 1].iter() {
+            let mut buf = Vec::new();
+            assert!(table.encode_char(chars[rank], &mut buf));
+            assert_eq!(buf.len(), CodeTable::code_len(buf[0]));
+            assert!(buf.iter().all(|&b| b < 0xFE));
+            assert_eq!(Some(chars[rank]), table.decode_char(&buf));
+        }
+        assert_eq!(table, CodeTable::from_bytes(&table.to_bytes()).unwrap());
+        assert!(CodeTable::from_bytes(&[0, 0, 0]).is_err());
+        assert!(CodeTable::from_bytes(&[0, 0, 0xFF, 0]).is_err());
+    }
+}
@@ -0,0 +1,553 @@
+//! 辞書ファイルを扱うコマンドラインツール
+
+use std::any;
+use std::env;
+use std::fmt::{Debug, Display};
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+
+use dary::{Alphabet, DiffEntry, DoubleArray, DoubleArrayBuilder, Layout, Normalizer, Trie};
+use dary::format::METADATA_VALUE_TYPE;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use serde_json::json;
+
+const USAGE: &str = "\
+usage: dary <command> [options]
+
+commands:
+  build [options] <INPUT> <OUTPUT>
+      ソースファイルから辞書を構築する。<INPUT> に - を指定すると標準入力から読み込む
+      --format tsv|csv|jsonl   入力の形式 (デフォルトは拡張子から判定し、不明な場合は tsv)
+                               tsv, csv: 1列目がキー、2列目が値
+                               jsonl   : 1行に1件の {\"key\": ..., \"value\": ...}
+      --type TYPE              値の型 u32|i32|u64|i64|f64|string (デフォルトは string)
+      --layout LAYOUT          separate|interleaved|compact (デフォルトは separate)
+      --alphabet ALPHABET      byte|codepoint (デフォルトは byte)
+      --normalize NAMES        キーの正規化 nfkc,case_fold,width_fold,kana_fold をカンマで区切って指定する
+      --threads N              構築に使うスレッド数
+      --tail                   TAIL圧縮を行う
+      --compress               data配列を圧縮する
+      --sort-values            同じキーの値を並べ替える
+  get <DICTIONARY> <KEY>
+      キーの値を出力する
+  prefix <DICTIONARY> <KEY>
+      キーの接頭辞になっている登録済みのキーと値を出力する
+  predict [--limit N] <DICTIONARY> <PREFIX>
+      接頭辞で始まる登録済みのキーと値を出力する
+  dump [--format text|json] <DICTIONARY>
+      すべてのキーと値を出力する
+  stats <DICTIONARY>
+      辞書のサイズ・充填率・メタデータを出力する
+  verify <DICTIONARY>
+      ヘッダと各セクションのチェックサム、ダブル配列の構造と値を検証する
+  diff [--format text|json] <OLD> <NEW>
+      2つの辞書の差分を出力する
+      text: 追加は '+', 削除は '-', 変更は '~' で始まるタブ区切りの行
+      json: 1行に1件のJSON (JSON Lines)
+
+値は JSON で出力する。text 形式ではキーと値をタブで区切る。";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command: fn(&[String]) -> Result<(), io::Error> = match args.first().map(String::as_str) {
+        Some("build")   => build,
+        Some("get")     => get,
+        Some("prefix")  => prefix,
+        Some("predict") => predict,
+        Some("dump")    => dump,
+        Some("stats")   => stats,
+        Some("verify")  => verify,
+        Some("diff")    => diff,
+        _ => {
+            eprintln!("{USAGE}");
+            process::exit(2);
+        },
+    };
+    if let Err(e) = command(&args[1..]) {
+        // 出力先のパイプが閉じられた場合は正常終了とする
+        if e.kind() == io::ErrorKind::BrokenPipe {
+            return;
+        }
+        eprintln!("error: {e}");
+        process::exit(1);
+    }
+}
+
+/// 辞書に格納できる値の型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValueType {
+    U32,
+    I32,
+    U64,
+    I64,
+    F64,
+    String,
+}
+
+impl ValueType {
+    const ALL: [ValueType; 6] = [ValueType::U32, ValueType::I32, ValueType::U64, ValueType::I64, ValueType::F64, ValueType::String];
+
+    /// コマンドラインで指定された名前から値の型を返す
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - 値の型の名前
+    fn from_name(name: &str) -> Result<ValueType, io::Error> {
+        ValueType::ALL.iter()
+            .find(|value_type| value_type.name() == name)
+            .copied()
+            .ok_or_else(|| invalid_input(&format!("unknown value type '{name}'")))
+    }
+
+    /// 辞書に記録された値の型名から値の型を判定する
+    ///
+    /// # Arguments
+    ///
+    /// * `dictionary_path` - 辞書ファイルパス
+    fn of(dictionary_path: &str) -> Result<ValueType, io::Error> {
+        let type_name = open_unchecked(dictionary_path)?.metadata().remove(METADATA_VALUE_TYPE).unwrap_or_default();
+        ValueType::ALL.iter()
+            .find(|value_type| value_type.type_name() == type_name)
+            .copied()
+            .ok_or_else(|| invalid_input(&format!("{dictionary_path}: unsupported value type '{type_name}'")))
+    }
+
+    /// コマンドラインで指定する名前
+    fn name(self) -> &'static str {
+        match self {
+            ValueType::U32    => "u32",
+            ValueType::I32    => "i32",
+            ValueType::U64    => "u64",
+            ValueType::I64    => "i64",
+            ValueType::F64    => "f64",
+            ValueType::String => "string",
+        }
+    }
+
+    /// 値の型の `std::any::type_name`
+    fn type_name(self) -> &'static str {
+        match self {
+            ValueType::U32    => any::type_name::<u32>(),
+            ValueType::I32    => any::type_name::<i32>(),
+            ValueType::U64    => any::type_name::<u64>(),
+            ValueType::I64    => any::type_name::<i64>(),
+            ValueType::F64    => any::type_name::<f64>(),
+            ValueType::String => any::type_name::<String>(),
+        }
+    }
+}
+
+/// 値の型に応じて型引数を指定して関数を呼び出す
+macro_rules! with_value_type {
+    ($value_type:expr, $func:ident($($arg:expr),*)) => {
+        match $value_type {
+            ValueType::U32    => $func::<u32>($($arg),*),
+            ValueType::I32    => $func::<i32>($($arg),*),
+            ValueType::U64    => $func::<u64>($($arg),*),
+            ValueType::I64    => $func::<i64>($($arg),*),
+            ValueType::F64    => $func::<f64>($($arg),*),
+            ValueType::String => $func::<String>($($arg),*),
+        }
+    };
+}
+
+/// コマンドで扱う値の型が満たすトレイト
+trait Value: Serialize + DeserializeOwned + Debug + PartialEq + FromStr<Err = <Self as Value>::ParseError> {
+    type ParseError: Display;
+}
+
+impl<T> Value for T
+    where T: Serialize + DeserializeOwned + Debug + PartialEq + FromStr,
+          T::Err: Display,
+{
+    type ParseError = T::Err;
+}
+
+/// コマンドの引数
+struct Args {
+    positional: Vec<String>,
+    options   : Vec<(String, String)>,
+    flags     : Vec<String>,
+}
+
+impl Args {
+    /// `--name value` 形式のオプション, `--name` 形式のフラグと位置引数に分ける
+    ///
+    /// # Arguments
+    ///
+    /// * `args`    - コマンド名を除いた引数
+    /// * `options` - 値を取るオプションの名前
+    /// * `flags`   - 値を取らないオプションの名前
+    fn parse(args: &[String], options: &[&str], flags: &[&str]) -> Result<Args, io::Error> {
+        let mut ret = Args { positional: Vec::new(), options: Vec::new(), flags: Vec::new() };
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.strip_prefix("--") {
+                Some(name) if options.contains(&name) => {
+                    let value = iter.next().ok_or_else(|| invalid_input(&format!("option '{arg}' requires a value")))?;
+                    ret.options.push((name.to_string(), value.clone()));
+                },
+                Some(name) if flags.contains(&name) => ret.flags.push(name.to_string()),
+                Some(_) => return Err(invalid_input(&format!("unknown option '{arg}'"))),
+                None    => ret.positional.push(arg.clone()),
+            }
+        }
+        Ok(ret)
+    }
+
+    /// オプションの値を返す。複数回指定された場合は最後の値
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - オプションの名前
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.iter().rev().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// オプションの値を数値として返す
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - オプションの名前
+    fn number_option(&self, name: &str) -> Result<Option<usize>, io::Error> {
+        self.option(name)
+            .map(|value| value.parse().map_err(|_| invalid_input(&format!("option '--{name}' requires a number"))))
+            .transpose()
+    }
+
+    /// フラグが指定されたかどうか
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - フラグの名前
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|n| n == name)
+    }
+
+    /// 位置引数の数を検証して返す
+    ///
+    /// # Arguments
+    ///
+    /// * `names` - 位置引数の名前
+    fn positional(&self, names: &[&str]) -> Result<&[String], io::Error> {
+        if self.positional.len() != names.len() {
+            return Err(invalid_input(&format!("expected arguments: {}", names.join(" "))));
+        }
+        Ok(&self.positional)
+    }
+}
+
+/// 出力の形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl OutputFormat {
+    fn parse(format: Option<&str>) -> Result<OutputFormat, io::Error> {
+        match format {
+            None | Some("text") => Ok(OutputFormat::Text),
+            Some("json")        => Ok(OutputFormat::Json),
+            Some(format)        => Err(invalid_input(&format!("unknown format '{format}'"))),
+        }
+    }
+}
+
+/// 構築に使うソースファイルの形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputFormat {
+    Tsv,
+    Csv,
+    JsonLines,
+}
+
+impl InputFormat {
+    /// 指定された形式、または拡張子から入力の形式を判定する
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - コマンドラインで指定された形式
+    /// * `path`   - ソースファイルパス
+    fn parse(format: Option<&str>, path: &str) -> Result<InputFormat, io::Error> {
+        let extension = Path::new(path).extension().and_then(|extension| extension.to_str());
+        match format.or(extension) {
+            Some("csv")            => Ok(InputFormat::Csv),
+            Some("jsonl" | "json") => Ok(InputFormat::JsonLines),
+            Some(name) if format.is_some() && name != "tsv" => Err(invalid_input(&format!("unknown input format '{name}'"))),
+            _ => Ok(InputFormat::Tsv),
+        }
+    }
+}
+
+/// buildコマンド
+fn build(args: &[String]) -> Result<(), io::Error> {
+    let args = Args::parse(args, &["format", "type", "layout", "alphabet", "normalize", "threads"], &["tail", "compress", "sort-values"])?;
+    let paths = args.positional(&["<INPUT>", "<OUTPUT>"])?;
+    let format = InputFormat::parse(args.option("format"), &paths[0])?;
+    let value_type = ValueType::from_name(args.option("type").unwrap_or("string"))?;
+    let layout = match args.option("layout") {
+        None | Some("separate") => Layout::Separate,
+        Some("interleaved")     => Layout::Interleaved,
+        Some("compact")         => Layout::Compact,
+        Some(layout)            => return Err(invalid_input(&format!("unknown layout '{layout}'"))),
+    };
+    let alphabet = match args.option("alphabet") {
+        None | Some("byte") => Alphabet::Byte,
+        Some("codepoint")   => Alphabet::CodePoint,
+        Some(alphabet)      => return Err(invalid_input(&format!("unknown alphabet '{alphabet}'"))),
+    };
+    let normalizer: Normalizer = args.option("normalize").unwrap_or("").parse()?;
+    let builder = DoubleArrayBuilder::new()
+        .layout(layout)
+        .alphabet(alphabet)
+        .normalizer(normalizer)
+        .threads(args.number_option("threads")?.unwrap_or(1))
+        .tail(args.flag("tail"))
+        .compress_data(args.flag("compress"))
+        .sort_values(args.flag("sort-values"));
+    let input: Box<dyn Read> = if paths[0] == "-" { Box::new(io::stdin()) } else { Box::new(File::open(&paths[0])?) };
+    with_value_type!(value_type, build_dictionary(input, format, &builder, &paths[1]))
+}
+
+/// JSON Linesのソースファイルの1行
+#[derive(Deserialize)]
+struct JsonEntry<T> {
+    key  : String,
+    value: T,
+}
+
+/// ソースファイルを読み込んで辞書を構築し、ファイルに保存する
+///
+/// # Arguments
+///
+/// * `input`       - ソースファイル
+/// * `format`      - ソースファイルの形式
+/// * `builder`     - 構築のオプション
+/// * `output_path` - 辞書ファイルパス
+fn build_dictionary<T: Value>(input: Box<dyn Read>, format: InputFormat, builder: &DoubleArrayBuilder, output_path: &str) -> Result<(), io::Error> {
+    let mut trie: Trie<T> = Trie::new();
+    let parse_value = |value: &str, line: usize| {
+        value.parse::<T>().map_err(|e| invalid_data(&format!("line {line}: invalid value '{value}': {e}")))
+    };
+    match format {
+        InputFormat::Tsv => {
+            for (i, line) in BufReader::new(input).lines().enumerate() {
+                let line = line?;
+                if line.is_empty() {
+                    continue;
+                }
+                let (key, value) = line.split_once('\t').ok_or_else(|| invalid_data(&format!("line {}: missing value column", i + 1)))?;
+                trie.set(key, parse_value(value, i + 1)?);
+            }
+        },
+        InputFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new().has_headers(false).flexible(true).from_reader(input);
+            for record in reader.records() {
+                let record = record.map_err(|e| invalid_data(&e.to_string()))?;
+                let line = record.position().map_or(0, |position| position.line() as usize);
+                if record.len() < 2 {
+                    return Err(invalid_data(&format!("line {line}: missing value column")));
+                }
+                trie.set(&record[0], parse_value(&record[1], line)?);
+            }
+        },
+        InputFormat::JsonLines => {
+            for (i, line) in BufReader::new(input).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let entry: JsonEntry<T> = serde_json::from_str(&line).map_err(|e| invalid_data(&format!("line {}: {}", i + 1, e)))?;
+                trie.set(&entry.key, entry.value);
+            }
+        },
+    }
+    builder.build(trie)?.dump(output_path)?;
+    Ok(())
+}
+
+/// getコマンド
+fn get(args: &[String]) -> Result<(), io::Error> {
+    let args = Args::parse(args, &[], &[])?;
+    let paths = args.positional(&["<DICTIONARY>", "<KEY>"])?;
+    with_value_type!(ValueType::of(&paths[0])?, write_get(&paths[0], &paths[1]))
+}
+
+fn write_get<T: Value>(dictionary_path: &str, key: &str) -> Result<(), io::Error> {
+    let double_array: DoubleArray<T> = DoubleArray::from_file(dictionary_path)?;
+    let values = double_array.get(key).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("key '{key}' not found")))?;
+    println!("{}", to_json(&values)?);
+    Ok(())
+}
+
+/// prefixコマンド
+fn prefix(args: &[String]) -> Result<(), io::Error> {
+    let args = Args::parse(args, &[], &[])?;
+    let paths = args.positional(&["<DICTIONARY>", "<KEY>"])?;
+    with_value_type!(ValueType::of(&paths[0])?, write_prefix(&paths[0], &paths[1]))
+}
+
+fn write_prefix<T: Value>(dictionary_path: &str, key: &str) -> Result<(), io::Error> {
+    let double_array: DoubleArray<T> = DoubleArray::from_file(dictionary_path)?;
+    let mut out = BufWriter::new(io::stdout().lock());
+    for (prefix, values) in double_array.prefix_search_iter(key) {
+        writeln!(out, "{}\t{}", prefix, to_json(&values)?)?;
+    }
+    out.flush()
+}
+
+/// predictコマンド
+fn predict(args: &[String]) -> Result<(), io::Error> {
+    let args = Args::parse(args, &["limit"], &[])?;
+    let limit = args.number_option("limit")?.unwrap_or(usize::MAX);
+    let paths = args.positional(&["<DICTIONARY>", "<PREFIX>"])?;
+    with_value_type!(ValueType::of(&paths[0])?, write_predict(&paths[0], &paths[1], limit))
+}
+
+fn write_predict<T: Value>(dictionary_path: &str, prefix: &str, limit: usize) -> Result<(), io::Error> {
+    let double_array: DoubleArray<T> = DoubleArray::from_file(dictionary_path)?;
+    let mut out = BufWriter::new(io::stdout().lock());
+    for (key, values) in double_array.predictive_search_iter(prefix).take(limit) {
+        writeln!(out, "{}\t{}", key, to_json(&values)?)?;
+    }
+    out.flush()
+}
+
+/// dumpコマンド
+fn dump(args: &[String]) -> Result<(), io::Error> {
+    let args = Args::parse(args, &["format"], &[])?;
+    let format = OutputFormat::parse(args.option("format"))?;
+    let paths = args.positional(&["<DICTIONARY>"])?;
+    with_value_type!(ValueType::of(&paths[0])?, write_dump(&paths[0], format))
+}
+
+fn write_dump<T: Value>(dictionary_path: &str, format: OutputFormat) -> Result<(), io::Error> {
+    let double_array: DoubleArray<T> = DoubleArray::from_file(dictionary_path)?;
+    let mut out = BufWriter::new(io::stdout().lock());
+    for (key, values) in double_array.iter() {
+        match format {
+            OutputFormat::Text => writeln!(out, "{}\t{}", key, to_json(&values)?)?,
+            OutputFormat::Json => writeln!(out, "{}", json!({ "key": key, "values": values }))?,
+        }
+    }
+    out.flush()
+}
+
+/// statsコマンド
+fn stats(args: &[String]) -> Result<(), io::Error> {
+    let args = Args::parse(args, &[], &[])?;
+    let paths = args.positional(&["<DICTIONARY>"])?;
+    let file_size = std::fs::metadata(&paths[0])?.len();
+    // 値をデシリアライズしないので値の型に依らない
+    let double_array = open_unchecked(&paths[0])?;
+    let stats = double_array.stats();
+    let mut out = BufWriter::new(io::stdout().lock());
+    writeln!(out, "file_size\t{file_size}")?;
+    writeln!(out, "layout\t{:?}", double_array.layout())?;
+    writeln!(out, "alphabet\t{:?}", double_array.alphabet())?;
+    writeln!(out, "node_count\t{}", stats.node_count)?;
+    writeln!(out, "key_count\t{}", stats.key_count)?;
+    writeln!(out, "value_count\t{}", stats.value_count)?;
+    writeln!(out, "array_len\t{}", stats.array_len)?;
+    writeln!(out, "fill_ratio\t{:.4}", stats.fill_ratio)?;
+    writeln!(out, "tail_size\t{}", stats.tail_size)?;
+    writeln!(out, "data_size\t{}", stats.data_size)?;
+    for (key, value) in double_array.metadata() {
+        writeln!(out, "metadata.{key}\t{value}")?;
+    }
+    out.flush()
+}
+
+/// verifyコマンド
+fn verify(args: &[String]) -> Result<(), io::Error> {
+    let args = Args::parse(args, &[], &[])?;
+    let paths = args.positional(&["<DICTIONARY>"])?;
+    // ヘッダは読み込み時に検証される
+    open_unchecked(&paths[0])?.verify()?;
+    with_value_type!(ValueType::of(&paths[0])?, verify_structure(&paths[0]))?;
+    println!("{}: OK", paths[0]);
+    Ok(())
+}
+
+fn verify_structure<T: Value>(dictionary_path: &str) -> Result<(), io::Error> {
+    let double_array: DoubleArray<T> = DoubleArray::from_file(dictionary_path)?;
+    double_array.verify_structure()
+}
+
+/// diffコマンド
+fn diff(args: &[String]) -> Result<(), io::Error> {
+    let args = Args::parse(args, &["format"], &[])?;
+    let format = OutputFormat::parse(args.option("format"))?;
+    let paths = args.positional(&["<OLD>", "<NEW>"])?;
+    let value_type = ValueType::of(&paths[0])?;
+    if ValueType::of(&paths[1])? != value_type {
+        return Err(invalid_input("value types of the dictionaries differ"));
+    }
+    with_value_type!(value_type, write_diff(&paths[0], &paths[1], format))
+}
+
+/// 2つの辞書の差分を標準出力に書き込む
+///
+/// # Arguments
+///
+/// * `old_path` - 比較元の辞書ファイルパス
+/// * `new_path` - 比較先の辞書ファイルパス
+/// * `format`   - 出力の形式
+fn write_diff<T: Value>(old_path: &str, new_path: &str, format: OutputFormat) -> Result<(), io::Error> {
+    let old: DoubleArray<T> = DoubleArray::from_file(old_path)?;
+    let new: DoubleArray<T> = DoubleArray::from_file(new_path)?;
+    let mut out = BufWriter::new(io::stdout().lock());
+    for entry in dary::diff(&old, &new) {
+        match format {
+            OutputFormat::Text => match &entry {
+                DiffEntry::Added(key, values)   => writeln!(out, "+\t{}\t{}", key, to_json(values)?)?,
+                DiffEntry::Removed(key, values) => writeln!(out, "-\t{}\t{}", key, to_json(values)?)?,
+                DiffEntry::Changed(key, old_values, new_values) => {
+                    writeln!(out, "~\t{}\t{}\t{}", key, to_json(old_values)?, to_json(new_values)?)?
+                },
+            },
+            OutputFormat::Json => {
+                let line = match &entry {
+                    DiffEntry::Added(key, values)   => json!({ "op": "added", "key": key, "values": values }),
+                    DiffEntry::Removed(key, values) => json!({ "op": "removed", "key": key, "values": values }),
+                    DiffEntry::Changed(key, old_values, new_values) => {
+                        json!({ "op": "changed", "key": key, "old": old_values, "new": new_values })
+                    },
+                };
+                writeln!(out, "{line}")?;
+            },
+        }
+    }
+    out.flush()
+}
+
+/// 値の型を検証せずに辞書を読み込む
+/// 値をデシリアライズしない処理 (メタデータ, 統計情報, チェックサムの検証) に使う
+///
+/// # Arguments
+///
+/// * `dictionary_path` - 辞書ファイルパス
+fn open_unchecked(dictionary_path: &str) -> Result<DoubleArray<String>, io::Error> {
+    DoubleArray::from_file_unchecked(dictionary_path)
+}
+
+/// 値をJSONの文字列に変換する
+fn to_json<T: Serialize>(values: &[T]) -> Result<String, io::Error> {
+    serde_json::to_string(values).map_err(|e| invalid_data(&e.to_string()))
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
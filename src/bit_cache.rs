@@ -35,6 +35,24 @@ impl BitCache {
         }
     }
 
+    /// 探索開始indexを指定されたindexまで進める
+    /// 指定されたindexが現在の探索開始indexより前の場合は何もしない
+    ///
+    /// # Arguments
+    ///
+    /// * `idx`- 新しい探索開始index
+    pub fn advance_start(&mut self, idx: usize) {
+        let start = idx >> Self::BIT_CNT; // idx / Self::BIT_LEN
+        if start > self.start {
+            self.start = start;
+        }
+    }
+
+    /// 探索開始index
+    pub fn start_idx(&self) -> usize {
+        self.start * Self::BIT_LEN
+    }
+
     /// 指定されたインデックスを取得する
     /// 空なら0, 空でないなら0以外
     ///
@@ -51,6 +69,23 @@ impl BitCache {
         }
     }
 
+    /// 指定されたインデックスから64個分のビットを取得する
+    /// 戻り値の最下位ビットが idx に対応する
+    ///
+    /// # Arguments
+    ///
+    /// * `idx`- 取得を開始するindex
+    pub fn get_bits(&self, idx: usize) -> u64 {
+        let arr_idx: usize = idx >> Self::BIT_CNT; // idx / Self::BIT_LEN
+        let bit_idx: usize = idx & Self::BIT_MASK; // idx % Self::BIT_LEN
+        let word = |i: usize| self.cache.get(i).map_or(0, |&bits| bits as u64);
+        if bit_idx == 0 {
+            word(arr_idx)
+        } else {
+            (word(arr_idx) >> bit_idx) | (word(arr_idx + 1) << (Self::BIT_LEN - bit_idx))
+        }
+    }
+
     /// 指定されたインデックスのビットを立てる
     ///
     /// # Arguments
@@ -78,8 +113,7 @@ impl BitCache {
         }
         // offset よりも前のビットを0埋めするためのマスク
         let mut mask: i64 = -1 << bit_idx;
-        let mut cnt = arr_idx;
-        for &e in &self.cache[arr_idx..] {
+        for (cnt, &e) in self.cache.iter().enumerate().skip(arr_idx) {
             // bit反転しているので、0が要素あり、1が空
             let bits = (e ^ -1) & mask;
             if bits != 0 {
@@ -88,7 +122,6 @@ impl BitCache {
                 return cnt * Self::BIT_LEN + zeros;
             }
             mask = -1;
-            cnt += 1;
         }
         self.cache.len() * Self::BIT_LEN
     }
@@ -107,7 +140,14 @@ impl BitCache {
     }
 }
 
+impl Default for BitCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
 
@@ -154,6 +194,32 @@ mod tests {
         assert_eq!(10000960, bit_cache.find_empty_idx(10000000));
     }
 
+    #[test]
+    fn test_advance_start() {
+        let mut bit_cache = BitCache::new();
+        assert_eq!(256, bit_cache.start_idx());
+        bit_cache.advance_start(1000);
+        assert_eq!(960, bit_cache.start_idx());
+        // 現在より前には戻らない
+        bit_cache.advance_start(300);
+        assert_eq!(960, bit_cache.start_idx());
+        assert_eq!(1000, bit_cache.find_empty_idx(40));
+    }
+
+    #[test]
+    fn test_get_bits() {
+        let mut bit_cache = BitCache::new();
+        bit_cache.set(3);
+        bit_cache.set(64);
+        bit_cache.set(130);
+        assert_eq!(0b1000, bit_cache.get_bits(0));
+        assert_eq!(0b1 | (1 << 61), bit_cache.get_bits(3));
+        assert_eq!(0b100, bit_cache.get_bits(62));
+        assert_eq!(0b1 << 4, bit_cache.get_bits(126));
+        // 範囲外は0
+        assert_eq!(0, bit_cache.get_bits(100000000));
+    }
+
     #[test]
     fn test_last_index_of_one() {
         let mut bit_cache = BitCache::new();
@@ -0,0 +1,1302 @@
+use std::any;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::alphabet::{Alphabet, CodeTable};
+use crate::bit_cache::BitCache;
+use crate::double_array::{DoubleArray, TAIL_FLAG, TERMINAL_KEY};
+use crate::data::{compress_blocks, DEFAULT_BLOCK_SIZE};
+use crate::format::{METADATA_KEY_COUNT, METADATA_NORMALIZER, METADATA_SCHEMA_VERSION, METADATA_VALUE_TYPE, METADATA_VERSION};
+use crate::layout::Layout;
+use crate::merge::{merge_entries, MergePolicy};
+use crate::normalizer::Normalizer;
+use crate::stats::BuildStats;
+use crate::trie::{Trie, Node};
+use crate::utils::*;
+
+use bincode;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// Compactレイアウトでbase値を探索する範囲
+/// base値の一意性の制約で埋まらない空きが残るため、配置済みの末尾からこの範囲より前は探索しない
+const COMPACT_SEARCH_WINDOW: usize = 1 << 12;
+
+/// keyが取りうる値のパターン
+const MAX_KEY: usize = u8::MAX as usize + 1;
+
+/// 進捗を通知するノード数の間隔
+const PROGRESS_INTERVAL: usize = 1 << 16;
+
+/// 並列構築で部分木に分割する数の目安
+/// スレッド数に依らず同じ出力になるよう、分割の仕方はスレッド数に依存させない
+const PARALLEL_SUBTREES: usize = 64;
+
+/// 並列構築で部分木に分割する最大の深さ
+/// UTF-8の日本語1文字(3バイト)までで分割する
+const MAX_SPLIT_DEPTH: usize = 3;
+
+/// ダブル配列の構築に使うノード
+/// 値はシリアライズ済みのバイト列として持つので、値の型に依らずスレッド間で受け渡せる
+pub(crate) struct BuildNode {
+    pub(crate) key  : u8,
+    pub(crate) data : Option<Vec<u8>>,
+    pub(crate) nexts: Vec<BuildNode>,
+}
+
+impl BuildNode {
+    /// トライ木のノードを変換する
+    ///
+    /// # Arguments
+    ///
+    /// * `node`        - トライ木のノード
+    /// * `sort_values` - 値をシリアライズしたバイト列の順に並べ替えるかどうか
+    pub(crate) fn from_node<T: Serialize>(node: Node<T>, sort_values: bool) -> Result<BuildNode, std::io::Error> {
+        let data = if node.values.is_empty() {
+            None
+        } else {
+            Some(BuildNode::encode_values(&node.values, sort_values)?)
+        };
+        Ok(BuildNode {
+            key: node.key,
+            data,
+            nexts: node.nexts.into_iter().map(|n| BuildNode::from_node(n, sort_values)).collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// トライ木のノードを消費せずに変換する
+    ///
+    /// # Arguments
+    ///
+    /// * `node`        - トライ木のノード
+    /// * `sort_values` - 値をシリアライズしたバイト列の順に並べ替えるかどうか
+    pub(crate) fn from_node_ref<T: Serialize>(node: &Node<T>, sort_values: bool) -> Result<BuildNode, std::io::Error> {
+        let data = if node.values.is_empty() {
+            None
+        } else {
+            Some(BuildNode::encode_values(&node.values, sort_values)?)
+        };
+        Ok(BuildNode {
+            key: node.key,
+            data,
+            nexts: node.nexts.iter().map(|n| BuildNode::from_node_ref(n, sort_values)).collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// 値をdata配列に格納するバイト列に変換する
+    /// 値をシリアライズできなかった場合はエラーを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `values`      - 1つのキーに対応する値
+    /// * `sort_values` - 値をシリアライズしたバイト列の順に並べ替えるかどうか
+    pub(crate) fn encode_values<T: Serialize>(values: &[T], sort_values: bool) -> Result<Vec<u8>, std::io::Error> {
+        let serialize_error = |e: bincode::Error| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("failed to serialize values: {e}"));
+        if sort_values {
+            // Vec<T> のbincode表現は 要素数(u64) と各要素の表現を連結したもの
+            let mut values: Vec<Vec<u8>> = values.iter().map(bincode::serialize).collect::<Result<_, _>>().map_err(serialize_error)?;
+            values.sort();
+            let mut data = (values.len() as u64).to_le_bytes().to_vec();
+            values.iter().for_each(|v| data.extend_from_slice(v));
+            Ok(data)
+        } else {
+            bincode::serialize(values).map_err(serialize_error)
+        }
+    }
+
+    /// キーに値を追加する
+    /// キーは追加済みのキーより辞書順で後ろでなければならない。
+    /// そのため末尾の子ノードだけを見ればよく、子ノードはkeyの昇順に並ぶ。
+    ///
+    /// # Arguments
+    ///
+    /// * `key`  - 追加するキー
+    /// * `data` - 値をシリアライズしたバイト列
+    pub(crate) fn push_sorted(&mut self, key: &[u8], data: Vec<u8>) {
+        let mut node = self;
+        for &k in key {
+            if node.nexts.last().is_none_or(|n| n.key != k) {
+                node.nexts.push(BuildNode { key: k, data: None, nexts: Vec::new() });
+            }
+            node = node.nexts.last_mut().unwrap();
+        }
+        node.data = Some(data);
+    }
+
+    /// 自身以降のノードのキー(自身からの経路)と値を、キーの昇順に取り出す
+    pub(crate) fn into_entries(self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+        let mut stack: Vec<(Vec<u8>, BuildNode)> = vec![(Vec::new(), self)];
+        while let Some((key, node)) = stack.pop() {
+            for next in node.nexts.into_iter().rev() {
+                let mut next_key = key.clone();
+                next_key.push(next.key);
+                stack.push((next_key, next));
+            }
+            if let Some(data) = node.data {
+                entries.push((key, data));
+            }
+        }
+        entries
+    }
+
+    /// キーを符号表で変換して、ルートのノードを作る
+    /// 符号表はキーの文字の出現回数から作る
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - キーと、値をシリアライズしたバイト列
+    pub(crate) fn from_code_points(entries: Vec<(String, Vec<u8>)>) -> (BuildNode, CodeTable) {
+        let code_table = CodeTable::from_keys(entries.iter().map(|(key, _)| key.as_str()));
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = entries.into_iter()
+            .map(|(key, data)| (code_table.encode(&key).unwrap(), data))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut root = BuildNode { key: 0, data: None, nexts: Vec::new() };
+        for (key, data) in entries {
+            root.push_sorted(&key, data);
+        }
+        (root, code_table)
+    }
+
+    /// 自身以降のノードのうち、値を持つノードの数
+    pub(crate) fn key_count(&self) -> usize {
+        let mut count = 0;
+        let mut stack: Vec<&BuildNode> = vec![self];
+        while let Some(node) = stack.pop() {
+            if node.data.is_some() {
+                count += 1;
+            }
+            stack.extend(node.nexts.iter());
+        }
+        count
+    }
+}
+
+/// 並列に構築した部分木の、連結先での根のindexと構築結果
+type SubRegion = (usize, Result<Region, std::io::Error>);
+
+/// tail配列の位置をbase値に格納できる値に変換する
+/// base値の最上位ビットはTAIL_FLAGなので、位置はTAIL_FLAG未満である必要がある
+///
+/// # Arguments
+///
+/// * `pos` - tail配列の位置
+fn tail_offset(pos: usize) -> Result<u32, std::io::Error> {
+    if pos < TAIL_FLAG as usize {
+        Ok(pos as u32)
+    } else {
+        Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("tail array is too large: {pos} bytes")))
+    }
+}
+
+/// base配列のindexをbase値に格納できる値に変換する
+/// TAIL_FLAGと重なるとtail配列の位置と区別できないので、indexはTAIL_FLAG未満である必要がある
+///
+/// # Arguments
+///
+/// * `pos` - base配列のindex
+fn base_offset(pos: usize) -> Result<u32, std::io::Error> {
+    if pos < TAIL_FLAG as usize {
+        Ok(pos as u32)
+    } else {
+        Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("base array is too large: {pos} elements")))
+    }
+}
+
+/// data配列の位置をbase値やtail配列に格納できるu32に変換する
+///
+/// # Arguments
+///
+/// * `pos` - data配列の位置
+fn data_offset(pos: usize) -> Result<u32, std::io::Error> {
+    u32::try_from(pos)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("data array is too large: {pos} bytes")))
+}
+
+/// 構築済みのダブル配列の領域
+/// base配列, check配列の長さは配置済みの最大index + 1
+struct Region {
+    base_arr : Vec<u32>,
+    check_arr: Vec<u32>,
+    tail_arr : Vec<u8>,
+    data_arr : Vec<u8>,
+}
+
+/// 構築の進捗
+/// 複数スレッドで構築する場合はすべてのスレッドの合計
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildProgress {
+    /// 配置済みのノード数
+    pub nodes_placed: usize,
+    /// tail配列, data配列に書き込んだバイト数
+    pub bytes_written: usize,
+}
+
+/// 進捗を通知するコールバック
+type ProgressCallback = dyn Fn(&BuildProgress) + Send + Sync;
+
+/// 構築中にスレッド間で共有する進捗と計測値
+struct BuildState<'a> {
+    progress       : Option<&'a ProgressCallback>,
+    nodes_placed   : AtomicUsize,
+    bytes_written  : AtomicUsize,
+    find_base_nanos: AtomicU64,
+}
+
+impl<'a> BuildState<'a> {
+    fn new(progress: Option<&'a ProgressCallback>) -> Self {
+        BuildState {
+            progress,
+            nodes_placed   : AtomicUsize::new(0),
+            bytes_written  : AtomicUsize::new(0),
+            find_base_nanos: AtomicU64::new(0),
+        }
+    }
+
+    /// 進捗を加算してコールバックに通知する
+    ///
+    /// # Arguments
+    ///
+    /// * `nodes` - 前回の通知以降に配置したノード数
+    /// * `bytes` - 前回の通知以降に書き込んだバイト数
+    fn report(&self, nodes: usize, bytes: usize) {
+        let nodes_placed  = self.nodes_placed.fetch_add(nodes, Ordering::Relaxed) + nodes;
+        let bytes_written = self.bytes_written.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        if let Some(progress) = self.progress {
+            progress(&BuildProgress { nodes_placed, bytes_written });
+        }
+    }
+
+    /// base値の探索時間を加算する
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - 探索時間
+    fn add_find_base_time(&self, time: Duration) {
+        self.find_base_nanos.fetch_add(time.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// トライ木からダブル配列を構築するビルダー。
+/// 構築時のオプションを指定する場合に利用する。
+///
+/// # Examples
+///
+/// ```
+/// use dary::{Trie, DoubleArrayBuilder};
+///
+/// let mut trie: Trie<u32> = Trie::new();
+/// trie.set("abcdef", 1);
+/// trie.set("abxyz", 2);
+///
+/// let double_array = DoubleArrayBuilder::new()
+///     .tail(true)
+///     .threads(2)
+///     .build(trie)
+///     .unwrap();
+/// assert_eq!(vec![1], double_array.get("abcdef").unwrap());
+/// assert_eq!(vec![2], double_array.get("abxyz").unwrap());
+/// assert_eq!(None, double_array.get("abx"));
+/// ```
+#[derive(Clone)]
+pub struct DoubleArrayBuilder {
+    tail: bool,
+    layout: Layout,
+    alphabet: Alphabet,
+    normalizer: Normalizer,
+    threads: usize,
+    sort_values: bool,
+    metadata: BTreeMap<String, String>,
+    schema_version: Option<u32>,
+    compress_data: bool,
+    data_block_size: usize,
+    progress: Option<Arc<ProgressCallback>>,
+}
+
+impl Debug for DoubleArrayBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DoubleArrayBuilder")
+            .field("tail", &self.tail)
+            .field("layout", &self.layout)
+            .field("alphabet", &self.alphabet)
+            .field("normalizer", &self.normalizer)
+            .field("threads", &self.threads)
+            .field("sort_values", &self.sort_values)
+            .field("metadata", &self.metadata)
+            .field("schema_version", &self.schema_version)
+            .field("compress_data", &self.compress_data)
+            .field("data_block_size", &self.data_block_size)
+            .field("progress", &self.progress.is_some())
+            .finish()
+    }
+}
+
+impl DoubleArrayBuilder {
+    pub fn new() -> Self {
+        DoubleArrayBuilder {
+            tail: false,
+            layout: Layout::Separate,
+            alphabet: Alphabet::Byte,
+            normalizer: Normalizer::new(),
+            threads: 1,
+            sort_values: false,
+            metadata: BTreeMap::new(),
+            schema_version: None,
+            compress_data: false,
+            data_block_size: DEFAULT_BLOCK_SIZE,
+            progress: None,
+        }
+    }
+
+    /// TAIL圧縮を有効にする
+    /// 経路が一意に定まったノード以降のキーをtail配列に格納し、base/check配列を節約する
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - TAIL圧縮を行うかどうか
+    pub fn tail(mut self, enabled: bool) -> Self {
+        self.tail = enabled;
+        self
+    }
+
+    /// base配列, check配列のレイアウトを指定する
+    ///
+    /// # Arguments
+    ///
+    /// * `layout` - レイアウト
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// 遷移のラベルの単位を指定する (デフォルトは `Alphabet::Byte`)
+    /// `Alphabet::CodePoint` の場合はキーの文字の出現回数から符号表を作って辞書ファイルに格納し、
+    /// 頻度の高い文字ほど少ない遷移で表す。検索のAPIは変わらない。
+    ///
+    /// # Arguments
+    ///
+    /// * `alphabet` - ラベルの単位
+    pub fn alphabet(mut self, alphabet: Alphabet) -> Self {
+        self.alphabet = alphabet;
+        self
+    }
+
+    /// キーの正規化を指定する (デフォルトは正規化しない)
+    /// 登録するキーを正規化して構築し、正規化の種類を辞書ファイルに記録する。
+    /// 検索時は記録された正規化をクエリに適用するので、クエリを正規化する必要はない。
+    ///
+    /// # Arguments
+    ///
+    /// * `normalizer` - キーの正規化
+    pub fn normalizer(mut self, normalizer: Normalizer) -> Self {
+        self.normalizer = normalizer;
+        self
+    }
+
+    /// 構築に使うスレッド数を指定する (デフォルトは1)
+    /// 2以上の場合はルートの子ノードごとの部分木を並列に構築し、順に連結する。
+    /// 出力はスレッド数に依らず同じになるが、1スレッドの場合とはノードの配置が異なる。
+    /// ファイル形式と検索結果は変わらない。
+    ///
+    /// # Arguments
+    ///
+    /// * `threads` - スレッド数。0の場合は1として扱う
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// data配列を固定長のブロックに分割してLZ4で圧縮する
+    /// 値のサイズが大きい辞書でファイルサイズを削減できる。
+    /// 検索時は値を含むブロックを展開するため、展開したブロックを `DoubleArray` 内のLRUキャッシュに保持する。
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - data配列を圧縮するかどうか
+    pub fn compress_data(mut self, enabled: bool) -> Self {
+        self.compress_data = enabled;
+        self
+    }
+
+    /// 圧縮したdata配列の、展開後のブロックのバイト数を指定する (デフォルトは64KiB)
+    /// 小さくすると1回の検索で展開するバイト数が減るが、圧縮率が下がる。
+    ///
+    /// # Arguments
+    ///
+    /// * `block_size` - ブロックのバイト数。0の場合は1として扱う
+    pub fn data_block_size(mut self, block_size: usize) -> Self {
+        self.data_block_size = block_size.max(1);
+        self
+    }
+
+    /// 同じキーに登録された値を、値をシリアライズしたバイト列の順に並べ替える
+    /// デフォルトでは登録した順に格納する。
+    ///
+    /// 構築結果はキーの登録順序に依らず同じバイト列になる。
+    /// これを有効にすると、同じキーに対する値の登録順序にも依らなくなる。
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - 値を並べ替えるかどうか
+    pub fn sort_values(mut self, enabled: bool) -> Self {
+        self.sort_values = enabled;
+        self
+    }
+
+    /// 辞書ファイルに格納するメタデータを追加する
+    /// `Trie::set_metadata` で設定したエントリと同じkeyの場合はこちらを優先する。
+    /// `dary.` で始まるkeyは組み込みのエントリに上書きされる。
+    ///
+    /// # Arguments
+    ///
+    /// * `key`   - メタデータのキー
+    /// * `value` - メタデータの値
+    pub fn metadata(mut self, key: &str, value: &str) -> Self {
+        self.metadata.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// 値のスキーマのバージョンを辞書に記録する
+    /// 値の型名が同じでも表現が変わった場合にバージョンを上げると、
+    /// `DoubleArray::from_file_with_schema` で古い辞書の読み込みを拒否できる。
+    ///
+    /// # Arguments
+    ///
+    /// * `version` - スキーマのバージョン
+    pub fn schema_version(mut self, version: u32) -> Self {
+        self.schema_version = Some(version);
+        self
+    }
+
+    /// 構築の進捗を通知するコールバックを指定する
+    /// 一定数のノードを配置するごとに、配置済みのノード数と書き込んだバイト数が通知される。
+    /// 複数スレッドで構築する場合は各スレッドから呼び出される。
+    ///
+    /// # Arguments
+    ///
+    /// * `callback` - 進捗を受け取るコールバック
+    pub fn progress<F: Fn(&BuildProgress) + Send + Sync + 'static>(mut self, callback: F) -> Self {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// トライ木をダブル配列に変換する
+    ///
+    /// # Arguments
+    ///
+    /// * `trie` - 変換するトライ木
+    pub fn build<T: Serialize + DeserializeOwned + Debug>(&self, trie: Trie<T>) -> Result<DoubleArray<T>, std::io::Error> {
+        self.build_with_stats(trie).map(|(double_array, _)| double_array)
+    }
+
+    /// トライ木をダブル配列に変換し、構築の統計情報と合わせて返す
+    /// キーの正規化を指定しない場合は、トライ木のメタデータに記録された正規化を引き継ぐ (`Trie::from_double_array` で読み込んだ場合など)
+    ///
+    /// # Arguments
+    ///
+    /// * `trie` - 変換するトライ木
+    pub fn build_with_stats<T: Serialize + DeserializeOwned + Debug>(&self, trie: Trie<T>) -> Result<(DoubleArray<T>, BuildStats), std::io::Error> {
+        if self.normalizer.is_identity() {
+            if let Some(normalizer) = trie.metadata.get(METADATA_NORMALIZER) {
+                let normalizer: Normalizer = normalizer.parse()?;
+                if !normalizer.is_identity() {
+                    return self.clone().normalizer(normalizer).build_with_stats(trie);
+                }
+            }
+        }
+        let trie = if self.normalizer.is_identity() { trie } else { trie.normalize(&self.normalizer) };
+        let capacity = trie.len;
+        let root = BuildNode::from_node(trie.root, self.sort_values)?;
+        self.build_nodes(root, capacity, trie.metadata)
+    }
+
+    /// トライ木を消費せずにダブル配列に変換する
+    /// 値はシリアライズしながら読むだけなので、トライ木を複製しない
+    ///
+    /// # Arguments
+    ///
+    /// * `trie` - 変換するトライ木
+    pub(crate) fn build_ref<T: Serialize + DeserializeOwned + Debug>(&self, trie: &Trie<T>) -> Result<DoubleArray<T>, std::io::Error> {
+        if self.normalizer.is_identity() {
+            if let Some(normalizer) = trie.metadata.get(METADATA_NORMALIZER) {
+                let normalizer: Normalizer = normalizer.parse()?;
+                if !normalizer.is_identity() {
+                    return self.clone().normalizer(normalizer).build_ref(trie);
+                }
+            }
+        }
+        let root = if self.normalizer.is_identity() {
+            BuildNode::from_node_ref(&trie.root, self.sort_values)?
+        } else {
+            // 正規化で同じキーになった値は、元のキーの辞書順にまとめる
+            let mut entries: BTreeMap<String, Vec<&T>> = BTreeMap::new();
+            for (key, values) in trie.iter() {
+                entries.entry(self.normalizer.normalize(&key)).or_default().extend(values);
+            }
+            let mut root = BuildNode { key: 0, data: None, nexts: Vec::new() };
+            for (key, values) in entries {
+                root.push_sorted(key.as_bytes(), BuildNode::encode_values(&values, self.sort_values)?);
+            }
+            root
+        };
+        self.build_nodes(root, trie.len, trie.metadata.clone()).map(|(double_array, _)| double_array)
+    }
+
+    /// トライ木から変換したノードからダブル配列を構築する
+    ///
+    /// # Arguments
+    ///
+    /// * `root`     - 根となるノード
+    /// * `capacity` - 値の数の目安
+    /// * `metadata` - トライ木のメタデータ
+    fn build_nodes<T: Serialize + DeserializeOwned + Debug>(&self, root: BuildNode, capacity: usize, metadata: BTreeMap<String, String>) -> Result<(DoubleArray<T>, BuildStats), std::io::Error> {
+        if self.alphabet == Alphabet::CodePoint {
+            // Trie のキーは &str なので UTF-8 として正しい
+            let entries = root.into_entries().into_iter()
+                .map(|(key, data)| (String::from_utf8(key).unwrap(), data))
+                .collect();
+            let (root, code_table) = BuildNode::from_code_points(entries);
+            return self.build_root(root, capacity, Some(&code_table), metadata);
+        }
+        self.build_root(root, capacity, None, metadata)
+    }
+
+    /// 複数の辞書を1つのダブル配列にまとめる
+    /// 各辞書のキーを辞書順に読み出しながら併合し、トライ木を経由せずに構築する。
+    /// 同じキーに複数の辞書の値がある場合は `policy` に従う。
+    ///
+    /// メタデータは後ろの辞書のエントリを優先してまとめ、ビルダーで指定したエントリでさらに上書きする。
+    /// キーの正規化を指定しない場合は、すべての辞書の正規化が同じであればその正規化を引き継ぎ、
+    /// 辞書によって正規化が異なる場合はエラーを返す。
+    /// スキーマのバージョンも同様に、指定しない場合はすべての辞書で同じであれば引き継ぎ、異なる場合はエラーを返す。
+    ///
+    /// # Panics
+    /// 入力の辞書に格納された値をデシリアライズできなかった場合にpanicする。
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - まとめる辞書
+    /// * `policy` - 同じキーの値の扱い
+    pub fn merge<T: Serialize + DeserializeOwned + Debug>(&self, inputs: &[&DoubleArray<T>], policy: MergePolicy) -> Result<DoubleArray<T>, std::io::Error> {
+        if self.schema_version.is_none() {
+            let versions = inputs.iter().map(|input| input.schema_version()).collect::<Result<Vec<_>, _>>()?;
+            let inherited = versions.first().copied().flatten();
+            if versions.iter().any(|&version| version != inherited) {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "dictionaries with different schema versions cannot be merged without specifying a schema version"));
+            }
+            if let Some(inherited) = inherited {
+                return self.clone().schema_version(inherited).merge(inputs, policy);
+            }
+        }
+        if self.normalizer.is_identity() {
+            let inherited = inputs.first().and_then(|first| first.normalizer());
+            if inputs.iter().any(|input| input.normalizer() != inherited) {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "dictionaries with different normalizers cannot be merged without specifying a normalizer"));
+            }
+            if let Some(inherited) = inherited {
+                return self.clone().normalizer(inherited).merge(inputs, policy);
+            }
+        }
+        if !self.normalizer.is_identity() {
+            // 正規化するとキーの順序が変わり、異なるキーが同じキーになるので、トライ木を経由する
+            let mut trie: Trie<T> = Trie::new();
+            for (key, values) in merge_entries(inputs, policy) {
+                for value in values {
+                    trie.set(&key, value);
+                }
+            }
+            for input in inputs {
+                trie.metadata.extend(input.read_metadata()?);
+            }
+            return self.build(trie);
+        }
+        let mut root = BuildNode { key: 0, data: None, nexts: Vec::new() };
+        let mut code_point_entries: Vec<(String, Vec<u8>)> = Vec::new();
+        let mut capacity = 0;
+        for (key, values) in merge_entries(inputs, policy) {
+            capacity += values.len();
+            let data = BuildNode::encode_values(&values, self.sort_values)?;
+            if self.alphabet == Alphabet::CodePoint {
+                // 符号表はすべてのキーが揃ってから作る
+                code_point_entries.push((key, data));
+            } else {
+                root.push_sorted(key.as_bytes(), data);
+            }
+        }
+        let mut metadata = BTreeMap::new();
+        for input in inputs {
+            metadata.extend(input.read_metadata()?);
+        }
+        if self.alphabet == Alphabet::CodePoint {
+            let (root, code_table) = BuildNode::from_code_points(code_point_entries);
+            return self.build_root(root, capacity, Some(&code_table), metadata).map(|(double_array, _)| double_array);
+        }
+        self.build_root(root, capacity, None, metadata).map(|(double_array, _)| double_array)
+    }
+
+    /// 変換済みのノードからダブル配列を構築する
+    ///
+    /// # Arguments
+    ///
+    /// * `root`       - 根となるノード
+    /// * `capacity`   - 値の数の目安
+    /// * `code_table` - キーの変換に使った符号表。`Alphabet::Byte` の場合はNone
+    /// * `metadata`   - 辞書に格納するメタデータ。ビルダーで指定したエントリと組み込みのエントリで上書きする
+    fn build_root<T: Serialize + DeserializeOwned + Debug>(&self, root: BuildNode, capacity: usize, code_table: Option<&CodeTable>, mut metadata: BTreeMap<String, String>) -> Result<(DoubleArray<T>, BuildStats), std::io::Error> {
+        metadata.extend(self.metadata.clone());
+        metadata.insert(METADATA_VERSION.to_string(), env!("CARGO_PKG_VERSION").to_string());
+        metadata.insert(METADATA_KEY_COUNT.to_string(), root.key_count().to_string());
+        metadata.insert(METADATA_VALUE_TYPE.to_string(), any::type_name::<T>().to_string());
+        metadata.remove(METADATA_SCHEMA_VERSION);
+        if let Some(version) = self.schema_version {
+            metadata.insert(METADATA_SCHEMA_VERSION.to_string(), version.to_string());
+        }
+        metadata.remove(METADATA_NORMALIZER);
+        if !self.normalizer.is_identity() {
+            metadata.insert(METADATA_NORMALIZER.to_string(), self.normalizer.to_string());
+        }
+        let state = BuildState::new(self.progress.as_deref());
+        let region = if self.threads > 1 {
+            self.build_parallel(root, capacity, &state)?
+        } else {
+            self.build_region(root, capacity, &state, None)?.0
+        };
+
+        // 配列のりサイズ
+        // 末尾のノードから最大のkeyで遷移しても範囲外にならないようにする
+        let Region { mut base_arr, mut check_arr, tail_arr, data_arr } = region;
+        let new_len = base_arr.len() + MAX_KEY - 1;
+        base_arr.resize(new_len, 0);
+        check_arr.resize(new_len, 0);
+        let (data_arr, data_index) = if self.compress_data {
+            compress_blocks(&data_arr, self.data_block_size)
+        } else {
+            (data_arr, Vec::new())
+        };
+        let double_array = DoubleArray::from_sections(self.layout, &base_arr, &check_arr, &tail_arr, &data_arr, &data_index, code_table, &metadata)?;
+        let mut stats = double_array.stats();
+        stats.find_base_time = Some(Duration::from_nanos(state.find_base_nanos.load(Ordering::Relaxed)));
+        Ok((double_array, stats))
+    }
+
+    /// rootをindex=1に配置して、rootを根とする木を1つの領域に構築する
+    /// split_depthを指定した場合は、その深さのノードの子ノードは配置せず、ノードのindexと部分木を返す
+    ///
+    /// # Arguments
+    ///
+    /// * `root`        - 根となるノード
+    /// * `capacity`    - 木に含まれる値の数の目安
+    /// * `state`       - 進捗と計測値
+    /// * `split_depth` - 部分木に分割する深さ。Noneの場合は分割しない
+    fn build_region(&self, root: BuildNode, capacity: usize, state: &BuildState, split_depth: Option<usize>) -> Result<(Region, Vec<(usize, BuildNode)>), std::io::Error> {
+        let mut len = MAX_KEY.max(4 * capacity);
+        let mut base_arr: Vec<u32>  = vec![0; len];
+        let mut check_arr: Vec<u32> = vec![0; len];
+        let mut tail_arr: Vec<u8>   = Vec::new();
+        let mut data_arr: Vec<u8>   = Vec::with_capacity(capacity);
+        let mut bit_cache: BitCache = BitCache::new();
+        bit_cache.set(0);
+        bit_cache.set(1);
+        // 使用済みのbase値。Compactレイアウトではbase値が一意である必要がある
+        let mut used_bases: Option<BitCache> = if self.layout == Layout::Compact { Some(BitCache::new()) } else { None };
+        // 配置済みのノードの最大index
+        let mut last_idx: usize = 1;
+        // 前回の通知以降に配置したノード数, 書き込んだバイト数
+        let mut nodes_placed: usize = 0;
+        let mut bytes_written: usize = 0;
+        let mut find_base_time = Duration::default();
+        let mut subtrees: Vec<(usize, BuildNode)> = Vec::new();
+        let mut stack: Vec<(usize, usize, BuildNode)> = Vec::with_capacity(capacity);
+        if !root.nexts.is_empty() || root.data.is_some() {
+            stack.push((1, 0, root));
+        }
+
+        while let Some((curr_idx, depth, mut node)) = stack.pop() {
+            // 以降の経路が一意に定まる場合はtail配列に格納する
+            if self.tail && Self::is_tail_node(&node) {
+                base_arr[curr_idx] = TAIL_FLAG | tail_offset(tail_arr.len())?;
+                let len = tail_arr.len() + data_arr.len();
+                Self::push_tail(node, &mut tail_arr, &mut data_arr)?;
+                bytes_written += tail_arr.len() + data_arr.len() - len;
+                continue;
+            }
+            // 分割する深さのノードは部分木として別の領域に構築する
+            if split_depth == Some(depth) {
+                subtrees.push((curr_idx, node));
+                continue;
+            }
+
+            bit_cache.update_start();
+            if used_bases.is_some() {
+                bit_cache.advance_start(last_idx.saturating_sub(COMPACT_SEARCH_WINDOW));
+            }
+
+            // base値を探索・セット
+            if node.data.is_some() {
+                // valuesが存在する場合はkey=255のノードとして計算する
+                node.nexts.push(BuildNode { key: TERMINAL_KEY, data: None, nexts: vec![] });
+            }
+
+            let start = Instant::now();
+            let base: usize = Self::find_base(&node.nexts, &bit_cache, used_bases.as_ref());
+            find_base_time += start.elapsed();
+            base_arr[curr_idx] = base as u32;
+            if let Some(used_bases) = used_bases.as_mut() {
+                used_bases.set(base);
+            }
+
+            // 配列の長さが足りなければ配列を拡張
+            if base + MAX_KEY >= len {
+                len *= 2;
+                base_arr.resize(len, 0);
+                check_arr.resize(len, 0);
+            }
+
+            // 新しいノードをダブル配列に登録
+            for n in node.nexts {
+                let i = base + (n.key as usize);
+                bit_cache.set(i);
+                last_idx = last_idx.max(i);
+                check_arr[i] = curr_idx as u32;
+                if n.key == TERMINAL_KEY {
+                    // valueノードの登録
+                    // base には data の開始 index を格納する
+                    base_arr[i]  = data_offset(data_arr.len())?;
+                    // data には末尾に values を追加する
+                    let data = node.data.as_ref().unwrap();
+                    data_arr.extend_from_slice(data);
+                    bytes_written += data.len();
+                } else {
+                    // 通常ノードの登録
+                    stack.push((i, depth + 1, n));
+                }
+                nodes_placed += 1;
+                if nodes_placed >= PROGRESS_INTERVAL {
+                    state.report(nodes_placed, bytes_written);
+                    nodes_placed = 0;
+                    bytes_written = 0;
+                }
+            }
+        }
+        state.report(nodes_placed, bytes_written);
+        state.add_find_base_time(find_base_time);
+
+        base_arr.truncate(last_idx + 1);
+        check_arr.truncate(last_idx + 1);
+        Ok((Region { base_arr, check_arr, tail_arr, data_arr }, subtrees))
+    }
+
+    /// 浅い部分の木を1つの領域に構築した後、分割した部分木を並列に領域として構築し、
+    /// 浅い部分の領域の後ろに連結する
+    ///
+    /// # Arguments
+    ///
+    /// * `root`     - トライ木のルート
+    /// * `capacity` - トライ木に含まれる値の数
+    /// * `state`    - 進捗と計測値
+    fn build_parallel(&self, root: BuildNode, capacity: usize, state: &BuildState) -> Result<Region, std::io::Error> {
+        let split_depth = Self::split_depth(&root);
+        let (mut region, jobs) = self.build_region(root, capacity, state, Some(split_depth))?;
+
+        // 部分木を並列に構築
+        // 構築の順序に依らず結果が同じになるよう、結果はjobの順に格納する
+        let jobs_len = jobs.len();
+        let sub_capacity = capacity / jobs_len.max(1);
+        let queue: Mutex<Vec<(usize, (usize, BuildNode))>> = Mutex::new(jobs.into_iter().enumerate().collect());
+        let results: Mutex<Vec<Option<SubRegion>>> = Mutex::new((0..jobs_len).map(|_| None).collect());
+        thread::scope(|scope| {
+            for _ in 0..self.threads.min(jobs_len) {
+                scope.spawn(|| loop {
+                    let job = queue.lock().unwrap().pop();
+                    let Some((job_idx, (idx, node))) = job else { break };
+                    let sub_region = self.build_region(node, sub_capacity, state, None).map(|(region, _)| region);
+                    results.lock().unwrap()[job_idx] = Some((idx, sub_region));
+                });
+            }
+        });
+
+        // 部分木の領域を連結
+        for (idx, sub_region) in results.into_inner().unwrap().into_iter().flatten() {
+            Self::append_region(&mut region, idx, sub_region?)?;
+        }
+        Ok(region)
+    }
+
+    /// 部分木の数がPARALLEL_SUBTREES以上になる最小の深さを返す
+    /// ASCIIのキーは1バイト目、日本語のキーは1文字目までで十分な数に分割できることが多い
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - トライ木のルート
+    fn split_depth(root: &BuildNode) -> usize {
+        let mut level: Vec<&BuildNode> = vec![root];
+        for depth in 1..MAX_SPLIT_DEPTH {
+            level = level.iter().flat_map(|node| node.nexts.iter()).collect();
+            if level.len() >= PARALLEL_SUBTREES {
+                return depth;
+            }
+        }
+        MAX_SPLIT_DEPTH
+    }
+
+    /// 部分木の領域を再配置して領域の末尾に連結する
+    /// 部分木の領域のindex, tail配列の位置, data配列の位置はすべて連結先の末尾からの相対位置になる
+    ///
+    /// # Arguments
+    ///
+    /// * `region`     - 連結先の領域
+    /// * `idx`        - 連結先の領域での部分木の根のindex
+    /// * `sub_region` - 部分木の領域。部分木の根はindex=1に配置されている
+    fn append_region(region: &mut Region, idx: usize, sub_region: Region) -> Result<(), std::io::Error> {
+        // 連結後の位置もbase値やtail配列に格納できる必要がある
+        base_offset(region.base_arr.len() + sub_region.base_arr.len())?;
+        tail_offset(region.tail_arr.len() + sub_region.tail_arr.len())?;
+        data_offset(region.data_arr.len() + sub_region.data_arr.len())?;
+        let offset     = region.base_arr.len();
+        let tail_shift = region.tail_arr.len() as u32;
+        let data_shift = region.data_arr.len() as u32;
+        let relocate = |base: u32, is_terminal: bool| -> u32 {
+            if base & TAIL_FLAG != 0 {
+                base + tail_shift
+            } else if is_terminal {
+                base + data_shift
+            } else {
+                base + offset as u32
+            }
+        };
+
+        // 部分木の根は連結先の領域に配置済み
+        region.base_arr[idx] = relocate(sub_region.base_arr[1], false);
+        let new_len = offset + sub_region.base_arr.len();
+        region.base_arr.resize(new_len, 0);
+        region.check_arr.resize(new_len, 0);
+        for i in 2..sub_region.base_arr.len() {
+            let check = sub_region.check_arr[i] as usize;
+            if check == 0 {
+                continue;
+            }
+            let is_terminal = i - sub_region.base_arr[check] as usize == TERMINAL_KEY as usize;
+            region.base_arr[offset + i]  = relocate(sub_region.base_arr[i], is_terminal);
+            region.check_arr[offset + i] = if check == 1 { idx as u32 } else { (offset + check) as u32 };
+        }
+
+        // tail配列に格納されたdataの開始indexを再配置
+        let mut tail_arr = sub_region.tail_arr;
+        let mut pos = 0;
+        while pos < tail_arr.len() {
+            let (suffix_len, consumed) = read_varint(&tail_arr[pos..]);
+            pos += consumed + suffix_len;
+            let mut data_idx = [0u8; 4];
+            data_idx.copy_from_slice(&tail_arr[pos..(pos + 4)]);
+            let data_idx = u32::from_le_bytes(data_idx) + data_shift;
+            tail_arr[pos..(pos + 4)].copy_from_slice(&data_idx.to_le_bytes());
+            pos += 4;
+        }
+        region.tail_arr.extend_from_slice(&tail_arr);
+        region.data_arr.extend_from_slice(&sub_region.data_arr);
+        Ok(())
+    }
+
+    /// ノード以降の経路が一意に定まり、末端のノードにのみ値が存在するかを判定する
+    ///
+    /// # Arguments
+    ///
+    /// * `node` - 判定対象のノード
+    fn is_tail_node(node: &BuildNode) -> bool {
+        let mut node = node;
+        while node.data.is_none() {
+            if node.nexts.len() != 1 {
+                return false;
+            }
+            node = &node.nexts[0];
+        }
+        node.nexts.is_empty()
+    }
+
+    /// ノード以降のキーと値をtail配列, data配列に追加する
+    /// tail配列には [接尾辞の長さ(可変長), 接尾辞, dataの開始index(u32)] の順に格納する
+    ///
+    /// # Arguments
+    ///
+    /// * `node`     - 経路が一意に定まったノード
+    /// * `tail_arr` - tail配列
+    /// * `data_arr` - data配列
+    fn push_tail(node: BuildNode, tail_arr: &mut Vec<u8>, data_arr: &mut Vec<u8>) -> Result<(), std::io::Error> {
+        let mut suffix: Vec<u8> = Vec::new();
+        let mut node = node;
+        while node.data.is_none() {
+            node = node.nexts.pop().unwrap();
+            suffix.push(node.key);
+        }
+        write_varint(tail_arr, suffix.len());
+        tail_arr.extend_from_slice(&suffix);
+        tail_arr.extend_from_slice(&data_offset(data_arr.len())?.to_le_bytes());
+        data_arr.extend_from_slice(&node.data.unwrap());
+        Ok(())
+    }
+
+    /// 新しいbase値を探索するメソッド
+    ///
+    /// # Panics
+    /// nodesが空の場合にpanicする。値も子ノードもないノードは配置しないので、構築中には起こらない。
+    ///
+    /// # Arguments
+    ///
+    /// * `nodes`      - 追加対象のノード
+    /// * `bit_cache`  - BitCacheのインスタンス
+    /// * `used_bases` - 使用済みのbase値を管理するBitCacheのインスタンス。Noneの場合はbase値の重複を許す
+    fn find_base(nodes: &[BuildNode], bit_cache: &BitCache, used_bases: Option<&BitCache>) -> usize {
+        if nodes.is_empty() {
+                panic!("探索すべきノードがありません");
+        }
+        let first_key = nodes[0].key as usize;
+        // 先頭ノードを探索開始位置以降に配置するbase値から探索する
+        let mut new_base = bit_cache.start_idx() - first_key;
+        loop {
+            // new_base から64個分のbase値の候補を、先頭ノードの配置先が空いているかどうかで絞り込む
+            let mut candidates = !bit_cache.get_bits(new_base + first_key);
+            if let Some(used_bases) = used_bases {
+                // 他のノードが使用しているbase値は使わない
+                candidates &= !used_bases.get_bits(new_base);
+            }
+            if candidates == 0 {
+                new_base += 64;
+                continue;
+            }
+            new_base += candidates.trailing_zeros() as usize;
+            // すべてのノードが重複せずに配置できるかをチェック
+            if nodes.iter().all(|next| bit_cache.get(new_base + next.key as usize) == 0) {
+                return new_base;
+            }
+            // 配置できなかった場合は次のbase値から探索し直す
+            new_base += 1;
+        }
+    }
+}
+
+impl Default for DoubleArrayBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_base_1() {
+        let nodes: Vec<BuildNode> = vec![
+            BuildNode { key: 2  , data: None, nexts: vec![] },
+            BuildNode { key: 5  , data: None, nexts: vec![] },
+            BuildNode { key: 255, data: None, nexts: vec![] },
+        ];
+        let mut bit_cache = BitCache::new();
+        let mut used_bases = BitCache::new();
+
+        // 探索開始位置 = 256。空きindex = 256
+        // base値 = 空きindex - 先頭ノードのkey = 256 - 2 = 254
+        assert_eq!(254, DoubleArrayBuilder::find_base(&nodes, &bit_cache, Some(&used_bases)));
+
+        // 0 ~ 399, 500 ~ 999 を埋める
+        (256..400).for_each(|i| bit_cache.set(i));
+        (500..1000).for_each(|i| bit_cache.set(i));
+
+        // 探索開始位置 = 256。空きindex = 1000
+        // base値 = 空きindex - 先頭ノードのkey = 1000 - 2 = 998
+        assert_eq!(998, DoubleArrayBuilder::find_base(&nodes, &bit_cache, Some(&used_bases)));
+
+        //1000..1002, 1003..1005, 1006..1255 を埋める
+        (1000..1002).for_each(|i| bit_cache.set(i));
+        (1003..1005).for_each(|i| bit_cache.set(i));
+        (1006..1255).for_each(|i| bit_cache.set(i));
+
+        // 探索開始位置 = 256。空きindex = 1002
+        // base値 = 空きindex - 先頭ノードのkey = 1002 - 2 = 1000
+        assert_eq!(1000, DoubleArrayBuilder::find_base(&nodes, &bit_cache, Some(&used_bases)));
+
+        // 400 ~ 500 を埋める
+        (400..500).for_each(|i| bit_cache.set(i));
+
+        // 探索開始位置=1216。空きindex = 1255
+        // base値 = 空きindex - 先頭ノードのkey = 1255 - 2 = 1253
+        bit_cache.update_start();
+        assert_eq!(1253, DoubleArrayBuilder::find_base(&nodes, &bit_cache, Some(&used_bases)));
+
+        // base値 = 1253 が使用済みの場合は次の空きindex = 1256 を探す
+        // base値 = 空きindex - 先頭ノードのkey = 1256 - 2 = 1254
+        used_bases.set(1253);
+        assert_eq!(1254, DoubleArrayBuilder::find_base(&nodes, &bit_cache, Some(&used_bases)));
+    }
+
+    #[test]
+    #[should_panic(expected = "探索すべきノードがありません")]
+    fn test_find_base_2() {
+        let nodes: Vec<BuildNode> = vec![];
+        let bit_cache = BitCache::new();
+        let used_bases = BitCache::new();
+        // nodesが空の場合は、base値を求められないのでpanic
+        DoubleArrayBuilder::find_base(&nodes, &bit_cache, Some(&used_bases));
+    }
+
+    #[test]
+    fn test_is_tail_node() {
+        let mut trie: Trie<u32> = Trie::new();
+        trie.set("abc", 1);
+        trie.set("abd", 2);
+        trie.set("x", 3);
+        trie.set("xyz", 4);
+        let root = &BuildNode::from_node(trie.root, false).unwrap();
+        // 分岐を含むノードはtailにできない
+        assert!(!DoubleArrayBuilder::is_tail_node(root));
+        assert!(!DoubleArrayBuilder::is_tail_node(&root.nexts[0]));
+        // 末端までの経路が一意なノードはtailにできる
+        assert!(DoubleArrayBuilder::is_tail_node(&root.nexts[0].nexts[0].nexts[0]));
+        // 途中に値を持つノードを含む経路はtailにできない
+        assert!(!DoubleArrayBuilder::is_tail_node(&root.nexts[1]));
+        assert!(DoubleArrayBuilder::is_tail_node(&root.nexts[1].nexts[0]));
+    }
+
+    #[test]
+    fn test_build_tail() {
+        let keys = ["abc", "ab", "abcdefg", "b", "bcd", "bce", "合沢", "会沢"];
+        let mut trie: Trie<u32> = Trie::new();
+        for (i, key) in keys.iter().enumerate() {
+            trie.set(key, i as u32);
+        }
+        let double_array = DoubleArrayBuilder::new().tail(true).build(trie).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(vec![i as u32], double_array.get(key).unwrap());
+        }
+        // tailの途中や、tailを超えるキーはNoneを返す
+        assert_eq!(None, double_array.get("abcd"));
+        assert_eq!(None, double_array.get("abcdefgh"));
+        assert_eq!(None, double_array.get("bc"));
+        assert_eq!(None, double_array.get("合"));
+    }
+
+    #[test]
+    fn test_build_tail_single_key() {
+        // ルートから一意に定まる場合はルートがtailになる
+        let mut trie: Trie<u32> = Trie::new();
+        trie.set("abc", 1);
+        let double_array = DoubleArrayBuilder::new().tail(true).build(trie).unwrap();
+        assert_eq!(vec![1], double_array.get("abc").unwrap());
+        assert_eq!(None, double_array.get("ab"));
+        assert_eq!(None, double_array.get(""));
+        assert_eq!(vec![("abc", vec![1])], double_array.prefix_search("abcd"));
+    }
+
+    #[test]
+    fn test_build_layout() {
+        let keys = ["abc", "ab", "abcdefg", "b", "bcd", "bce", "合沢", "会沢", "哀澤"];
+        for &layout in &[Layout::Separate, Layout::Interleaved, Layout::Compact] {
+            for &tail in &[false, true] {
+                let mut trie: Trie<u32> = Trie::new();
+                for (i, key) in keys.iter().enumerate() {
+                    trie.set(key, i as u32);
+                }
+                let double_array = DoubleArrayBuilder::new().layout(layout).tail(tail).build(trie).unwrap();
+                assert_eq!(layout, double_array.layout());
+                for (i, key) in keys.iter().enumerate() {
+                    assert_eq!(vec![i as u32], double_array.get(key).unwrap());
+                }
+                assert_eq!(None, double_array.get("abcd"));
+                assert_eq!(None, double_array.get("bc"));
+                assert_eq!(None, double_array.get("合"));
+                assert_eq!(None, double_array.get("愛沢"));
+                assert_eq!(vec![("ab", vec![1]), ("abc", vec![0])], double_array.prefix_search("abcd"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_build_parallel() {
+        let mut keys: Vec<String> = Vec::new();
+        for i in 0..2000u32 {
+            let c = char::from_u32(0x3042 + (i % 80)).unwrap();
+            keys.push(format!("{}{}{}", (b'a' + (i % 26) as u8) as char, c, i * 7919 % 1000));
+        }
+        keys.push(String::from("z"));
+        keys.push(String::from(""));
+        let build = |layout: Layout, tail: bool, threads: usize| {
+            let mut trie: Trie<u32> = Trie::new();
+            for (i, key) in keys.iter().enumerate() {
+                trie.set(key, i as u32);
+            }
+            DoubleArrayBuilder::new().layout(layout).tail(tail).threads(threads).build(trie).unwrap()
+        };
+        for &layout in &[Layout::Separate, Layout::Interleaved, Layout::Compact] {
+            for &tail in &[false, true] {
+                let single = build(layout, tail, 1);
+                let parallel = build(layout, tail, 2);
+                for key in keys.iter() {
+                    assert_eq!(single.get(key), parallel.get(key));
+                    assert_eq!(single.prefix_search(key), parallel.prefix_search(key));
+                }
+                assert!(parallel.verify_structure().is_ok());
+                assert_eq!(None, parallel.get("a"));
+                assert_eq!(None, parallel.get("zz"));
+                // スレッド数に依らず同じ出力になる
+                assert_eq!(parallel.as_bytes(), build(layout, tail, 4).as_bytes());
+            }
+        }
+    }
+
+    #[test]
+    fn test_build_parallel_multibyte() {
+        // 日本語のキーはルートの子ノードがUTF-8の先頭バイトの数種類しかないので、1文字目まで分割する
+        let mut keys: Vec<String> = Vec::new();
+        for i in 0..3000u32 {
+            let first = char::from_u32(0x3041 + (i % 83)).unwrap();
+            let second = char::from_u32(0x4E00 + (i * 31 % 500)).unwrap();
+            keys.push(format!("{}{}{}", first, second, i % 7));
+        }
+        let trie = || {
+            let mut trie: Trie<u32> = Trie::new();
+            for (i, key) in keys.iter().enumerate() {
+                trie.set(key, i as u32);
+            }
+            trie
+        };
+        let root = BuildNode::from_node(trie().root, false).unwrap();
+        assert_eq!(3, DoubleArrayBuilder::split_depth(&root));
+        let double_array: DoubleArray<u32> = DoubleArrayBuilder::new().threads(4).build(trie()).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            assert!(double_array.get(key).unwrap().contains(&(i as u32)));
+        }
+        assert_eq!(None, double_array.get("あ"));
+    }
+
+    #[test]
+    fn test_build_with_stats() {
+        let keys = ["abc", "ab", "abcdefg", "b", "bcd", "bce", "合沢", "会沢"];
+        for &tail in &[false, true] {
+            for &threads in &[1, 2] {
+                let mut trie: Trie<u32> = Trie::new();
+                for (i, key) in keys.iter().enumerate() {
+                    trie.set(key, i as u32);
+                }
+                trie.set("ab", 100);
+                let reports: Arc<Mutex<Vec<BuildProgress>>> = Arc::new(Mutex::new(Vec::new()));
+                let reports_ref = Arc::clone(&reports);
+                let (double_array, stats) = DoubleArrayBuilder::new()
+                    .tail(tail)
+                    .threads(threads)
+                    .progress(move |progress| reports_ref.lock().unwrap().push(*progress))
+                    .build_with_stats(trie)
+                    .unwrap();
+                assert_eq!(8, stats.key_count);
+                assert_eq!(9, stats.value_count);
+                assert!(stats.find_base_time.is_some());
+                assert!(stats.fill_ratio > 0.0 && stats.fill_ratio <= 1.0);
+                assert_eq!(stats.fill_ratio, stats.node_count as f64 / stats.array_len as f64);
+                assert_eq!(tail, stats.tail_size > 0);
+                // 読み込んだ辞書からも同じ値が得られる
+                assert_eq!(BuildStats { find_base_time: None, ..stats.clone() }, double_array.stats());
+                // 最後の通知はすべてのノードとバイト数を含む
+                let last = reports.lock().unwrap().iter().map(|p| p.nodes_placed).max().unwrap();
+                assert_eq!(stats.node_count - 1, last);
+                let last = reports.lock().unwrap().iter().map(|p| p.bytes_written).max().unwrap();
+                assert_eq!(stats.tail_size + stats.data_size, last);
+            }
+        }
+    }
+
+    #[test]
+    fn test_build_deterministic() {
+        let mut entries: Vec<(String, u32)> = Vec::new();
+        for i in 0..500u32 {
+            let key = format!("{}{}", (b'a' + (i % 7) as u8) as char, i % 50);
+            entries.push((key, i));
+        }
+        let build = |entries: &[(String, u32)], builder: &DoubleArrayBuilder| {
+            let mut trie: Trie<u32> = Trie::new();
+            for (key, value) in entries {
+                trie.set(key, *value);
+            }
+            builder.build(trie).unwrap()
+        };
+        let builder = DoubleArrayBuilder::new().tail(true).sort_values(true);
+        let expected = build(&entries, &builder);
+        let mut shuffled = entries.clone();
+        shuffled.reverse();
+        shuffled.rotate_left(123);
+        let actual = build(&shuffled, &builder);
+        // 登録順序に依らず同じバイト列になる
+        assert_eq!(expected.as_bytes(), actual.as_bytes());
+        assert_eq!(expected.content_hash(), actual.content_hash());
+        // 同じキーの値は並べ替えられる
+        assert_eq!(expected.get("a0"), actual.get("a0"));
+
+        // sort_values を指定しない場合は値の登録順序が保たれる
+        let builder = DoubleArrayBuilder::new();
+        assert_ne!(build(&entries, &builder).get("a0"), build(&shuffled, &builder).get("a0"));
+    }
+
+    #[test]
+    fn test_build_metadata() {
+        let mut trie: Trie<String> = Trie::new();
+        trie.set("abc", String::from("x"));
+        trie.set("abc", String::from("y"));
+        trie.set("b", String::from("z"));
+        trie.set_metadata("source", "trie");
+        trie.set_metadata("name", "sample");
+        let double_array = DoubleArrayBuilder::new()
+            .metadata("source", "builder")
+            .metadata(METADATA_KEY_COUNT, "0")
+            .build(trie)
+            .unwrap();
+        let metadata = double_array.metadata();
+        assert_eq!("builder", metadata["source"]);
+        assert_eq!("sample", metadata["name"]);
+        // 組み込みのエントリは上書きできない
+        assert_eq!("2", metadata[METADATA_KEY_COUNT]);
+        assert_eq!(env!("CARGO_PKG_VERSION"), metadata[METADATA_VERSION]);
+        assert_eq!("alloc::string::String", metadata[METADATA_VALUE_TYPE]);
+    }
+
+    #[test]
+    fn test_build_compress_data() {
+        let keys = ["abc", "ab", "abcdefg", "b", "bcd", "bce", "合沢", "会沢"];
+        for &tail in &[false, true] {
+            let mut trie: Trie<String> = Trie::new();
+            for key in keys.iter() {
+                trie.set(key, key.repeat(10));
+            }
+            trie.set("ab", String::from("x"));
+            let (double_array, stats) = DoubleArrayBuilder::new()
+                .tail(tail)
+                .compress_data(true)
+                .data_block_size(16)
+                .build_with_stats(trie)
+                .unwrap();
+            double_array.set_block_cache_size(1);
+            for key in keys.iter() {
+                assert_eq!(key.repeat(10), double_array.get(key).unwrap()[0]);
+            }
+            assert_eq!(vec![("ab", vec!["ab".repeat(10), String::from("x")]), ("abc", vec!["abc".repeat(10)])], double_array.prefix_search("abcd"));
+            assert_eq!(9, stats.value_count);
+        }
+    }
+
+    #[test]
+    fn test_offset_overflow() {
+        // tail配列の位置はTAIL_FLAGと重なってはいけない
+        assert_eq!(TAIL_FLAG - 1, tail_offset(TAIL_FLAG as usize - 1).unwrap());
+        assert_eq!(std::io::ErrorKind::InvalidInput, tail_offset(TAIL_FLAG as usize).unwrap_err().kind());
+        // base配列のindexも同様
+        assert_eq!(TAIL_FLAG - 1, base_offset(TAIL_FLAG as usize - 1).unwrap());
+        assert_eq!(std::io::ErrorKind::InvalidInput, base_offset(TAIL_FLAG as usize).unwrap_err().kind());
+        // data配列の位置はu32に収まる必要がある
+        assert_eq!(u32::MAX, data_offset(u32::MAX as usize).unwrap());
+        assert!(data_offset(u32::MAX as usize + 1).is_err());
+    }
+}
@@ -0,0 +1,295 @@
+use std::io;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+
+use crate::format::invalid_data;
+
+use bincode;
+use serde::de::DeserializeOwned;
+
+/// 圧縮したdata配列のブロックのデフォルトのバイト数 (展開後)
+pub const DEFAULT_BLOCK_SIZE: usize = 1 << 16;
+
+/// 展開したブロックを保持する数のデフォルト
+pub const DEFAULT_BLOCK_CACHE_SIZE: usize = 16;
+
+/// ブロックインデックスの固定長部分のバイト数 (ブロックのバイト数, 展開後のdata配列のバイト数)
+const INDEX_FIXED_SIZE: usize = 16;
+
+/// data配列を固定長のブロックに分割してLZ4で圧縮する
+/// 戻り値は (圧縮したブロックを連結したバイト列, ブロックインデックス)
+///
+/// ブロックインデックスには [ブロックのバイト数(u64), 展開後のdata配列のバイト数(u64), 各ブロックの開始位置(u64) * (ブロック数 + 1)]
+/// をリトルエンディアンで格納する。
+///
+/// # Arguments
+///
+/// * `data_arr`   - data配列
+/// * `block_size` - 展開後のブロックのバイト数
+pub(crate) fn compress_blocks(data_arr: &[u8], block_size: usize) -> (Vec<u8>, Vec<u8>) {
+    let mut blocks: Vec<u8> = Vec::new();
+    let mut index: Vec<u8> = Vec::with_capacity(INDEX_FIXED_SIZE + 8 * (data_arr.len() / block_size + 2));
+    index.extend_from_slice(&(block_size as u64).to_le_bytes());
+    index.extend_from_slice(&(data_arr.len() as u64).to_le_bytes());
+    for block in data_arr.chunks(block_size) {
+        index.extend_from_slice(&(blocks.len() as u64).to_le_bytes());
+        blocks.extend_from_slice(&lz4_flex::block::compress(block));
+    }
+    index.extend_from_slice(&(blocks.len() as u64).to_le_bytes());
+    (blocks, index)
+}
+
+/// data配列への参照
+/// 圧縮されたdata配列では、dataの開始indexは展開後のdata配列での位置を表す。
+/// 開始indexを i、ブロックのバイト数を s とすると、(i / s) 番目のブロックの (i % s) バイト目から読み始める。
+pub(crate) enum DataArr<'a> {
+    /// 圧縮されていないdata配列
+    Plain(&'a [u8]),
+    /// ブロックごとに圧縮されたdata配列
+    Blocks(Blocks<'a>),
+}
+
+impl<'a> DataArr<'a> {
+    /// data配列のindexから値をデシリアライズする
+    ///
+    /// # Panics
+    /// 値をデシリアライズできなかった場合にpanicする。
+    ///
+    /// # Arguments
+    ///
+    /// * `data_idx` - dataの開始index
+    pub(crate) fn decode<T: DeserializeOwned>(&self, data_idx: usize) -> Vec<T> {
+        match self {
+            DataArr::Plain(data_arr) => bincode::deserialize(&data_arr[data_idx..]).unwrap(),
+            DataArr::Blocks(blocks)  => bincode::deserialize_from(blocks.reader(data_idx)).unwrap(),
+        }
+    }
+
+    /// data配列のindexから値をデシリアライズする。デシリアライズできなかった場合はエラーを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `data_idx` - dataの開始index
+    pub(crate) fn try_decode<T: DeserializeOwned>(&self, data_idx: usize) -> Result<Vec<T>, io::Error> {
+        let values = match self {
+            DataArr::Plain(data_arr) => {
+                let bytes = data_arr.get(data_idx..).ok_or_else(|| invalid_data(&format!("data index {data_idx} is out of range")))?;
+                bincode::deserialize(bytes)
+            },
+            DataArr::Blocks(blocks)  => bincode::deserialize_from(blocks.reader(data_idx)),
+        };
+        values.map_err(|e| invalid_data(&format!("invalid values at data index {data_idx}: {e}")))
+    }
+
+    /// data配列のindexに格納された値の数を返す
+    /// bincodeでシリアライズしたVecは先頭に要素数(u64)を持つ
+    ///
+    /// # Arguments
+    ///
+    /// * `data_idx` - dataの開始index
+    pub(crate) fn value_len(&self, data_idx: usize) -> usize {
+        let len: u64 = match self {
+            DataArr::Plain(data_arr) => bincode::deserialize(&data_arr[data_idx..]).unwrap(),
+            DataArr::Blocks(blocks)  => bincode::deserialize_from(blocks.reader(data_idx)).unwrap(),
+        };
+        len as usize
+    }
+
+    /// data配列のセクションのバイト数 (圧縮されている場合は圧縮後)
+    pub(crate) fn stored_len(&self) -> usize {
+        match self {
+            DataArr::Plain(data_arr) => data_arr.len(),
+            DataArr::Blocks(blocks)  => blocks.blocks.len(),
+        }
+    }
+}
+
+/// ブロックごとに圧縮されたdata配列
+pub(crate) struct Blocks<'a> {
+    block_size: usize,
+    data_len  : usize,
+    offsets   : &'a [u8],
+    blocks    : &'a [u8],
+    cache     : &'a Mutex<BlockCache>,
+}
+
+impl<'a> Blocks<'a> {
+    /// ブロックインデックスと圧縮したブロックから生成する
+    ///
+    /// # Arguments
+    ///
+    /// * `index`  - ブロックインデックス
+    /// * `blocks` - 圧縮したブロックを連結したバイト列
+    /// * `cache`  - 展開したブロックのキャッシュ
+    pub(crate) fn new(index: &'a [u8], blocks: &'a [u8], cache: &'a Mutex<BlockCache>) -> Result<Blocks<'a>, io::Error> {
+        if index.len() < INDEX_FIXED_SIZE + 8 || !(index.len() - INDEX_FIXED_SIZE).is_multiple_of(8) {
+            return Err(invalid_data("invalid block index"));
+        }
+        let block_size = read_u64(index, 0) as usize;
+        let data_len = read_u64(index, 8) as usize;
+        let offsets = &index[INDEX_FIXED_SIZE..];
+        let block_count = offsets.len() / 8 - 1;
+        if block_size == 0 || block_count != data_len.div_ceil(block_size) || read_u64(offsets, block_count * 8) as usize != blocks.len() {
+            return Err(invalid_data("invalid block index"));
+        }
+        Ok(Blocks { block_size, data_len, offsets, blocks, cache })
+    }
+
+    /// 展開後のdata配列のposバイト目から読み取るReader
+    ///
+    /// # Arguments
+    ///
+    /// * `pos` - 読み始める位置
+    fn reader(&self, pos: usize) -> BlockReader<'_, 'a> {
+        BlockReader { blocks: self, pos, current: None }
+    }
+
+    /// idx番目のブロックを展開して返す。キャッシュにある場合は展開しない
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - ブロックのindex
+    fn block(&self, idx: usize) -> Result<Arc<Vec<u8>>, io::Error> {
+        if let Some(block) = self.cache.lock().unwrap().get(idx) {
+            return Ok(block);
+        }
+        let start = read_u64(self.offsets, idx * 8) as usize;
+        let end = read_u64(self.offsets, (idx + 1) * 8) as usize;
+        let len = self.block_size.min(self.data_len - idx * self.block_size);
+        let compressed = self.blocks.get(start..end).ok_or_else(|| invalid_data("invalid block index"))?;
+        let block = lz4_flex::block::decompress(compressed, len).map_err(|e| invalid_data(&e.to_string()))?;
+        let block = Arc::new(block);
+        self.cache.lock().unwrap().put(idx, Arc::clone(&block));
+        Ok(block)
+    }
+}
+
+/// 圧縮されたdata配列を展開しながら読み取るReader
+/// ブロックの境界をまたぐ値も読み取れる
+struct BlockReader<'b, 'a> {
+    blocks : &'b Blocks<'a>,
+    pos    : usize,
+    current: Option<(usize, Arc<Vec<u8>>)>,
+}
+
+impl<'b, 'a> Read for BlockReader<'b, 'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.blocks.data_len || buf.is_empty() {
+            return Ok(0);
+        }
+        let idx = self.pos / self.blocks.block_size;
+        let offset = self.pos % self.blocks.block_size;
+        if self.current.as_ref().map(|(i, _)| *i) != Some(idx) {
+            self.current = Some((idx, self.blocks.block(idx)?));
+        }
+        let block = &self.current.as_ref().unwrap().1;
+        let n = buf.len().min(block.len() - offset);
+        buf[..n].copy_from_slice(&block[offset..(offset + n)]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// 展開したブロックのLRUキャッシュ
+#[derive(Debug)]
+pub(crate) struct BlockCache {
+    capacity: usize,
+    /// 最近使われた順に並べた (ブロックのindex, 展開したブロック)
+    blocks  : Vec<(usize, Arc<Vec<u8>>)>,
+}
+
+impl BlockCache {
+    /// # Arguments
+    ///
+    /// * `capacity` - 保持するブロックの数
+    pub(crate) fn new(capacity: usize) -> Self {
+        BlockCache { capacity, blocks: Vec::with_capacity(capacity) }
+    }
+
+    /// 保持するブロックの数を変更する
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - 保持するブロックの数
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.blocks.truncate(capacity);
+    }
+
+    /// ブロックを取得し、最近使われたブロックとして先頭に移動する
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - ブロックのindex
+    fn get(&mut self, idx: usize) -> Option<Arc<Vec<u8>>> {
+        let pos = self.blocks.iter().position(|(i, _)| *i == idx)?;
+        let entry = self.blocks.remove(pos);
+        let block = Arc::clone(&entry.1);
+        self.blocks.insert(0, entry);
+        Some(block)
+    }
+
+    /// ブロックを追加する。保持する数を超えた場合は最も古いブロックを捨てる
+    ///
+    /// # Arguments
+    ///
+    /// * `idx`   - ブロックのindex
+    /// * `block` - 展開したブロック
+    fn put(&mut self, idx: usize, block: Arc<Vec<u8>>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.blocks.retain(|(i, _)| *i != idx);
+        self.blocks.truncate(self.capacity - 1);
+        self.blocks.insert(0, (idx, block));
+    }
+}
+
+fn read_u64(bytes: &[u8], pos: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[pos..(pos + 8)]);
+    u64::from_le_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compress_blocks() {
+        let values: Vec<Vec<String>> = (0..100).map(|i| vec![format!("value-{}", i); i % 3 + 1]).collect();
+        let mut data_arr: Vec<u8> = Vec::new();
+        let mut data_idx: Vec<usize> = Vec::new();
+        for v in values.iter() {
+            data_idx.push(data_arr.len());
+            data_arr.extend_from_slice(&bincode::serialize(v).unwrap());
+        }
+        // 値がブロックの境界をまたぐように小さなブロックにする
+        let (blocks, index) = compress_blocks(&data_arr, 32);
+        let cache = Mutex::new(BlockCache::new(2));
+        let data = DataArr::Blocks(Blocks::new(&index, &blocks, &cache).unwrap());
+        for (v, &idx) in values.iter().zip(data_idx.iter()) {
+            assert_eq!(*v, data.decode::<String>(idx));
+            assert_eq!(v.len(), data.value_len(idx));
+        }
+        assert_eq!(2, cache.lock().unwrap().blocks.len());
+        // ブロックインデックスが壊れている
+        assert!(Blocks::new(&index[..(index.len() - 8)], &blocks, &cache).is_err());
+    }
+
+    #[test]
+    fn test_block_cache() {
+        let mut cache = BlockCache::new(2);
+        cache.put(0, Arc::new(vec![0]));
+        cache.put(1, Arc::new(vec![1]));
+        assert_eq!(Some(Arc::new(vec![0])), cache.get(0));
+        // 最も古い 1 が捨てられる
+        cache.put(2, Arc::new(vec![2]));
+        assert_eq!(None, cache.get(1));
+        assert_eq!(Some(Arc::new(vec![0])), cache.get(0));
+        assert_eq!(Some(Arc::new(vec![2])), cache.get(2));
+        cache.set_capacity(0);
+        cache.put(3, Arc::new(vec![3]));
+        assert_eq!(None, cache.get(3));
+    }
+}
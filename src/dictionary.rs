@@ -0,0 +1,118 @@
+use std::fmt::Debug;
+
+use crate::double_array::DoubleArray;
+use crate::trie::Trie;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// `Trie` と `DoubleArray` に共通の検索の操作
+/// 検索する側のコードをこのトレイトで書くと、テストでは構築途中の `Trie` を、本番ではファイルから読み込んだ `DoubleArray` を使える。
+/// イテレータは `Box` で返すので、`&dyn Dictionary<T>` としても使える。
+///
+/// # Examples
+///
+/// ```
+/// use dary::{Dictionary, Trie};
+///
+/// fn longest_match<D: Dictionary<u32> + ?Sized>(dictionary: &D, text: &str) -> Option<usize> {
+///     dictionary.prefix_search_iter(text).last().map(|(prefix, _)| prefix.len())
+/// }
+///
+/// let entries = [("東京", 1), ("東京都", 2)];
+/// let trie: Trie<u32> = entries.iter().cloned().collect();
+/// assert_eq!(Some(9), longest_match(&trie, "東京都庁"));
+/// let double_array = trie.to_double_array().unwrap();
+/// assert_eq!(Some(9), longest_match(&double_array, "東京都庁"));
+/// ```
+pub trait Dictionary<T> {
+    /// keyに登録された値を返す。登録されていない場合はNone
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - 探索するkey
+    fn get(&self, key: &str) -> Option<Vec<T>>;
+
+    /// keyに値が登録されているかどうか
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - 探索するkey
+    fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// keyの接頭辞になっている登録済みのキーと値を、短い順に列挙するイテレータを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - 探索対象の文字列
+    fn prefix_search_iter<'a>(&'a self, key: &'a str) -> Box<dyn Iterator<Item = (&'a str, Vec<T>)> + 'a>;
+
+    /// prefixで始まるすべてのキーと値をキーの辞書順に列挙するイテレータを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - 探索対象の接頭辞
+    fn predictive_search_iter<'a>(&'a self, prefix: &str) -> Box<dyn Iterator<Item = (String, Vec<T>)> + 'a>;
+}
+
+impl<T: Serialize + DeserializeOwned + Debug> Dictionary<T> for DoubleArray<T> {
+    fn get(&self, key: &str) -> Option<Vec<T>> {
+        DoubleArray::get(self, key)
+    }
+
+    fn prefix_search_iter<'a>(&'a self, key: &'a str) -> Box<dyn Iterator<Item = (&'a str, Vec<T>)> + 'a> {
+        Box::new(DoubleArray::prefix_search_iter(self, key))
+    }
+
+    fn predictive_search_iter<'a>(&'a self, prefix: &str) -> Box<dyn Iterator<Item = (String, Vec<T>)> + 'a> {
+        Box::new(DoubleArray::predictive_search_iter(self, prefix))
+    }
+}
+
+/// 値はトライ木の中の値を複製して返す
+impl<T: Serialize + DeserializeOwned + Debug + Clone> Dictionary<T> for Trie<T> {
+    fn get(&self, key: &str) -> Option<Vec<T>> {
+        Trie::get(self, key).map(|values| values.to_vec())
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.contains_key(key)
+    }
+
+    fn prefix_search_iter<'a>(&'a self, key: &'a str) -> Box<dyn Iterator<Item = (&'a str, Vec<T>)> + 'a> {
+        Box::new(Trie::prefix_search_iter(self, key).map(|(prefix, values)| (prefix, values.to_vec())))
+    }
+
+    fn predictive_search_iter<'a>(&'a self, prefix: &str) -> Box<dyn Iterator<Item = (String, Vec<T>)> + 'a> {
+        Box::new(Trie::predictive_search_iter(self, prefix).map(|(key, values)| (key, values.to_vec())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::DoubleArrayBuilder;
+    use crate::alphabet::Alphabet;
+
+    #[test]
+    fn test_dictionary() {
+        let entries = [("東京", 1), ("東京", 2), ("東京都", 3), ("京都", 4), ("abc", 5)];
+        let trie: Trie<u32> = entries.iter().cloned().collect();
+        let double_array = entries.iter().cloned().collect::<Trie<u32>>().to_double_array().unwrap();
+        let code_point = DoubleArrayBuilder::new().tail(true).alphabet(Alphabet::CodePoint)
+            .build(entries.iter().cloned().collect()).unwrap();
+        let dictionaries: Vec<&dyn Dictionary<u32>> = vec![&trie, &double_array, &code_point];
+        for dictionary in dictionaries {
+            assert_eq!(Some(vec![1, 2]), dictionary.get("東京"));
+            assert!(dictionary.contains("京都"));
+            assert!(!dictionary.contains("東"));
+            let prefixes: Vec<(&str, Vec<u32>)> = dictionary.prefix_search_iter("東京都庁").collect();
+            assert_eq!(vec![("東京", vec![1, 2]), ("東京都", vec![3])], prefixes);
+            let keys: Vec<String> = dictionary.predictive_search_iter("").map(|(key, _)| key).collect();
+            assert_eq!(vec!["abc", "京都", "東京", "東京都"], keys);
+            assert_eq!(0, dictionary.predictive_search_iter("大").count());
+        }
+    }
+}
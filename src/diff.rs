@@ -0,0 +1,140 @@
+use std::cmp::Ordering;
+use std::fmt::Debug;
+use std::iter::Peekable;
+
+use crate::double_array::{DoubleArray, Iter};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// 2つの辞書の差分の1件
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffEntry<T> {
+    /// 新しい辞書にだけあるキーと値
+    Added(String, Vec<T>),
+    /// 古い辞書にだけあるキーと値
+    Removed(String, Vec<T>),
+    /// 両方の辞書にあり値が異なるキーと、古い値, 新しい値
+    Changed(String, Vec<T>, Vec<T>),
+}
+
+impl<T> DiffEntry<T> {
+    /// 差分のキー
+    pub fn key(&self) -> &str {
+        match self {
+            DiffEntry::Added(key, _) | DiffEntry::Removed(key, _) | DiffEntry::Changed(key, _, _) => key,
+        }
+    }
+}
+
+/// 2つの辞書をキーの辞書順に比較し、差分を列挙するイテレータを返す
+/// 値は同じキーに登録された値の列として比較するので、値の順序が変わった場合も `Changed` になる。
+///
+/// # Examples
+///
+/// ```
+/// use dary::{Trie, DiffEntry};
+///
+/// let mut old: Trie<u32> = Trie::new();
+/// old.set("abc", 1);
+/// old.set("def", 2);
+/// let mut new: Trie<u32> = Trie::new();
+/// new.set("abc", 3);
+/// new.set("xyz", 4);
+///
+/// let old = old.to_double_array().unwrap();
+/// let new = new.to_double_array().unwrap();
+/// let entries: Vec<DiffEntry<u32>> = dary::diff(&old, &new).collect();
+/// assert_eq!(vec![
+///     DiffEntry::Changed(String::from("abc"), vec![1], vec![3]),
+///     DiffEntry::Removed(String::from("def"), vec![2]),
+///     DiffEntry::Added(String::from("xyz"), vec![4]),
+/// ], entries);
+/// ```
+///
+/// # Arguments
+///
+/// * `old` - 比較元の辞書
+/// * `new` - 比較先の辞書
+pub fn diff<'a, T>(old: &'a DoubleArray<T>, new: &'a DoubleArray<T>) -> Diff<'a, T>
+    where T: Serialize + DeserializeOwned + Debug + PartialEq,
+{
+    Diff { old: old.iter().peekable(), new: new.iter().peekable() }
+}
+
+/// 2つの辞書の差分を列挙するイテレータ
+pub struct Diff<'a, T>
+    where T: Serialize + DeserializeOwned + Debug + PartialEq,
+{
+    old: Peekable<Iter<'a, T>>,
+    new: Peekable<Iter<'a, T>>,
+}
+
+impl<'a, T> Iterator for Diff<'a, T>
+    where T: Serialize + DeserializeOwned + Debug + PartialEq,
+{
+    type Item = DiffEntry<T>;
+
+    fn next(&mut self) -> Option<DiffEntry<T>> {
+        loop {
+            let order = match (self.old.peek(), self.new.peek()) {
+                (None, None)                     => return None,
+                (Some(_), None)                  => Ordering::Less,
+                (None, Some(_))                  => Ordering::Greater,
+                (Some((old, _)), Some((new, _))) => old.cmp(new),
+            };
+            match order {
+                Ordering::Less    => {
+                    let (key, values) = self.old.next().unwrap();
+                    return Some(DiffEntry::Removed(key, values));
+                },
+                Ordering::Greater => {
+                    let (key, values) = self.new.next().unwrap();
+                    return Some(DiffEntry::Added(key, values));
+                },
+                Ordering::Equal   => {
+                    let (key, old_values) = self.old.next().unwrap();
+                    let (_, new_values) = self.new.next().unwrap();
+                    if old_values != new_values {
+                        return Some(DiffEntry::Changed(key, old_values, new_values));
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trie::Trie;
+
+    fn to_double_array(entries: &[(&str, u32)]) -> DoubleArray<u32> {
+        let mut trie: Trie<u32> = Trie::new();
+        for &(key, value) in entries {
+            trie.set(key, value);
+        }
+        trie.to_double_array().unwrap()
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = to_double_array(&[("a", 1), ("ab", 2), ("ab", 3), ("abc", 4), ("b", 5), ("合沢", 6)]);
+        let new = to_double_array(&[("a", 1), ("ab", 3), ("ab", 2), ("abd", 7), ("b", 5), ("合沢", 8), ("合沢", 9)]);
+        let entries: Vec<DiffEntry<u32>> = diff(&old, &new).collect();
+        let expected = vec![
+            DiffEntry::Changed(String::from("ab"), vec![2, 3], vec![3, 2]),
+            DiffEntry::Removed(String::from("abc"), vec![4]),
+            DiffEntry::Added(String::from("abd"), vec![7]),
+            DiffEntry::Changed(String::from("合沢"), vec![6], vec![8, 9]),
+        ];
+        assert_eq!(expected, entries);
+        assert_eq!("abd", entries[2].key());
+        // 同じ辞書
+        assert_eq!(0, diff(&old, &old).count());
+        // 空の辞書との比較
+        let empty = to_double_array(&[]);
+        assert!(diff(&empty, &old).all(|entry| matches!(entry, DiffEntry::Added(_, _))));
+        assert_eq!(5, diff(&old, &empty).count());
+    }
+}
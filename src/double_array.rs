@@ -1,27 +1,35 @@
+use std::any;
+use std::borrow::Cow;
+use std::cmp::Reverse;
 use std::fmt::Debug;
-use std::slice;
-use std::mem;
+use std::collections::BTreeMap;
 use std::io::prelude::*;
 use std::fs::File;
 use std::fs::OpenOptions;
-use std::ptr;
 use std::marker::PhantomData;
+use std::sync::Mutex;
 
 use crate::utils::*;
+use crate::alphabet::{Alphabet, CodeTable};
+use crate::builder::DoubleArrayBuilder;
+use crate::data::{Blocks, BlockCache, DataArr, DEFAULT_BLOCK_CACHE_SIZE};
+use crate::format::{Header, SectionKind, METADATA_NORMALIZER, METADATA_SCHEMA_VERSION, METADATA_VALUE_TYPE, invalid_data};
+use crate::layout::{Layout, Units, EMPTY_LABEL};
+use crate::merge::MergePolicy;
+use crate::normalizer::Normalizer;
+use crate::stats::BuildStats;
 
 use memmap::*;
 use bincode;
+use xxhash_rust::xxh64::xxh64;
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 
-#[derive(Debug)]
-struct DoubleArrayHeader {
-    base_idx  : usize,
-    check_idx : usize,
-    data_idx  : usize,
-    base_len  : usize,
-    check_len : usize,
-}
+/// 値を持つノードを表す遷移のkey
+pub(crate) const TERMINAL_KEY: u8 = u8::MAX;
+
+/// base値がtail配列のindexであることを表すフラグ
+pub(crate) const TAIL_FLAG: u32 = 1 << 31;
 
 /// ダブル配列の実装。
 ///
@@ -64,7 +72,10 @@ struct DoubleArrayHeader {
 #[derive(Debug)]
 pub struct DoubleArray<T: Serialize + DeserializeOwned + Debug> {
     mmap: Mmap,
-    header: DoubleArrayHeader,
+    header: Header,
+    block_cache: Mutex<BlockCache>,
+    code_table: Option<CodeTable>,
+    normalizer: Option<Normalizer>,
     phantom: PhantomData<T>,
 }
 
@@ -78,67 +89,250 @@ impl<T: Serialize + DeserializeOwned + Debug> DoubleArray<T> {
     /// * `check_arr`  - check配列
     /// * `data_bytes` - data配列
     pub fn from_arrays(base_arr: &[u32], check_arr: &[u32], data_bytes: &[u8]) -> Result<Self, std::io::Error> {
-        let base_bytes = to_bytes(base_arr);
-        let check_bytes = to_bytes(check_arr);
-        // headerの生成
-        let header_size: usize = mem::size_of::<DoubleArrayHeader>();
-        let header = DoubleArrayHeader {
-            base_idx        : header_size,
-            check_idx       : header_size + base_bytes.len(),
-            data_idx        : header_size + base_bytes.len() + check_bytes.len(),
-            base_len        : base_arr.len(),
-            check_len       : check_arr.len(),
-        };
-
-        // header をバイト列にする
-        let header_bytes: &[u8] = unsafe {
-            slice::from_raw_parts(
-                &header as *const DoubleArrayHeader as *const u8,
-                header_size,
-            )
-        };
+        let mut metadata = BTreeMap::new();
+        metadata.insert(METADATA_VALUE_TYPE.to_string(), any::type_name::<T>().to_string());
+        Self::from_sections(Layout::Separate, base_arr, check_arr, &[], data_bytes, &[], None, &metadata)
+    }
 
-        let bytes_len = header_size + base_bytes.len() + check_bytes.len() + data_bytes.len();
+    /// base配列, check配列, tail配列, data配列からDoubleArrayインスタンスを生成する。
+    ///
+    /// # Arguments
+    ///
+    /// * `layout`     - base配列, check配列のレイアウト
+    /// * `base_arr`   - base配列
+    /// * `check_arr`  - check配列
+    /// * `tail_bytes` - tail配列
+    /// * `data_bytes` - data配列。圧縮されている場合は圧縮したブロックを連結したバイト列
+    /// * `data_index` - 圧縮したdata配列のブロックインデックス。圧縮しない場合は空
+    /// * `code_table` - 文字の符号表。`Alphabet::Byte` の場合はNone
+    /// * `metadata`   - メタデータ
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn from_sections(
+        layout: Layout,
+        base_arr: &[u32],
+        check_arr: &[u32],
+        tail_bytes: &[u8],
+        data_bytes: &[u8],
+        data_index: &[u8],
+        code_table: Option<&CodeTable>,
+        metadata: &BTreeMap<String, String>,
+    ) -> Result<Self, std::io::Error> {
+        let (base_bytes, check_bytes) = layout.encode(base_arr, check_arr);
+        let metadata_bytes = bincode::serialize(metadata).unwrap();
+        let code_table_bytes = code_table.map(|code_table| code_table.to_bytes());
+        let mut sections: Vec<(SectionKind, &[u8])> = vec![
+            (SectionKind::Base    , &base_bytes),
+            (SectionKind::Check   , &check_bytes),
+            (SectionKind::Tail    , tail_bytes),
+            (SectionKind::Data    , data_bytes),
+            (SectionKind::Metadata, &metadata_bytes),
+        ];
+        if !data_index.is_empty() {
+            sections.push((SectionKind::DataIndex, data_index));
+        }
+        if let Some(code_table_bytes) = code_table_bytes.as_ref() {
+            sections.push((SectionKind::CodeTable, code_table_bytes));
+        }
+        let header = Header::new(layout, &sections);
         let mut mmap_options = MmapOptions::new();
-        let mut mmap_mut: MmapMut = mmap_options.len(bytes_len).map_anon()?;
-        (&mut mmap_mut[..]).write_all(header_bytes)?;
-        (&mut mmap_mut[header.base_idx..]).write_all(base_bytes)?;
-        (&mut mmap_mut[header.check_idx..]).write_all(check_bytes)?;
-        (&mut mmap_mut[header.data_idx..]).write_all(&data_bytes)?;
+        let mut mmap_mut: MmapMut = mmap_options.len(header.file_len()).map_anon()?;
+        let section_bytes: Vec<&[u8]> = sections.iter().map(|&(_, bytes)| bytes).collect();
+        header.write(&mut mmap_mut, &section_bytes);
         let mmap: Mmap = mmap_mut.make_read_only()?;
-        Ok(DoubleArray { mmap, header, phantom: PhantomData })
+        Self::new(mmap, header)
+    }
+
+    /// mmapとヘッダからDoubleArrayインスタンスを生成する。
+    /// 圧縮されたdata配列のブロックインデックスを検証し、文字の符号表とキーの正規化を読み込む
+    ///
+    /// # Arguments
+    ///
+    /// * `mmap`   - 辞書ファイルのバイト列
+    /// * `header` - 読み取ったヘッダ
+    fn new(mmap: Mmap, header: Header) -> Result<Self, std::io::Error> {
+        let code_table_range = header.range(SectionKind::CodeTable);
+        let code_table = if code_table_range.is_empty() {
+            None
+        } else {
+            Some(CodeTable::from_bytes(&mmap[code_table_range])?)
+        };
+        let mut double_array = DoubleArray {
+            mmap,
+            header,
+            block_cache: Mutex::new(BlockCache::new(DEFAULT_BLOCK_CACHE_SIZE)),
+            code_table,
+            normalizer: None,
+            phantom: PhantomData,
+        };
+        double_array.data_arr()?;
+        if let Some(value) = double_array.read_metadata()?.get(METADATA_NORMALIZER) {
+            double_array.normalizer = Some(value.parse::<Normalizer>()?).filter(|normalizer| !normalizer.is_identity());
+        }
+        Ok(double_array)
     }
 
     /// u8の配列からDoubleArrayインスタンスを生成する。
+    /// 辞書に記録された値の型が T と一致しない場合はエラーを返す。
     ///
     /// # Arguments
     ///
-    /// * `bytes` - base配列, check配列, data配列を u8 の配列として連結させた配列
+    /// * `bytes` - 辞書ファイルのバイト列
     pub fn from_slice(bytes: &[u8]) -> Result<Self, std::io::Error> {
+        let header = Header::read(bytes)?;
         let mut mmap_options = MmapOptions::new();
         let mut mmap_mut: MmapMut = mmap_options.len(bytes.len()).map_anon()?;
         mmap_mut.copy_from_slice(bytes);
         let mmap: Mmap = mmap_mut.make_read_only()?;
-        let header: DoubleArrayHeader = unsafe {
-            ptr::read((&mmap).as_ptr() as *const DoubleArrayHeader)
-        };
-        Ok(DoubleArray { mmap, header, phantom: PhantomData })
+        let double_array = Self::new(mmap, header)?;
+        double_array.check_value_type()?;
+        Ok(double_array)
     }
 
     /// ファイルからDoubleArrayインスタンスを生成する。
+    /// ヘッダのチェックサムと、ファイルの長さがヘッダに記録されたセクションの範囲を満たすことを検証する。
+    /// セクションの内容まで検証する場合は `from_file_verified` を利用する。
+    ///
+    /// 辞書に記録された値の型 (`std::any::type_name::<T>()`) が T と一致しない場合はエラーを返す。
+    /// 型名はコンパイラのバージョンや型のパスによって変わることがあるので、
+    /// 型名が変わっても同じ表現であることが分かっている場合は `from_file_unchecked` を利用する。
     ///
     /// # Arguments
     ///
     /// * `dictionary_path` - 辞書ファイルパス
     pub fn from_file(dictionary_path: &str) -> Result<Self, std::io::Error> {
+        let double_array = Self::from_file_unchecked(dictionary_path)?;
+        double_array.check_value_type()?;
+        Ok(double_array)
+    }
+
+    /// ファイルからDoubleArrayインスタンスを生成する。
+    /// `from_file` と異なり、辞書に記録された値の型を検証しない。
+    /// 型が異なる辞書を読み込んだ場合、検索結果のデシリアライズに失敗してpanicすることがある。
+    ///
+    /// # Arguments
+    ///
+    /// * `dictionary_path` - 辞書ファイルパス
+    pub fn from_file_unchecked(dictionary_path: &str) -> Result<Self, std::io::Error> {
         let file: File = File::open(dictionary_path)?;
         let mmap: Mmap = unsafe {
             MmapOptions::new().map(&file)?
         };
-        let header: DoubleArrayHeader = unsafe {
-            ptr::read((&mmap).as_ptr() as *const DoubleArrayHeader)
-        };
-        Ok(DoubleArray { mmap, header, phantom: PhantomData })
+        let header = Header::read(&mmap)?;
+        Self::new(mmap, header)
+    }
+
+    /// ファイルからDoubleArrayインスタンスを生成し、値のスキーマのバージョンを検証する。
+    /// 辞書に記録されたバージョン (`DoubleArrayBuilder::schema_version`) が一致しない場合はエラーを返す。
+    ///
+    /// # Arguments
+    ///
+    /// * `dictionary_path` - 辞書ファイルパス
+    /// * `schema_version`  - 期待するスキーマのバージョン
+    pub fn from_file_with_schema(dictionary_path: &str, schema_version: u32) -> Result<Self, std::io::Error> {
+        let double_array = Self::from_file(dictionary_path)?;
+        match double_array.schema_version()? {
+            Some(version) if version == schema_version => Ok(double_array),
+            Some(version) => Err(invalid_data(&format!(
+                "schema version mismatch: the dictionary has {version}, but {schema_version} was requested",
+            ))),
+            None => Err(invalid_data("the dictionary has no schema version")),
+        }
+    }
+
+    /// 辞書に記録された値の型と T が一致することを検証する
+    /// 型が記録されていない辞書は検証しない
+    fn check_value_type(&self) -> Result<(), std::io::Error> {
+        let metadata = self.read_metadata()?;
+        let expected = any::type_name::<T>();
+        match metadata.get(METADATA_VALUE_TYPE) {
+            Some(value_type) if value_type != expected => Err(invalid_data(&format!(
+                "value type mismatch: the dictionary stores {value_type}, but {expected} was requested",
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// 辞書に記録された値のスキーマのバージョン。記録されていない場合はNone
+    pub fn schema_version(&self) -> Result<Option<u32>, std::io::Error> {
+        match self.read_metadata()?.get(METADATA_SCHEMA_VERSION) {
+            Some(version) => version.parse().map(Some).map_err(|_| invalid_data("invalid schema version")),
+            None => Ok(None),
+        }
+    }
+
+    /// ファイルからDoubleArrayインスタンスを生成し、すべてのセクションのチェックサムを検証する。
+    /// ファイル全体を読むため `from_file` より遅い。
+    ///
+    /// # Arguments
+    ///
+    /// * `dictionary_path` - 辞書ファイルパス
+    pub fn from_file_verified(dictionary_path: &str) -> Result<Self, std::io::Error> {
+        let double_array = Self::from_file(dictionary_path)?;
+        double_array.verify()?;
+        Ok(double_array)
+    }
+
+    /// 複数の辞書を1つのダブル配列にまとめる
+    /// 構築時のオプションを指定する場合は `DoubleArrayBuilder::merge` を使う。
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - まとめる辞書
+    /// * `policy` - 同じキーに複数の辞書の値がある場合の扱い
+    pub fn merge(inputs: &[&DoubleArray<T>], policy: MergePolicy) -> Result<Self, std::io::Error> {
+        DoubleArrayBuilder::new().merge(inputs, policy)
+    }
+
+    /// すべてのセクションのチェックサムを検証する
+    pub fn verify(&self) -> Result<(), std::io::Error> {
+        self.header.verify(&self.mmap)
+    }
+
+    /// ルートから到達できるすべてのノードをたどり、ダブル配列の構造を検証する
+    /// base値の範囲、tail配列のレコード、dataの開始index、値のデシリアライズを確認する。
+    /// チェックサムは検証しないので、必要な場合は `verify` と合わせて使う。
+    pub fn verify_structure(&self) -> Result<(), std::io::Error> {
+        let units = Units::new(
+            self.layout(),
+            &self.mmap[self.header.range(SectionKind::Base)],
+            &self.mmap[self.header.range(SectionKind::Check)],
+        );
+        let tail_arr: &[u8] = &self.mmap[self.header.range(SectionKind::Tail)];
+        let data_arr = self.data_arr()?;
+        if units.len() <= TERMINAL_KEY as usize + 1 {
+            return Err(invalid_data("base/check arrays are too short"));
+        }
+
+        // 壊れた辞書で同じノードに2回遷移して終わらなくならないよう、たどったノードを記録する
+        let mut visited: Vec<bool> = vec![false; units.len()];
+        let mut stack: Vec<usize> = vec![1];
+        while let Some(idx) = stack.pop() {
+            if visited[idx] {
+                return Err(invalid_data(&format!("node {idx} is reachable from more than one parent")));
+            }
+            visited[idx] = true;
+            let base = units.base(idx);
+            if base & TAIL_FLAG != 0 {
+                let data_idx = check_tail(tail_arr, base)?;
+                data_arr.try_decode::<T>(data_idx)?;
+                continue;
+            }
+            // 構築時に末尾のノードから最大のkeyで遷移しても範囲外にならないようにしている
+            if base as usize + TERMINAL_KEY as usize >= units.len() {
+                return Err(invalid_data(&format!("base value of node {idx} is out of range")));
+            }
+            for label in (0..TERMINAL_KEY).filter(|&label| label != EMPTY_LABEL) {
+                let next = base as usize + label as usize;
+                if units.is_child(idx, next, label) {
+                    stack.push(next);
+                }
+            }
+            let value_idx = base as usize + TERMINAL_KEY as usize;
+            if units.is_child(idx, value_idx, TERMINAL_KEY) {
+                data_arr.try_decode::<T>(units.base(value_idx) as usize)?;
+            }
+        }
+        Ok(())
     }
 
     /// DoubleArrayをファイルにダンプする
@@ -147,142 +341,610 @@ impl<T: Serialize + DeserializeOwned + Debug> DoubleArray<T> {
     ///
     /// * `output_path` - 辞書ファイルパス
     pub fn dump(self, output_path: &str) -> Result<Self, std::io::Error> {
-        let file: File = OpenOptions::new().read(true).write(true).create(true).open(output_path)?;
+        let file: File = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(output_path)?;
         file.set_len(self.mmap.len() as u64)?;
         let mut new_mmap_mut = unsafe { MmapMut::map_mut(&file)? };
-        (&mut new_mmap_mut[..]).write_all(&self.mmap)?;
+        (&mut new_mmap_mut[..]).write_all(self.as_bytes())?;
         new_mmap_mut.flush()?;
         Self::from_file(output_path)
     }
 
-    /// mmapをパースして、base配列, check配列, data配列 を返す。
+    /// ダブル配列のバイト列
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.mmap
+    }
+
+    /// ダブル配列のレイアウト
+    pub fn layout(&self) -> Layout {
+        self.header.layout
+    }
+
+    /// 遷移のラベルの単位
+    pub fn alphabet(&self) -> Alphabet {
+        if self.code_table.is_some() { Alphabet::CodePoint } else { Alphabet::Byte }
+    }
+
+    /// 構築時と検索時にキーに適用する正規化。正規化しない辞書の場合はNone
+    pub fn normalizer(&self) -> Option<Normalizer> {
+        self.normalizer
+    }
+
+    /// 辞書のバイト列のハッシュ値 (xxHash64)
+    /// 同じ内容の辞書は環境に依らず同じ値になるので、ビルド成果物の識別に利用できる
+    pub fn content_hash(&self) -> u64 {
+        xxh64(self.as_bytes(), 0)
+    }
+
+    /// 辞書ファイルに格納されたメタデータを返す
+    /// base/check配列, data配列は読まない。
     ///
-    /// # Arguments
+    /// 構築時に設定したエントリに加えて、以下のエントリが格納されている。
+    /// 辞書を再現可能にするため、構築日時などの環境に依存する値は格納しない。
     ///
-    /// * `output_path` - 辞書ファイルパス
-    fn get_arrays(&self) -> (&[u32], &[u32], &[u8]) {
-        // base_arr
-        let base_arr: &[u32] = unsafe {
-            slice::from_raw_parts(
-                (&self.mmap)[self.header.base_idx..].as_ptr() as *const u32,
-                self.header.base_len
-            )
+    /// * `dary.version`    - 辞書を構築したクレートのバージョン
+    /// * `dary.key_count`  - キーの数
+    /// * `dary.value_type` - 値の型名
+    /// * `dary.schema_version` - 値のスキーマのバージョン (`DoubleArrayBuilder::schema_version` を指定した場合のみ)
+    /// * `dary.normalizer` - キーの正規化 (`DoubleArrayBuilder::normalizer` を指定した場合のみ)
+    ///
+    /// # Panics
+    /// メタデータのセクションが壊れている場合にpanicする。
+    pub fn metadata(&self) -> BTreeMap<String, String> {
+        self.read_metadata().unwrap()
+    }
+
+    /// メタデータのセクションをデシリアライズする
+    pub(crate) fn read_metadata(&self) -> Result<BTreeMap<String, String>, std::io::Error> {
+        let bytes = &self.mmap[self.header.range(SectionKind::Metadata)];
+        if bytes.is_empty() {
+            return Ok(BTreeMap::new());
+        }
+        bincode::deserialize(bytes).map_err(|_| invalid_data("invalid metadata section"))
+    }
+
+    /// 辞書の統計情報を返す
+    /// 構築時にのみ計測できる値 (find_base_time) はNoneになる
+    pub fn stats(&self) -> BuildStats {
+        let (units, tail_arr, data_arr) = self.get_arrays();
+        let mut node_count: usize = 1;
+        let mut key_count: usize = 0;
+        let mut value_count: usize = 0;
+        let mut count_values = |data_idx: usize| {
+            key_count += 1;
+            value_count += data_arr.value_len(data_idx);
         };
+        // 経路が一意に定まる場合はルートがtailになる
+        let root_base = units.base(1);
+        if root_base & TAIL_FLAG != 0 {
+            count_values(read_tail(tail_arr, root_base).1);
+        }
+        for i in 2..units.len() {
+            if !units.is_used(i) {
+                continue;
+            }
+            node_count += 1;
+            let base = units.base(i);
+            if units.label(i) == TERMINAL_KEY {
+                count_values(base as usize);
+            } else if base & TAIL_FLAG != 0 {
+                count_values(read_tail(tail_arr, base).1);
+            }
+        }
+        BuildStats {
+            node_count,
+            key_count,
+            value_count,
+            array_len     : units.len(),
+            fill_ratio    : node_count as f64 / units.len() as f64,
+            tail_size     : tail_arr.len(),
+            data_size     : data_arr.stored_len(),
+            find_base_time: None,
+        }
+    }
+
+    /// 圧縮されたdata配列について、展開したブロックを保持する数を変更する (デフォルトは16)
+    /// data配列が圧縮されていない場合は何もしない
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - 保持するブロックの数
+    pub fn set_block_cache_size(&self, capacity: usize) {
+        self.block_cache.lock().unwrap().set_capacity(capacity);
+    }
+
+    /// data配列への参照
+    fn data_arr(&self) -> Result<DataArr<'_>, std::io::Error> {
+        let data_arr: &[u8] = &self.mmap[self.header.range(SectionKind::Data)];
+        let data_index = self.header.range(SectionKind::DataIndex);
+        if data_index.is_empty() {
+            return Ok(DataArr::Plain(data_arr));
+        }
+        Ok(DataArr::Blocks(Blocks::new(&self.mmap[data_index], data_arr, &self.block_cache)?))
+    }
 
-        // check_arr
-        let check_arr: &[u32] = unsafe {
-            slice::from_raw_parts(
-                (&self.mmap)[self.header.check_idx..].as_ptr() as *const u32,
-                self.header.check_len
-            )
+    /// キーを正規化して遷移のラベルの列に変換する
+    /// 符号表にない文字を含むキーは辞書に存在しないので、Noneを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - キー
+    fn encode_key<'k>(&self, key: &'k str) -> Option<Cow<'k, [u8]>> {
+        let key: Cow<'k, str> = match self.normalizer.as_ref() {
+            Some(normalizer) => Cow::Owned(normalizer.normalize(key)),
+            None => Cow::Borrowed(key),
         };
+        match (self.code_table.as_ref(), key) {
+            (Some(code_table), key) => code_table.encode(&key).map(Cow::Owned),
+            (None, Cow::Borrowed(key)) => Some(Cow::Borrowed(key.as_bytes())),
+            (None, Cow::Owned(key)) => Some(Cow::Owned(key.into_bytes())),
+        }
+    }
+
+    /// 遷移のラベルの列をキーに変換する
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - 遷移のラベルの列
+    fn decode_key(&self, bytes: Vec<u8>) -> String {
+        // キーは &str として登録されているので UTF-8 として正しく、符号表にない文字も含まない
+        match self.code_table.as_ref() {
+            Some(code_table) => code_table.decode(&bytes).unwrap(),
+            None => String::from_utf8(bytes).unwrap(),
+        }
+    }
+
+    /// 共通接頭辞検索で探索するラベルの列
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - キー
+    fn search_key<'k>(&'k self, key: &'k str) -> SearchKey<'k> {
+        SearchKey::new(key, self.normalizer.as_ref(), self.code_table.as_ref())
+    }
+
+    /// mmapをパースして、base/check領域, tail配列, data配列 を返す。
+    fn get_arrays(&self) -> (Units<'_>, &[u8], DataArr<'_>) {
+        // base/check
+        let units = Units::new(
+            self.layout(),
+            &self.mmap[self.header.range(SectionKind::Base)],
+            &self.mmap[self.header.range(SectionKind::Check)],
+        );
+
+        // tail_arr
+        let tail_arr: &[u8] = &self.mmap[self.header.range(SectionKind::Tail)];
 
-        // data_arr
-        let data_arr: &[u8] = &self.mmap[self.header.data_idx..];
+        // data_arr (ブロックインデックスは生成時に検証済み)
+        let data_arr = self.data_arr().unwrap();
 
-        (base_arr, check_arr, data_arr)
+        (units, tail_arr, data_arr)
     }
 
     /// ダブル配列から指定されたkeyを探索する関数
     /// 途中で遷移できなくなった場合、data_arrに値が存在しない場合はNoneを返す
     /// 遷移ができて、data_arrに値が存在する場合はdata_arrのスライスを返す
-    /// デバッグ用
     ///
     /// # Arguments
     ///
     /// * `key`       - 探索対象の文字列
     pub fn get(&self, key: &str) -> Option<Vec<T>> {
-        let (base_arr, check_arr, data_arr) = self.get_arrays();
+        let (units, tail_arr, data_arr) = self.get_arrays();
+        let bytes = self.encode_key(key)?;
+        let bytes = &bytes[..];
 
         let mut idx  = 1;
-        let mut base = base_arr[idx] as usize;
-
-        for &byte in key.as_bytes() {
-            let next_idx = base + (byte as usize);
-            if  check_arr[next_idx] as usize != idx {
+        for (i, &byte) in bytes.iter().enumerate() {
+            let base = units.base(idx);
+            if base & TAIL_FLAG != 0 {
+                // 残りのキーとtailを比較する
+                let (suffix, data_idx) = read_tail(tail_arr, base);
+                return if suffix == &bytes[i..] { Some(data_arr.decode(data_idx)) } else { None };
+            }
+            let next_idx = (base as usize) + (byte as usize);
+            if  !units.is_child(idx, next_idx, byte) {
                 return None;
             }
             idx  = next_idx;
-            base = base_arr[idx] as usize;
         }
-        let value_idx = base + (u8::max_value() as usize);
-        if check_arr[value_idx] as usize == idx {
-            let data_idx = base_arr[value_idx] as usize;
-            let data: Vec<T> = bincode::deserialize(&data_arr[data_idx..]).unwrap();
-            Some(data)
+        let base = units.base(idx);
+        if base & TAIL_FLAG != 0 {
+            let (suffix, data_idx) = read_tail(tail_arr, base);
+            return if suffix.is_empty() { Some(data_arr.decode(data_idx)) } else { None };
+        }
+        let value_idx = (base as usize) + (TERMINAL_KEY as usize);
+        if units.is_child(idx, value_idx, TERMINAL_KEY) {
+            Some(data_arr.decode(units.base(value_idx) as usize))
         } else {
             None
         }
     }
 
     /// ダブル配列で共通接頭辞検索を行う
-    /// デバッグ用
+    /// 正規化する辞書では、一致した接頭辞を元の文字列の範囲で返す。
+    /// 正規化で1文字が複数の文字になる場合 (`㍻` → `平成` など)、その途中までの一致は返さない
     ///
     /// # Arguments
     ///
     /// * `key`       - 探索対象の文字列
     pub fn prefix_search<'a>(&self, key: &'a str) -> Vec<(&'a str, Vec<T>)> {
-        let (base_arr, check_arr, data_arr) = self.get_arrays();
+        let (_, _, data_arr) = self.get_arrays();
+        let mut search_key = self.search_key(key);
         let mut ret: Vec<(&str, Vec<T>)> = Vec::new();
-        let mut idx = 1;
-        let mut base = base_arr[idx] as usize;
+        let mut cursor = PrefixCursor::new();
+        while let Some((end, data_idx)) = self.next_prefix(&mut search_key, &mut cursor) {
+            ret.push((&key[0..end], data_arr.decode(data_idx)));
+        }
+        ret
+    }
 
-        for (i, &byte) in key.as_bytes().iter().enumerate() {
-            // 次のノードに遷移
-            let next_idx = base + (byte as usize);
-            if check_arr[next_idx] as usize != idx {
-                break;
+    /// keyの接頭辞になっている登録済みのキーのうち、最も長いものと値を返す
+    ///
+    /// # Arguments
+    ///
+    /// * `key`       - 探索対象の文字列
+    pub fn longest_prefix<'a>(&self, key: &'a str) -> Option<(&'a str, Vec<T>)> {
+        let (_, _, data_arr) = self.get_arrays();
+        let mut search_key = self.search_key(key);
+        let mut cursor = PrefixCursor::new();
+        let mut longest = None;
+        while let Some(found) = self.next_prefix(&mut search_key, &mut cursor) {
+            longest = Some(found);
+        }
+        // 値のデコードは最後に一致したキーだけ行う
+        longest.map(|(end, data_idx)| (&key[0..end], data_arr.decode(data_idx)))
+    }
+
+    /// ダブル配列で共通接頭辞検索を行うイテレータを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `key`       - 探索対象の文字列
+    pub fn prefix_search_iter<'a>(&'a self, key: &'a str) -> PrefixSearchIter<'a, T> {
+        PrefixSearchIter {
+            double_array: self,
+            key,
+            search_key: self.search_key(key),
+            cursor: PrefixCursor::new(),
+        }
+    }
+
+    /// すべてのキーと値をキーの辞書順(UTF-8のバイト列の昇順)に列挙するイテレータを返す
+    /// `Alphabet::CodePoint` の辞書でも、符号の順ではなくキーの辞書順に列挙する
+    pub fn iter(&self) -> Iter<'_, T> {
+        let (units, _, _) = self.get_arrays();
+        // ルートが使われていない(空の辞書)場合は何も列挙しない
+        let stack = if units.base(1) != 0 { vec![(1, Vec::new())] } else { Vec::new() };
+        Iter { double_array: self, stack }
+    }
+
+    /// prefixで始まるすべてのキーと値をキーの辞書順に返す (前方一致検索)
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - 探索対象の接頭辞
+    pub fn predictive_search(&self, prefix: &str) -> Vec<(String, Vec<T>)> {
+        self.predictive_search_iter(prefix).collect()
+    }
+
+    /// prefixで始まるすべてのキーと値をキーの辞書順に列挙するイテレータを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - 探索対象の接頭辞
+    pub fn predictive_search_iter(&self, prefix: &str) -> Iter<'_, T> {
+        let (units, tail_arr, _) = self.get_arrays();
+        let mut iter = Iter { double_array: self, stack: Vec::new() };
+        let bytes = match self.encode_key(prefix) {
+            Some(bytes) => bytes,
+            None => return iter,
+        };
+        let bytes = &bytes[..];
+        if units.base(1) == 0 {
+            return iter;
+        }
+        let mut idx = 1;
+        for (i, &byte) in bytes.iter().enumerate() {
+            let base = units.base(idx);
+            if base & TAIL_FLAG != 0 {
+                // 残りの接頭辞がtailの先頭と一致すれば、このノードのキーだけが候補になる
+                let (suffix, _) = read_tail(tail_arr, base);
+                if suffix.starts_with(&bytes[i..]) {
+                    iter.stack.push((idx, bytes[..i].to_vec()));
+                }
+                return iter;
+            }
+            let next_idx = (base as usize) + (byte as usize);
+            if !units.is_child(idx, next_idx, byte) {
+                return iter;
             }
             idx = next_idx;
-            base = base_arr[idx] as usize;
-            // value があれば戻り値の配列に追加
-            let value_idx = base + (u8::max_value() as usize);
-            if check_arr[value_idx] as usize == idx {
-                let data_idx = base_arr[value_idx] as usize;
-                let data: Vec<T> = bincode::deserialize(&data_arr[data_idx..]).unwrap();
-                ret.push((&key[0..(i + 1)], data));
+        }
+        iter.stack.push((idx, bytes.to_vec()));
+        iter
+    }
+
+    /// 辞書順の列挙を1ステップ進め、次に見つかったキーとdataの開始indexを返す
+    /// 深さ優先で探索し、子ノードより先に自身の値を返すことで辞書順になる
+    ///
+    /// # Arguments
+    ///
+    /// * `stack` - 未探索のノードのindexと、そのノードまでのキー
+    fn next_entry(&self, stack: &mut Vec<(usize, Vec<u8>)>) -> Option<(Vec<u8>, usize)> {
+        let (units, tail_arr, _) = self.get_arrays();
+        while let Some((idx, mut key)) = stack.pop() {
+            let base = units.base(idx);
+            if base & TAIL_FLAG != 0 {
+                let (suffix, data_idx) = read_tail(tail_arr, base);
+                key.extend_from_slice(suffix);
+                return Some((key, data_idx));
+            }
+            // 後から取り出されるように、ラベルの降順に積む
+            // EMPTY_LABEL は UTF-8 に現れないので遷移のラベルにならない
+            if let Some(code_table) = self.code_table.as_ref() {
+                // 符号の順はキーの辞書順と異なるので、1文字分の遷移の先を文字の降順に積む
+                let mut children: Vec<(char, usize, Vec<u8>)> = Vec::new();
+                self.collect_chars(code_table, idx, &mut Vec::new(), &mut children);
+                children.sort_by_key(|&(c, _, _)| Reverse(c));
+                for (_, next, code) in children {
+                    let mut next_key = key.clone();
+                    next_key.extend_from_slice(&code);
+                    stack.push((next, next_key));
+                }
+            } else {
+                for label in (0..TERMINAL_KEY).rev().filter(|&label| label != EMPTY_LABEL) {
+                    let next = base as usize + label as usize;
+                    if units.is_child(idx, next, label) {
+                        let mut next_key = key.clone();
+                        next_key.push(label);
+                        stack.push((next, next_key));
+                    }
+                }
+            }
+            let value_idx = base as usize + TERMINAL_KEY as usize;
+            if units.is_child(idx, value_idx, TERMINAL_KEY) {
+                return Some((key, units.base(value_idx) as usize));
             }
         }
-        ret
+        None
     }
 
-    pub fn prefix_search_iter<'a>(&'a self, key: &'a str) -> PrefixSearchIter<'a, T> {
-        let (base_arr, check_arr, data_arr) = self.get_arrays();
-        PrefixSearchIter {
-            key_ptr: 0,
-            key: key,
-            arr_ptr: 1,
-            base_arr: base_arr,
-            check_arr: check_arr,
-            data_arr: data_arr,
-            phantom: PhantomData,
+    /// 文字の境界のノードから1文字分の符号で遷移できるノードを、(文字, ノードのindex, 符号) として集める
+    /// 符号の途中でtailになった場合は、tailの先頭から文字を求める
+    ///
+    /// # Arguments
+    ///
+    /// * `code_table` - 文字の符号表
+    /// * `idx`        - 遷移元のノードのindex
+    /// * `code`       - 文字の境界のノードからidxまでの符号
+    /// * `children`   - 集めたノード
+    fn collect_chars(&self, code_table: &CodeTable, idx: usize, code: &mut Vec<u8>, children: &mut Vec<(char, usize, Vec<u8>)>) {
+        let (units, tail_arr, _) = self.get_arrays();
+        let base = units.base(idx) as usize;
+        for label in 0..EMPTY_LABEL {
+            let next = base + label as usize;
+            if !units.is_child(idx, next, label) {
+                continue;
+            }
+            code.push(label);
+            let len = CodeTable::code_len(code[0]);
+            let next_base = units.base(next);
+            if code.len() == len {
+                children.push((code_table.decode_char(code).unwrap(), next, code.clone()));
+            } else if next_base & TAIL_FLAG != 0 {
+                let (suffix, _) = read_tail(tail_arr, next_base);
+                let mut full = code.clone();
+                full.extend_from_slice(&suffix[..(len - code.len())]);
+                children.push((code_table.decode_char(&full).unwrap(), next, code.clone()));
+            } else {
+                self.collect_chars(code_table, next, code, children);
+            }
+            code.pop();
         }
     }
 
+    /// 共通接頭辞検索を1ステップ進め、次に見つかった接頭辞の元の文字列での長さとdataの開始indexを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `key`    - 探索対象のラベルの列
+    /// * `cursor` - 探索状態
+    fn next_prefix(&self, key: &mut SearchKey, cursor: &mut PrefixCursor) -> Option<(usize, usize)> {
+        let (units, tail_arr, _) = self.get_arrays();
+        while !cursor.done {
+            let base = units.base(cursor.arr_ptr);
+            if base & TAIL_FLAG != 0 {
+                // tail以降は分岐しないので、残りのキーがtailで始まる場合のみ値を返して終了
+                cursor.done = true;
+                let (suffix, data_idx) = read_tail(tail_arr, base);
+                let end = cursor.key_ptr + suffix.len();
+                if end > 0 && key.ensure(end) && key.bytes()[cursor.key_ptr..].starts_with(suffix) {
+                    return key.original_end(end).map(|end| (end, data_idx));
+                }
+                return None;
+            }
+            if !key.ensure(cursor.key_ptr + 1) {
+                cursor.done = true;
+                return None;
+            }
+
+            // 次のノードに遷移
+            let byte = key.bytes()[cursor.key_ptr];
+            let next_arr_ptr = (base as usize) + (byte as usize);
+            cursor.key_ptr += 1;
+            if !units.is_child(cursor.arr_ptr, next_arr_ptr, byte) {
+                cursor.done = true;
+                return None;
+            }
+            cursor.arr_ptr = next_arr_ptr;
+
+            // 遷移先がtailの場合は次のループで処理する
+            let base = units.base(cursor.arr_ptr);
+            if base & TAIL_FLAG != 0 {
+                continue;
+            }
+            // value があれば返す。元の文字列の文字の途中に当たる場合は返さない
+            let value_idx = (base as usize) + (TERMINAL_KEY as usize);
+            if units.is_child(cursor.arr_ptr, value_idx, TERMINAL_KEY) {
+                if let Some(end) = key.original_end(cursor.key_ptr) {
+                    return Some((end, units.base(value_idx) as usize));
+                }
+            }
+        }
+        None
+    }
 
     /// ダブル配列をデバッグ目的で表示するための関数
     #[allow(dead_code)]
     fn debug_double_array(&self, mut len: usize) {
-        let (base_arr, check_arr, data_arr) = self.get_arrays();
-        println!("size: base={}, check={}, data={}", base_arr.len(), check_arr.len(), data_arr.len());
-        println!("{:-10} | {:-10} | {:-10} | {:-10}", "index", "base", "check", "data");
-        println!("{:-10} | {:-10} | {:-10} |", 0, base_arr[0], check_arr[0]);
-        println!("{:-10} | {:-10} | {:-10} |", 1, base_arr[1], check_arr[1]);
+        let (units, tail_arr, data_arr) = self.get_arrays();
+        println!("layout: {:?}", self.layout());
+        println!("size: units={}, tail={}, data={}", units.len(), tail_arr.len(), data_arr.stored_len());
+        println!("{:-10} | {:-10} | {:-10}", "index", "base", "data");
+        println!("{:-10} | {:-10} |", 1, units.base(1));
 
-        len = if len < base_arr.len() { len } else { base_arr.len() };
+        len = if len < units.len() { len } else { units.len() };
         for i in 2..len {
-            let check = check_arr[i] as usize;
-            let base  = base_arr[i] as usize;
-            if  check != 0 {
-                if (base_arr[check] as usize) + (u8::max_value() as usize) == i {
-                    // 遷移前のbase値と255を足した値が現在のインデックスと等しいとき、dataが存在する
-                    let data: Vec<T> = bincode::deserialize(&data_arr[base..]).unwrap();
-                    println!( "{:-10} | {:-10} | {:-10} | {:?}", i, base, check, data);
-                } else {
-                    println!( "{:-10} | {:-10} | {:-10} |", i, base, check);
-                }
+            if !units.is_used(i) {
+                continue;
+            }
+            let base = units.base(i);
+            if base & TAIL_FLAG != 0 {
+                // tailが存在する
+                let (suffix, data_idx) = read_tail(tail_arr, base);
+                let data: Vec<T> = data_arr.decode(data_idx);
+                println!( "{:-10} | {:-10} | {:?} {:?}", i, "tail", String::from_utf8_lossy(suffix), data);
+            } else {
+                println!( "{i:-10} | {base:-10} |");
+            }
+        }
+    }
+}
+
+/// tail配列のレコードが配列の範囲に収まっているかを検証し、dataの開始indexを返す
+///
+/// # Arguments
+///
+/// * `tail_arr` - tail配列
+/// * `base`     - TAIL_FLAGが立ったbase値
+fn check_tail(tail_arr: &[u8], base: u32) -> Result<usize, std::io::Error> {
+    let pos = (base & !TAIL_FLAG) as usize;
+    let record = tail_arr.get(pos..).filter(|record| !record.is_empty())
+        .ok_or_else(|| invalid_data(&format!("tail record {pos} is out of range")))?;
+    let (len, n) = read_varint(record);
+    match n.checked_add(len).and_then(|end| end.checked_add(4)) {
+        Some(end) if end <= record.len() => Ok(read_tail(tail_arr, base).1),
+        _ => Err(invalid_data(&format!("tail record {pos} is out of range"))),
+    }
+}
+
+/// tail配列のレコードを読み取り、接尾辞とdataの開始indexを返す
+///
+/// # Arguments
+///
+/// * `tail_arr` - tail配列
+/// * `base`     - TAIL_FLAGが立ったbase値
+fn read_tail(tail_arr: &[u8], base: u32) -> (&[u8], usize) {
+    let pos = (base & !TAIL_FLAG) as usize;
+    let (len, n) = read_varint(&tail_arr[pos..]);
+    let suffix_idx = pos + n;
+    let data_idx_bytes = &tail_arr[(suffix_idx + len)..(suffix_idx + len + 4)];
+    let data_idx = u32::from_le_bytes([data_idx_bytes[0], data_idx_bytes[1], data_idx_bytes[2], data_idx_bytes[3]]);
+    (&tail_arr[suffix_idx..(suffix_idx + len)], data_idx as usize)
+}
+
+/// 共通接頭辞検索で探索するラベルの列
+/// 正規化や符号表による変換が必要な場合は、探索が進んだ分だけ元の文字列を変換する
+struct SearchKey<'a> {
+    /// 元の文字列
+    source    : &'a str,
+    /// 変換済みの元の文字列のバイト数
+    consumed  : usize,
+    /// 変換済みのラベルの列
+    bytes     : Cow<'a, [u8]>,
+    /// ラベルの位置から元の文字列のバイト位置を引く表。長さはラベルの数 + 1
+    /// 元の文字列の文字の途中に当たる位置はNone。変換しない場合は表を持たない
+    offsets   : Option<Vec<Option<usize>>>,
+    normalizer: Option<&'a Normalizer>,
+    code_table: Option<&'a CodeTable>,
+}
+
+impl<'a> SearchKey<'a> {
+    fn new(source: &'a str, normalizer: Option<&'a Normalizer>, code_table: Option<&'a CodeTable>) -> Self {
+        if normalizer.is_none() && code_table.is_none() {
+            return SearchKey { source, consumed: source.len(), bytes: Cow::Borrowed(source.as_bytes()), offsets: None, normalizer, code_table };
+        }
+        SearchKey { source, consumed: 0, bytes: Cow::Owned(Vec::new()), offsets: Some(vec![Some(0)]), normalizer, code_table }
+    }
+
+    /// ラベルがlen個以上になるまで元の文字列を変換する。len個に満たない場合はfalseを返す
+    /// 符号表にない文字以降は辞書に一致しないので変換しない
+    ///
+    /// # Arguments
+    ///
+    /// * `len` - 必要なラベルの数
+    fn ensure(&mut self, len: usize) -> bool {
+        while self.bytes.len() < len && self.consumed < self.source.len() {
+            // 正規化は前後と独立に正規化できる区間ごとに行う
+            let end = match self.normalizer {
+                Some(normalizer) => normalizer.next_segment(self.source, self.consumed),
+                None => self.consumed + self.source[self.consumed..].chars().next().unwrap().len_utf8(),
+            };
+            let segment: Cow<str> = match self.normalizer {
+                Some(normalizer) => Cow::Owned(normalizer.normalize(&self.source[self.consumed..end])),
+                None => Cow::Borrowed(&self.source[self.consumed..end]),
+            };
+            let encoded: Vec<u8> = match self.code_table {
+                Some(code_table) => {
+                    let mut encoded: Vec<u8> = Vec::new();
+                    if !segment.chars().all(|c| code_table.encode_char(c, &mut encoded)) {
+                        self.consumed = self.source.len();
+                        break;
+                    }
+                    encoded
+                },
+                None => segment.as_bytes().to_vec(),
+            };
+            self.consumed = end;
+            if encoded.is_empty() {
+                continue;
             }
+            let bytes = self.bytes.to_mut();
+            let offsets = self.offsets.as_mut().unwrap();
+            bytes.extend_from_slice(&encoded);
+            offsets.resize(bytes.len(), None);
+            offsets.push(Some(end));
         }
+        self.bytes.len() >= len
+    }
+
+    /// 変換済みのラベルの列
+    fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// ラベルの位置に対応する元の文字列のバイト位置。文字の途中に当たる場合はNone
+    ///
+    /// # Arguments
+    ///
+    /// * `end` - ラベルの位置
+    fn original_end(&self, end: usize) -> Option<usize> {
+        match self.offsets.as_ref() {
+            Some(offsets) => offsets[end],
+            None => Some(end),
+        }
+    }
+}
+
+/// 共通接頭辞検索の探索状態
+struct PrefixCursor {
+    key_ptr: usize,
+    arr_ptr: usize,
+    done   : bool,
+}
+
+impl PrefixCursor {
+    fn new() -> Self {
+        PrefixCursor { key_ptr: 0, arr_ptr: 1, done: false }
     }
 }
 
@@ -290,13 +952,10 @@ use std::iter::Iterator;
 pub struct PrefixSearchIter<'a, T>
     where T: Serialize + DeserializeOwned + Debug,
 {
-    key_ptr  : usize,
-    key      : &'a str,
-    arr_ptr  : usize,
-    base_arr : &'a [u32],
-    check_arr: &'a [u32],
-    data_arr : &'a [u8],
-    phantom: PhantomData<T>,
+    double_array: &'a DoubleArray<T>,
+    key         : &'a str,
+    search_key  : SearchKey<'a>,
+    cursor      : PrefixCursor,
 }
 
 impl<'a, T> Iterator for PrefixSearchIter<'a, T>
@@ -305,25 +964,48 @@ impl<'a, T> Iterator for PrefixSearchIter<'a, T>
     type Item =  (&'a str, Vec<T>);
 
     fn next(&mut self) -> Option<(&'a str, Vec<T>)> {
-        let mut base = self.base_arr[self.arr_ptr] as usize;
+        let (prefix, data_idx) = self.next_match()?;
+        Some((prefix, self.decode(data_idx)))
+    }
+}
 
-        while self.key_ptr < self.key.len() {
-            let next_arr_ptr = base + (self.key.as_bytes()[self.key_ptr] as usize);
-            self.key_ptr += 1;
-            if self.check_arr[next_arr_ptr] as usize != self.arr_ptr {
-                return None;
-            }
-            self.arr_ptr = next_arr_ptr;
-            base = self.base_arr[self.arr_ptr] as usize;
-
-            let value_idx = base + (u8::max_value() as usize);
-            if self.check_arr[value_idx] as usize == self.arr_ptr {
-                let data_idx = self.base_arr[value_idx] as usize;
-                let data: Vec<T> = bincode::deserialize(&self.data_arr[data_idx..]).unwrap();
-                return Some((&self.key[0..self.key_ptr], data));
-            }
-        }
-        None
+impl<'a, T> PrefixSearchIter<'a, T>
+    where T: Serialize + DeserializeOwned + Debug,
+{
+    /// 次に一致した接頭辞とdataの開始indexを返す。値はデシリアライズしない
+    pub(crate) fn next_match(&mut self) -> Option<(&'a str, usize)> {
+        let (end, data_idx) = self.double_array.next_prefix(&mut self.search_key, &mut self.cursor)?;
+        Some((&self.key[0..end], data_idx))
+    }
+
+    /// `next_match` で得たdataの開始indexから値をデシリアライズする
+    ///
+    /// # Arguments
+    ///
+    /// * `data_idx` - dataの開始index
+    pub(crate) fn decode(&self, data_idx: usize) -> Vec<T> {
+        let (_, _, data_arr) = self.double_array.get_arrays();
+        data_arr.decode(data_idx)
+    }
+}
+
+/// すべてのキーと値をキーの辞書順に列挙するイテレータ
+pub struct Iter<'a, T>
+    where T: Serialize + DeserializeOwned + Debug,
+{
+    double_array: &'a DoubleArray<T>,
+    stack       : Vec<(usize, Vec<u8>)>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T>
+    where T: Serialize + DeserializeOwned + Debug,
+{
+    type Item = (String, Vec<T>);
+
+    fn next(&mut self) -> Option<(String, Vec<T>)> {
+        let (key, data_idx) = self.double_array.next_entry(&mut self.stack)?;
+        let (_, _, data_arr) = self.double_array.get_arrays();
+        Some((self.double_array.decode_key(key), data_arr.decode(data_idx)))
     }
 }
 
@@ -344,7 +1026,7 @@ mod tests {
         fn new(surface: &str, cost: i32) -> Self {
             MorphemeData {
                 surface: surface.to_string(),
-                cost: cost
+                cost,
             }
         }
     }
@@ -355,10 +1037,19 @@ mod tests {
         let check_arr: Vec<u32> = vec![10,20,30,40,50];
         let data_arr: Vec<u8> = vec![100,110,120,130,140];
         let double_array: DoubleArray<u32> = DoubleArray::from_arrays(&base_arr, &check_arr, &data_arr).ok().unwrap();
-        let (base_arr, check_arr, data_arr) = double_array.get_arrays();
-        assert_eq!([1,2,3,4,5]          , base_arr);
-        assert_eq!([10,20,30,40,50]     , check_arr);
-        assert_eq!([100,110,120,130,140], data_arr);
+        let (units, tail_arr, data_arr) = double_array.get_arrays();
+        match units {
+            Units::Separate { base, check } => {
+                assert_eq!([1,2,3,4,5]          , base);
+                assert_eq!([10,20,30,40,50]     , check);
+            },
+            _ => panic!("layout must be Separate"),
+        }
+        assert!(tail_arr.is_empty());
+        match data_arr {
+            DataArr::Plain(data_arr) => assert_eq!([100,110,120,130,140], data_arr),
+            _ => panic!("data must not be compressed"),
+        }
     }
 
     #[test]
@@ -477,4 +1168,243 @@ mod tests {
         assert_eq!(("鳴らし初め"  , vec![4]) , result[1]);
         assert_eq!(("鳴らし初めよ", vec![5]) , result[2]);
     }
+
+    #[test]
+    fn test_prefix_search_3() {
+        // tail圧縮した場合
+        let mut trie: Trie<u32> = Trie::new();
+        let s1 = String::from("鳴ら");
+        let s2 = String::from("鳴らしゃ");
+        let s3 = String::from("鳴らし初め");
+        let s4 = String::from("鳴らし初めよ");
+        trie.set(&s1, 1);
+        trie.set(&s1, 2);
+        trie.set(&s2, 3);
+        trie.set(&s3, 4);
+        trie.set(&s4, 5);
+        let double_array = DoubleArrayBuilder::new().tail(true).build(trie).unwrap();
+        let key = String::from("鳴らし初めよ");
+        let result: Vec<(&str, Vec<u32>)> = double_array.prefix_search_iter(&key).collect();
+        assert_eq!(("鳴ら"       , vec![1, 2]), result[0]);
+        assert_eq!(("鳴らし初め"  , vec![4]) , result[1]);
+        assert_eq!(("鳴らし初めよ", vec![5]) , result[2]);
+        assert_eq!(result, double_array.prefix_search(&key));
+        // tailの途中で終わるキー
+        let result = double_array.prefix_search("鳴らしゃ");
+        assert_eq!(vec![("鳴ら", vec![1, 2]), ("鳴らしゃ", vec![3])], result);
+        let result = double_array.prefix_search("鳴らし初");
+        assert_eq!(vec![("鳴ら", vec![1, 2])], result);
+    }
+
+    #[test]
+    fn test_longest_prefix() {
+        for &tail in &[false, true] {
+            let trie: Trie<u32> = vec![("鳴ら", 1), ("鳴ら", 2), ("鳴らしゃ", 3), ("鳴らし初め", 4)].into_iter().collect();
+            let double_array = DoubleArrayBuilder::new().tail(tail).build(trie).unwrap();
+            assert_eq!(Some(("鳴らし初め", vec![4])), double_array.longest_prefix("鳴らし初めよ"));
+            assert_eq!(Some(("鳴ら", vec![1, 2])), double_array.longest_prefix("鳴らし"));
+            assert_eq!(None, double_array.longest_prefix("鳴"));
+        }
+    }
+
+    #[test]
+    fn test_predictive_search() {
+        for &tail in &[false, true] {
+            let mut trie: Trie<u32> = Trie::new();
+            let keys = ["鳴ら", "鳴らしゃ", "鳴らし初め", "鳴らし初めよ", "鳴る", "abc"];
+            for (i, key) in keys.iter().enumerate() {
+                trie.set(key, i as u32);
+            }
+            let double_array = DoubleArrayBuilder::new().tail(tail).build(trie).unwrap();
+            let result = double_array.predictive_search("鳴らし");
+            assert_eq!(vec![
+                (String::from("鳴らしゃ")    , vec![1]),
+                (String::from("鳴らし初め")  , vec![2]),
+                (String::from("鳴らし初めよ"), vec![3]),
+            ], result);
+            assert_eq!(5, double_array.predictive_search("鳴").len());
+            assert_eq!(6, double_array.predictive_search("").len());
+            // tailの途中まで一致する
+            assert_eq!(vec![(String::from("abc"), vec![5])], double_array.predictive_search("ab"));
+            assert_eq!(0, double_array.predictive_search("abd").len());
+            assert_eq!(0, double_array.predictive_search("abcd").len());
+            assert_eq!(0, double_array.predictive_search("x").len());
+        }
+    }
+
+    #[test]
+    fn test_iter() {
+        for &layout in &[Layout::Separate, Layout::Interleaved, Layout::Compact] {
+            for &tail in &[false, true] {
+                let mut trie: Trie<u32> = Trie::new();
+                let keys = ["bdc", "abc", "", "b", "ac", "bd", "合沢", "abcdef"];
+                for (i, key) in keys.iter().enumerate() {
+                    trie.set(key, i as u32);
+                }
+                trie.set("abc", 10);
+                let double_array = DoubleArrayBuilder::new().layout(layout).tail(tail).build(trie).unwrap();
+                let entries: Vec<(String, Vec<u32>)> = double_array.iter().collect();
+                let expected: Vec<(String, Vec<u32>)> = vec![
+                    (String::from("")      , vec![2]),
+                    (String::from("abc")   , vec![1, 10]),
+                    (String::from("abcdef"), vec![7]),
+                    (String::from("ac")    , vec![4]),
+                    (String::from("b")     , vec![3]),
+                    (String::from("bd")    , vec![5]),
+                    (String::from("bdc")   , vec![0]),
+                    (String::from("合沢")  , vec![6]),
+                ];
+                assert_eq!(expected, entries);
+            }
+        }
+        // 空の辞書
+        let double_array = Trie::<u32>::new().to_double_array().unwrap();
+        assert_eq!(0, double_array.iter().count());
+    }
+
+    #[test]
+    fn test_code_point_alphabet() {
+        // 2バイト以上の符号が必要になるように、500種類以上の文字を使う
+        let mut keys: Vec<String> = vec![String::new(), String::from("abc"), String::from("すもも"), String::from("すももも")];
+        let mut seed: u32 = 1;
+        for _ in 0..2000 {
+            let len = 1 + seed as usize % 4;
+            let key: String = (0..len).map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                char::from_u32(0x4E00 + (seed >> 16) % 600).unwrap()
+            }).collect();
+            keys.push(key);
+        }
+        for &layout in &[Layout::Separate, Layout::Interleaved, Layout::Compact] {
+            for &tail in &[false, true] {
+                let build = |alphabet: Alphabet| {
+                    let mut trie: Trie<u32> = Trie::new();
+                    for (i, key) in keys.iter().enumerate() {
+                        trie.set(key, i as u32);
+                    }
+                    DoubleArrayBuilder::new().layout(layout).tail(tail).alphabet(alphabet).build(trie).unwrap()
+                };
+                let bytes = build(Alphabet::Byte);
+                let code_points = build(Alphabet::CodePoint);
+                assert_eq!(Alphabet::CodePoint, code_points.alphabet());
+                assert!(code_points.stats().node_count < bytes.stats().node_count);
+
+                // キーの辞書順に列挙する
+                let entries: Vec<(String, Vec<u32>)> = code_points.iter().collect();
+                assert_eq!(bytes.iter().collect::<Vec<(String, Vec<u32>)>>(), entries);
+                for key in keys.iter() {
+                    assert_eq!(bytes.get(key), code_points.get(key));
+                    assert_eq!(bytes.prefix_search(key), code_points.prefix_search(key));
+                    let prefix: String = key.chars().take(1).collect();
+                    assert_eq!(bytes.predictive_search(&prefix), code_points.predictive_search(&prefix));
+                }
+                // 符号表にない文字
+                assert_eq!(None, code_points.get("x"));
+                assert_eq!(0, code_points.predictive_search("すx").len());
+                let result: Vec<&str> = code_points.prefix_search_iter("すももxも").map(|(prefix, _)| prefix).collect();
+                assert_eq!(vec!["すもも"], result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_structure() {
+        let keys = ["abc", "ab", "abcdefg", "b", "bcd", "bce", "合沢", "会沢"];
+        let build = |builder: DoubleArrayBuilder| -> DoubleArray<u32> {
+            let mut trie: Trie<u32> = Trie::new();
+            for (i, key) in keys.iter().enumerate() {
+                trie.set(key, i as u32);
+            }
+            builder.build(trie).unwrap()
+        };
+        for &layout in &[Layout::Separate, Layout::Interleaved, Layout::Compact] {
+            for &tail in &[false, true] {
+                for &alphabet in &[Alphabet::Byte, Alphabet::CodePoint] {
+                    let builder = DoubleArrayBuilder::new().layout(layout).tail(tail).alphabet(alphabet);
+                    assert!(build(builder.clone()).verify_structure().is_ok());
+                    assert!(build(builder.compress_data(true).data_block_size(8)).verify_structure().is_ok());
+                }
+            }
+        }
+
+        // 壊れた配列から生成した辞書は検証に失敗する
+        let double_array = build(DoubleArrayBuilder::new().tail(true));
+        let (units, tail_arr, data_arr) = double_array.get_arrays();
+        let (base_arr, check_arr) = match units {
+            Units::Separate { base, check } => (base.to_vec(), check.to_vec()),
+            _ => panic!("layout must be Separate"),
+        };
+        let data_arr = match data_arr {
+            DataArr::Plain(data_arr) => data_arr.to_vec(),
+            _ => panic!("data must not be compressed"),
+        };
+        let metadata = BTreeMap::new();
+        let verify = |base_arr: &[u32], tail_arr: &[u8], data_arr: &[u8]| {
+            DoubleArray::<u32>::from_sections(Layout::Separate, base_arr, &check_arr, tail_arr, data_arr, &[], None, &metadata)
+                .unwrap()
+                .verify_structure()
+        };
+        assert!(verify(&base_arr, tail_arr, &data_arr).is_ok());
+        // tail配列のレコードが途中で切れている
+        assert_eq!(std::io::ErrorKind::InvalidData, verify(&base_arr, &tail_arr[..(tail_arr.len() - 1)], &data_arr).unwrap_err().kind());
+        // 値が途中で切れている
+        assert_eq!(std::io::ErrorKind::InvalidData, verify(&base_arr, tail_arr, &data_arr[..(data_arr.len() - 1)]).unwrap_err().kind());
+        // base値が配列の範囲外
+        let mut broken = base_arr.clone();
+        broken[1] = base_arr.len() as u32;
+        assert_eq!(std::io::ErrorKind::InvalidData, verify(&broken, tail_arr, &data_arr).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_search_key() {
+        let table = CodeTable::from_keys(vec!["すもも", "もも", "a"]);
+        let mut search_key = SearchKey::new("ももうa", None, Some(&table));
+        // 符号表にない文字以降は変換しない
+        assert!(!search_key.ensure(3));
+        assert_eq!(&[0, 0], search_key.bytes());
+        assert_eq!(vec![Some(0), Some(3), Some(6)], (0..3).map(|end| search_key.original_end(end)).collect::<Vec<_>>());
+
+        // 変換しない場合はラベルの位置がそのまま元の文字列の位置になる
+        let mut search_key = SearchKey::new("もも", None, None);
+        assert!(search_key.ensure(6));
+        assert_eq!(Some(4), search_key.original_end(4));
+    }
+
+    #[test]
+    fn test_normalizer() {
+        let normalizer = Normalizer::new().nfkc(true).case_fold(true).kana_fold(true);
+        for &alphabet in &[Alphabet::Byte, Alphabet::CodePoint] {
+            for &tail in &[false, true] {
+                let mut trie: Trie<u32> = Trie::new();
+                trie.set("ガス", 1);
+                trie.set("ｶﾞｽ管", 2);
+                trie.set("TOKYO", 3);
+                trie.set("平", 4);
+                trie.set("平成", 5);
+                trie.set("がす", 6);
+                let double_array = DoubleArrayBuilder::new().alphabet(alphabet).tail(tail).normalizer(normalizer).build(trie).unwrap();
+                assert_eq!(Some(normalizer), double_array.normalizer());
+                assert_eq!("nfkc,case_fold,kana_fold", double_array.metadata()["dary.normalizer"]);
+
+                // 正規化で同じキーになった値は元のキーの辞書順に連結する
+                assert_eq!(vec![6, 1], double_array.get("ガス").unwrap());
+                assert_eq!(vec![6, 1], double_array.get("ｶﾞｽ").unwrap());
+                assert_eq!(vec![3], double_array.get("Ｔｏｋｙｏ").unwrap());
+                assert_eq!(None, double_array.get("ｶ"));
+
+                // 一致した長さは元の文字列のバイト位置に戻す
+                let result = double_array.prefix_search("ｶﾞｽ管理");
+                assert_eq!(vec![("ｶﾞｽ", vec![6, 1]), ("ｶﾞｽ管", vec![2])], result);
+                let result: Vec<&str> = double_array.prefix_search_iter("ＴＯＫＹＯ都").map(|(prefix, _)| prefix).collect();
+                assert_eq!(vec!["ＴＯＫＹＯ"], result);
+                // ㍻ は 平成 になるので、平 だけの一致は返さない
+                let result: Vec<&str> = double_array.prefix_search_iter("㍻").map(|(prefix, _)| prefix).collect();
+                assert_eq!(vec!["㍻"], result);
+
+                // 列挙するキーは正規化したキー
+                assert_eq!(vec![(String::from("がす"), vec![6, 1]), (String::from("がす管"), vec![2])], double_array.predictive_search("ガ"));
+                assert_eq!(5, double_array.iter().count());
+            }
+        }
+    }
 }
@@ -0,0 +1,338 @@
+use std::io;
+use std::ops::Range;
+
+use crate::layout::Layout;
+
+use xxhash_rust::xxh64::xxh64;
+
+/// 辞書ファイルの先頭に置く識別子
+pub const MAGIC: &[u8; 4] = b"DARY";
+
+/// 辞書ファイルの形式のバージョン
+pub const FORMAT_VERSION: u32 = 2;
+
+/// 辞書を構築したクレートのバージョンを表すメタデータのキー
+pub const METADATA_VERSION: &str = "dary.version";
+
+/// キーの数を表すメタデータのキー
+pub const METADATA_KEY_COUNT: &str = "dary.key_count";
+
+/// 値の型名を表すメタデータのキー
+pub const METADATA_VALUE_TYPE: &str = "dary.value_type";
+
+/// 値のスキーマのバージョンを表すメタデータのキー
+pub const METADATA_SCHEMA_VERSION: &str = "dary.schema_version";
+
+/// キーの正規化を表すメタデータのキー
+pub const METADATA_NORMALIZER: &str = "dary.normalizer";
+
+/// 識別子を持たない旧形式の辞書ファイルの、ヘッダのバイト数
+/// 旧形式はヘッダ (usizeのフィールド5〜8個) をそのまま書き込んでおり、先頭のフィールドがbase配列の開始位置 = ヘッダのバイト数になる
+const LEGACY_HEADER_SIZES: [u64; 3] = [40, 56, 64];
+
+/// ヘッダの固定長部分のバイト数 (識別子, バージョン, レイアウト, セクション数, ヘッダのチェックサム)
+const FIXED_SIZE: usize = 24;
+
+/// ヘッダのチェックサムの位置
+const HEADER_CHECKSUM_POS: usize = 16;
+
+/// セクションテーブルの1エントリのバイト数 (種類, 予約領域, 開始位置, 長さ, チェックサム)
+const ENTRY_SIZE: usize = 32;
+
+/// セクションの開始位置を揃える境界
+const ALIGN: usize = 8;
+
+/// セクションの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SectionKind {
+    /// base配列 (Interleavedレイアウトでは (base, check) のユニット)
+    Base,
+    /// check配列 (Compactレイアウトではラベル)
+    Check,
+    /// tail配列
+    Tail,
+    /// data配列
+    Data,
+    /// メタデータ
+    Metadata,
+    /// 圧縮したdata配列のブロックインデックス
+    DataIndex,
+    /// 文字の符号表 (`Alphabet::CodePoint` の場合のみ)
+    CodeTable,
+}
+
+impl SectionKind {
+    /// セクションテーブルに格納するID
+    fn id(self) -> u32 {
+        match self {
+            SectionKind::Base      => 1,
+            SectionKind::Check     => 2,
+            SectionKind::Tail      => 3,
+            SectionKind::Data      => 4,
+            SectionKind::Metadata  => 5,
+            SectionKind::DataIndex => 6,
+            SectionKind::CodeTable => 7,
+        }
+    }
+}
+
+/// セクションの配置
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Section {
+    kind    : u32,
+    offset  : usize,
+    len     : usize,
+    checksum: u64,
+}
+
+/// 辞書ファイルのヘッダ
+/// 整数はすべてリトルエンディアンで格納し、ビルド環境に依存する値は持たない。
+///
+/// | 位置         | バイト数 | 内容                                              |
+/// |--------------|----------|---------------------------------------------------|
+/// | 0            | 4        | 識別子 `DARY`                                     |
+/// | 4            | 4        | 形式のバージョン                                  |
+/// | 8            | 4        | レイアウトのID                                    |
+/// | 12           | 4        | セクション数 n                                    |
+/// | 16           | 8        | ヘッダのチェックサム                              |
+/// | 24 + 32 * i  | 32       | セクションの種類(u32), 予約(u32), 開始位置(u64), 長さ(u64), チェックサム(u64) |
+///
+/// セクションはセクションテーブルの後ろに8バイト境界に揃えて配置し、隙間は0で埋める。
+/// チェックサムはxxHash64で、ヘッダのチェックサムはその領域を0にしたヘッダ全体から計算する。
+#[derive(Debug, Clone)]
+pub(crate) struct Header {
+    pub(crate) layout: Layout,
+    sections: Vec<Section>,
+}
+
+impl Header {
+    /// 各セクションの配置を決め、チェックサムを計算してヘッダを生成する
+    /// セクションは指定された順に配置する
+    ///
+    /// # Arguments
+    ///
+    /// * `layout`   - base配列, check配列のレイアウト
+    /// * `sections` - セクションの種類と内容
+    pub(crate) fn new(layout: Layout, sections: &[(SectionKind, &[u8])]) -> Header {
+        let mut offset = align(FIXED_SIZE + ENTRY_SIZE * sections.len());
+        let sections = sections.iter().map(|&(kind, bytes)| {
+            let section = Section { kind: kind.id(), offset, len: bytes.len(), checksum: xxh64(bytes, 0) };
+            offset = align(offset + bytes.len());
+            section
+        }).collect();
+        Header { layout, sections }
+    }
+
+    /// ヘッダのバイト数
+    pub(crate) fn size(&self) -> usize {
+        FIXED_SIZE + ENTRY_SIZE * self.sections.len()
+    }
+
+    /// ファイル全体のバイト数
+    pub(crate) fn file_len(&self) -> usize {
+        self.sections.iter().map(|s| s.offset + s.len).max().unwrap_or_else(|| self.size())
+    }
+
+    /// セクションの範囲。セクションが存在しない場合は空の範囲を返す
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - セクションの種類
+    pub(crate) fn range(&self, kind: SectionKind) -> Range<usize> {
+        self.sections.iter()
+            .find(|s| s.kind == kind.id())
+            .map(|s| s.offset..(s.offset + s.len))
+            .unwrap_or(0..0)
+    }
+
+    /// ヘッダとセクションを書き込む
+    /// bufはfile_len()バイト以上で、0で初期化されている必要がある
+    ///
+    /// # Arguments
+    ///
+    /// * `buf`      - 書き込み先
+    /// * `sections` - セクションの内容。new()で指定した順に並べる
+    pub(crate) fn write(&self, buf: &mut [u8], sections: &[&[u8]]) {
+        buf[0..4].copy_from_slice(MAGIC);
+        buf[4..8].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.layout.id().to_le_bytes());
+        buf[12..16].copy_from_slice(&(self.sections.len() as u32).to_le_bytes());
+        for (i, (section, bytes)) in self.sections.iter().zip(sections).enumerate() {
+            let entry = FIXED_SIZE + ENTRY_SIZE * i;
+            buf[entry..(entry + 4)].copy_from_slice(&section.kind.to_le_bytes());
+            buf[(entry + 8)..(entry + 16)].copy_from_slice(&(section.offset as u64).to_le_bytes());
+            buf[(entry + 16)..(entry + 24)].copy_from_slice(&(section.len as u64).to_le_bytes());
+            buf[(entry + 24)..(entry + 32)].copy_from_slice(&section.checksum.to_le_bytes());
+            buf[section.offset..(section.offset + section.len)].copy_from_slice(bytes);
+        }
+        let checksum = header_checksum(&buf[..self.size()]);
+        buf[HEADER_CHECKSUM_POS..(HEADER_CHECKSUM_POS + 8)].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    /// バイト列の先頭からヘッダを読み取る
+    /// ヘッダのチェックサムと、各セクションがバイト列に収まっていることを検証する
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - 辞書ファイルのバイト列
+    pub(crate) fn read(bytes: &[u8]) -> Result<Header, io::Error> {
+        if bytes.len() >= 8 && &bytes[0..4] != MAGIC && LEGACY_HEADER_SIZES.contains(&read_u64(bytes, 0)) {
+            return Err(invalid_data("legacy dictionary file without a format header; rebuild it with this version"));
+        }
+        if bytes.len() < FIXED_SIZE || &bytes[0..4] != MAGIC {
+            return Err(invalid_data("not a dictionary file"));
+        }
+        let version = read_u32(bytes, 4);
+        if version != FORMAT_VERSION {
+            return Err(invalid_data(&format!("unsupported format version: {version}")));
+        }
+        let layout = Layout::from_id(read_u32(bytes, 8))
+            .ok_or_else(|| invalid_data("unknown layout"))?;
+        let count = read_u32(bytes, 12) as usize;
+        if bytes.len() < FIXED_SIZE + ENTRY_SIZE * count {
+            return Err(invalid_data("truncated section table"));
+        }
+        let size = FIXED_SIZE + ENTRY_SIZE * count;
+        if read_u64(bytes, HEADER_CHECKSUM_POS) != header_checksum(&bytes[..size]) {
+            return Err(invalid_data("header checksum mismatch"));
+        }
+        let sections: Vec<Section> = (0..count).map(|i| {
+            let entry = FIXED_SIZE + ENTRY_SIZE * i;
+            Section {
+                kind    : read_u32(bytes, entry),
+                offset  : read_u64(bytes, entry + 8) as usize,
+                len     : read_u64(bytes, entry + 16) as usize,
+                checksum: read_u64(bytes, entry + 24),
+            }
+        }).collect();
+        for section in sections.iter() {
+            if section.offset % ALIGN != 0 || section.offset < size {
+                return Err(invalid_data("invalid section offset"));
+            }
+            if section.offset.checked_add(section.len).is_none_or(|end| end > bytes.len()) {
+                return Err(invalid_data(&format!(
+                    "truncated file: section {} needs {} bytes, but the file has {} bytes",
+                    section.kind, section.offset.saturating_add(section.len), bytes.len(),
+                )));
+            }
+        }
+        Ok(Header { layout, sections })
+    }
+
+    /// 各セクションのチェックサムを計算し直して、ヘッダに格納された値と比較する
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - 辞書ファイルのバイト列
+    pub(crate) fn verify(&self, bytes: &[u8]) -> Result<(), io::Error> {
+        for section in self.sections.iter() {
+            if xxh64(&bytes[section.offset..(section.offset + section.len)], 0) != section.checksum {
+                return Err(invalid_data(&format!("section {} checksum mismatch", section.kind)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// ヘッダのチェックサムを計算する。チェックサムの領域は0として扱う
+///
+/// # Arguments
+///
+/// * `header` - ヘッダのバイト列
+fn header_checksum(header: &[u8]) -> u64 {
+    let mut bytes = header.to_vec();
+    bytes[HEADER_CHECKSUM_POS..(HEADER_CHECKSUM_POS + 8)].copy_from_slice(&[0; 8]);
+    xxh64(&bytes, 0)
+}
+
+/// ALIGNの倍数に切り上げる
+fn align(n: usize) -> usize {
+    n.div_ceil(ALIGN) * ALIGN
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&bytes[pos..(pos + 4)]);
+    u32::from_le_bytes(buf)
+}
+
+fn read_u64(bytes: &[u8], pos: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[pos..(pos + 8)]);
+    u64::from_le_bytes(buf)
+}
+
+/// 不正な辞書ファイルを表すエラー
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let sections: [&[u8]; 3] = [&[1; 12], &[2; 3], &[3; 5]];
+        let header = Header::new(Layout::Compact, &[
+            (SectionKind::Base, sections[0]),
+            (SectionKind::Check, sections[1]),
+            (SectionKind::Data, sections[2]),
+        ]);
+        // ヘッダ = 24 + 32 * 3 = 120 バイト
+        assert_eq!(120, header.size());
+        assert_eq!(120..132, header.range(SectionKind::Base));
+        assert_eq!(136..139, header.range(SectionKind::Check));
+        assert_eq!(144..149, header.range(SectionKind::Data));
+        assert_eq!(0..0, header.range(SectionKind::Tail));
+        assert_eq!(149, header.file_len());
+
+        let mut buf = vec![0u8; header.file_len()];
+        header.write(&mut buf, &sections);
+        assert_eq!(b"DARY", &buf[0..4]);
+        // 隙間は0で埋まっている
+        assert_eq!([0; 4], buf[132..136]);
+        let read = Header::read(&buf).unwrap();
+        assert_eq!(Layout::Compact, read.layout);
+        assert_eq!(header.sections, read.sections);
+        assert_eq!([2; 3], buf[read.range(SectionKind::Check)]);
+        assert!(read.verify(&buf).is_ok());
+    }
+
+    #[test]
+    fn test_header_invalid() {
+        let header = Header::new(Layout::Separate, &[(SectionKind::Data, &[1, 2])]);
+        let mut buf = vec![0u8; header.file_len()];
+        header.write(&mut buf, &[&[1, 2]]);
+        // 識別子が違う
+        let mut bytes = buf.clone();
+        bytes[0] = b'X';
+        assert_eq!(io::ErrorKind::InvalidData, Header::read(&bytes).unwrap_err().kind());
+        // 識別子のない旧形式 (base配列の開始位置から始まるヘッダ)
+        for &size in LEGACY_HEADER_SIZES.iter() {
+            let mut bytes = vec![0u8; 128];
+            bytes[0..8].copy_from_slice(&size.to_le_bytes());
+            let error = Header::read(&bytes).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, error.kind());
+            assert!(error.to_string().contains("legacy"));
+        }
+        // バージョンが違う
+        let mut bytes = buf.clone();
+        bytes[4] = 99;
+        assert!(Header::read(&bytes).is_err());
+        // セクションテーブルが途中で切れている
+        assert!(Header::read(&buf[0..40]).is_err());
+        // セクションが途中で切れている
+        assert!(Header::read(&buf[0..(buf.len() - 1)]).is_err());
+        // ヘッダが壊れている
+        let mut bytes = buf.clone();
+        bytes[FIXED_SIZE + 16] = 1;
+        assert!(Header::read(&bytes).is_err());
+        // セクションの内容が壊れている
+        let mut bytes = buf.clone();
+        let last = bytes.len() - 1;
+        bytes[last] = 3;
+        let header = Header::read(&bytes).unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, header.verify(&bytes).unwrap_err().kind());
+    }
+}
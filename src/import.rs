@@ -0,0 +1,306 @@
+use std::borrow::Cow;
+use std::error;
+use std::fmt;
+use std::fmt::Debug;
+use std::io;
+use std::io::Read;
+
+use crate::trie::Trie;
+
+use encoding_rs::{DecoderResult, Encoding, UTF_8};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// ソースファイルの読み込みに失敗した理由と、失敗した行
+#[derive(Debug)]
+pub struct ImportError {
+    line   : Option<u64>,
+    message: String,
+}
+
+impl ImportError {
+    pub(crate) fn new(line: Option<u64>, message: String) -> Self {
+        ImportError { line, message }
+    }
+
+    /// 失敗した行の行番号 (1始まり)。行を特定できない場合はNone
+    pub fn line(&self) -> Option<u64> {
+        self.line
+    }
+
+    /// 失敗した理由
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None       => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl error::Error for ImportError {}
+
+impl From<ImportError> for io::Error {
+    fn from(e: ImportError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// CSV, TSVのソースファイルを読み込んでトライ木に登録する
+/// 1行を1つの値として、行全体をserdeで `T` に変換する。
+///
+/// # Examples
+///
+/// ```
+/// use dary::import::CsvImporter;
+/// use serde_derive::{Serialize, Deserialize};
+///
+/// #[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// struct Word {
+///     surface: String,
+///     cost: i32,
+/// }
+///
+/// let source = "surface\tcost\n東京\t100\n京都\t200\n";
+/// let trie = CsvImporter::new()
+///     .delimiter(b'\t')
+///     .has_headers(true)
+///     .read::<_, Word>(source.as_bytes(), 0)
+///     .unwrap();
+/// let double_array = trie.to_double_array().unwrap();
+/// assert_eq!(vec![Word { surface: String::from("東京"), cost: 100 }], double_array.get("東京").unwrap());
+/// ```
+#[derive(Debug, Clone)]
+pub struct CsvImporter {
+    delimiter  : u8,
+    has_headers: bool,
+    encoding   : &'static Encoding,
+}
+
+impl CsvImporter {
+    pub fn new() -> Self {
+        CsvImporter { delimiter: b',', has_headers: false, encoding: UTF_8 }
+    }
+
+    /// 区切り文字を指定する (デフォルトは `,`)
+    ///
+    /// # Arguments
+    ///
+    /// * `delimiter` - 区切り文字
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// 先頭行をヘッダとして扱う (デフォルトは扱わない)
+    /// ヘッダがある場合は列名と `T` のフィールド名を対応付け、ない場合は列の順に対応付ける。
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - 先頭行をヘッダとして扱うかどうか
+    pub fn has_headers(mut self, enabled: bool) -> Self {
+        self.has_headers = enabled;
+        self
+    }
+
+    /// ソースファイルの文字コードを指定する (デフォルトはUTF-8)
+    /// WHATWG Encoding Standard のラベル (`shift_jis`, `euc-jp` など) で指定する。
+    ///
+    /// # Arguments
+    ///
+    /// * `label` - 文字コードのラベル
+    pub fn encoding(mut self, label: &str) -> Result<Self, ImportError> {
+        self.encoding = Encoding::for_label(label.as_bytes())
+            .ok_or_else(|| ImportError::new(None, format!("unknown encoding '{label}'")))?;
+        Ok(self)
+    }
+
+    /// ソースファイルを読み込んで新しいトライ木を返す
+    ///
+    /// # Arguments
+    ///
+    /// * `reader`     - ソースファイル
+    /// * `key_column` - キーの列のindex (0始まり)
+    pub fn read<R: Read, T: Serialize + DeserializeOwned + Debug>(&self, reader: R, key_column: usize) -> Result<Trie<T>, ImportError> {
+        let mut trie = Trie::new();
+        self.read_into(reader, key_column, &mut trie)?;
+        Ok(trie)
+    }
+
+    /// ソースファイルを読み込んでトライ木に追加し、追加した値の数を返す
+    /// 複数のソースファイルから1つの辞書を構築する場合に使う。
+    ///
+    /// # Arguments
+    ///
+    /// * `reader`     - ソースファイル
+    /// * `key_column` - キーの列のindex (0始まり)
+    /// * `trie`       - 値を追加するトライ木
+    pub fn read_into<R: Read, T: Serialize + DeserializeOwned + Debug>(&self, mut reader: R, key_column: usize, trie: &mut Trie<T>) -> Result<usize, ImportError> {
+        let mut bytes: Vec<u8> = Vec::new();
+        reader.read_to_end(&mut bytes).map_err(|e| ImportError::new(None, e.to_string()))?;
+        let text = decode(&bytes, self.encoding)?;
+
+        let mut csv_reader = csv::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .has_headers(self.has_headers)
+            .flexible(true)
+            .from_reader(text.as_bytes());
+        let headers = if self.has_headers {
+            Some(csv_reader.headers().map_err(csv_error)?.clone())
+        } else {
+            None
+        };
+        let mut count = 0;
+        for record in csv_reader.records() {
+            let record = record.map_err(csv_error)?;
+            let line = record.position().map(|position| position.line());
+            let key = record.get(key_column)
+                .ok_or_else(|| ImportError::new(line, format!("missing key column {key_column}")))?;
+            let value: T = record.deserialize(headers.as_ref())
+                .map_err(|e| ImportError::new(line, e.to_string()))?;
+            trie.set(key, value);
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+impl Default for CsvImporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// CSVのソースファイルを読み込んでトライ木を返す
+/// 区切り文字は `,`、ヘッダなし、UTF-8として読み込む。オプションを指定する場合は `CsvImporter` を使う。
+///
+/// # Arguments
+///
+/// * `reader`     - ソースファイル
+/// * `key_column` - キーの列のindex (0始まり)
+pub fn from_csv<R: Read, T: Serialize + DeserializeOwned + Debug>(reader: R, key_column: usize) -> Result<Trie<T>, ImportError> {
+    CsvImporter::new().read(reader, key_column)
+}
+
+/// ソースファイルをUTF-8の文字列に変換する
+/// 変換できないバイト列がある場合は、その行の行番号を含むエラーを返す
+///
+/// # Arguments
+///
+/// * `bytes`    - ソースファイルの内容
+/// * `encoding` - ソースファイルの文字コード
+pub(crate) fn decode<'a>(bytes: &'a [u8], encoding: &'static Encoding) -> Result<Cow<'a, str>, ImportError> {
+    let (text, had_errors) = encoding.decode_with_bom_removal(bytes);
+    if had_errors {
+        let line = malformed_line(bytes, encoding);
+        return Err(ImportError::new(Some(line), format!("invalid {} byte sequence", encoding.name())));
+    }
+    Ok(text)
+}
+
+/// 変換できない最初のバイト列の行番号を返す
+/// ソースファイルに元から U+FFFD が含まれていても正しい行を返すよう、置き換えずに変換できなくなった位置までを数える
+///
+/// # Arguments
+///
+/// * `bytes`    - ソースファイルの内容
+/// * `encoding` - ソースファイルの文字コード
+fn malformed_line(bytes: &[u8], encoding: &'static Encoding) -> u64 {
+    let mut decoder = encoding.new_decoder_with_bom_removal();
+    let mut decoded = String::new();
+    let mut src = bytes;
+    loop {
+        let capacity = decoder.max_utf8_buffer_length_without_replacement(src.len()).unwrap_or(src.len());
+        decoded.reserve(capacity.max(4));
+        let (result, read) = decoder.decode_to_string_without_replacement(src, &mut decoded, true);
+        src = &src[read..];
+        if result != DecoderResult::OutputFull {
+            break;
+        }
+    }
+    decoded.matches('\n').count() as u64 + 1
+}
+
+/// csvのエラーを行番号を含むエラーに変換する
+fn csv_error(e: csv::Error) -> ImportError {
+    let line = e.position().map(|position| position.line());
+    ImportError::new(line, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_derive::{Serialize, Deserialize};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Word {
+        surface: String,
+        pos    : String,
+        cost   : i32,
+    }
+
+    impl Word {
+        fn new(surface: &str, pos: &str, cost: i32) -> Self {
+            Word { surface: surface.to_string(), pos: pos.to_string(), cost }
+        }
+    }
+
+    #[test]
+    fn test_from_csv() {
+        let source = "東京,名詞,100\n京都,名詞,200\n東京,固有名詞,50\n";
+        let trie: Trie<Word> = from_csv(source.as_bytes(), 0).unwrap();
+        let double_array = trie.to_double_array().unwrap();
+        assert_eq!(vec![Word::new("東京", "名詞", 100), Word::new("東京", "固有名詞", 50)], double_array.get("東京").unwrap());
+        assert_eq!(vec![Word::new("京都", "名詞", 200)], double_array.get("京都").unwrap());
+    }
+
+    #[test]
+    fn test_options() {
+        // ヘッダの列名でフィールドを対応付ける
+        let source = "cost\tsurface\tpos\n100\t東京\t名詞\n";
+        let mut trie: Trie<Word> = Trie::new();
+        let importer = CsvImporter::new().delimiter(b'\t').has_headers(true);
+        assert_eq!(1, importer.read_into(source.as_bytes(), 1, &mut trie).unwrap());
+        assert_eq!(Some(&[Word::new("東京", "名詞", 100)][..]), trie.get("東京"));
+
+        // Shift_JIS
+        let (bytes, _, _) = encoding_rs::SHIFT_JIS.encode("京都,名詞,200\n");
+        let trie: Trie<Word> = CsvImporter::new().encoding("shift_jis").unwrap().read(&bytes[..], 0).unwrap();
+        assert_eq!(Some(&[Word::new("京都", "名詞", 200)][..]), trie.get("京都"));
+        assert!(CsvImporter::new().encoding("unknown").is_err());
+    }
+
+    #[test]
+    fn test_error_line() {
+        // 値を変換できない
+        let source = "東京,名詞,100\n京都,名詞,x\n";
+        let e = from_csv::<_, Word>(source.as_bytes(), 0).err().unwrap();
+        assert_eq!(Some(2), e.line());
+        assert!(e.to_string().starts_with("line 2: "));
+
+        // キーの列がない
+        let source = "a,b,1\n\"c\"\n";
+        let e = from_csv::<_, Word>(source.as_bytes(), 0).err().unwrap();
+        assert_eq!(Some(2), e.line());
+        let e = from_csv::<_, Word>(source.as_bytes(), 5).err().unwrap();
+        assert_eq!(Some(1), e.line());
+
+        // 文字コードが異なる
+        let source = "東京,名詞,100\n京都,名詞,200\n";
+        let e = CsvImporter::new().encoding("euc-jp").unwrap().read::<_, Word>(source.as_bytes(), 0).err().unwrap();
+        assert!(e.line().is_some());
+        let (bytes, _, _) = encoding_rs::SHIFT_JIS.encode(source);
+        let e = from_csv::<_, Word>(&bytes[..], 0).err().unwrap();
+        assert_eq!(Some(1), e.line());
+        // 変換できないバイト列より前に U+FFFD がある
+        let mut bytes = "東京\u{FFFD},名詞,100\n京都,名詞,200\n".as_bytes().to_vec();
+        bytes.extend_from_slice(b"\xFF,x,1\n");
+        let e = from_csv::<_, Word>(&bytes[..], 0).err().unwrap();
+        assert_eq!(Some(3), e.line());
+    }
+}
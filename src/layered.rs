@@ -0,0 +1,284 @@
+use std::fmt::Debug;
+
+use crate::double_array::{DoubleArray, PrefixSearchIter};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// 複数のレイヤーに同じキーがある場合の扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerMode {
+    /// 後ろのレイヤーの値で前のレイヤーの値を隠す
+    Shadow,
+    /// すべてのレイヤーの値をレイヤーの順に返す
+    Union,
+}
+
+/// 複数のダブル配列を1つの辞書として検索する
+/// レイヤーは前から順に優先度が低い。システム辞書を先頭に、ユーザー辞書を後ろに置く。
+/// 検索結果の値には、値を持っていたレイヤーのindexが付く。
+///
+/// # Examples
+///
+/// ```
+/// use dary::{Trie, LayeredDictionary, LayerMode};
+///
+/// let mut system: Trie<u32> = Trie::new();
+/// system.set("東京", 1);
+/// system.set("東京都", 2);
+/// let mut user: Trie<u32> = Trie::new();
+/// user.set("東京", 10);
+///
+/// let layers = vec![system.to_double_array().unwrap(), user.to_double_array().unwrap()];
+/// let dictionary = LayeredDictionary::new(layers, LayerMode::Shadow);
+/// assert_eq!(Some(vec![(1, 10)]), dictionary.get("東京"));
+/// assert_eq!(vec![("東京", vec![(1, 10)]), ("東京都", vec![(0, 2)])], dictionary.prefix_search("東京都庁"));
+/// ```
+pub struct LayeredDictionary<T>
+    where T: Serialize + DeserializeOwned + Debug,
+{
+    layers: Vec<DoubleArray<T>>,
+    mode  : LayerMode,
+}
+
+impl<T: Serialize + DeserializeOwned + Debug> LayeredDictionary<T> {
+    /// # Arguments
+    ///
+    /// * `layers` - 優先度の低い順に並べたダブル配列
+    /// * `mode`   - 複数のレイヤーに同じキーがある場合の扱い
+    pub fn new(layers: Vec<DoubleArray<T>>, mode: LayerMode) -> Self {
+        LayeredDictionary { layers, mode }
+    }
+
+    /// レイヤーを優先度の低い順に返す
+    pub fn layers(&self) -> &[DoubleArray<T>] {
+        &self.layers
+    }
+
+    /// 最も優先度の高いレイヤーとして追加する
+    ///
+    /// # Arguments
+    ///
+    /// * `layer` - 追加するダブル配列
+    pub fn push(&mut self, layer: DoubleArray<T>) {
+        self.layers.push(layer);
+    }
+
+    /// レイヤーを入れ替え、元のレイヤーを返す
+    /// 頻繁に更新するユーザー辞書を読み込み直す場合に使う。
+    ///
+    /// # Panics
+    /// indexがレイヤーの数以上の場合にpanicする。
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - 入れ替えるレイヤーのindex
+    /// * `layer` - 新しいダブル配列
+    pub fn replace(&mut self, index: usize, layer: DoubleArray<T>) -> DoubleArray<T> {
+        std::mem::replace(&mut self.layers[index], layer)
+    }
+
+    /// すべてのレイヤーからkeyを探索する
+    /// どのレイヤーにも値がない場合はNoneを返す
+    /// `LayerMode::Shadow` では優先度の高いレイヤーから探索し、最初に見つかったレイヤーの値だけをデシリアライズする
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - 探索対象の文字列
+    pub fn get(&self, key: &str) -> Option<Vec<(usize, T)>> {
+        if self.mode == LayerMode::Shadow {
+            return self.layers.iter().enumerate().rev()
+                .find_map(|(layer, double_array)| double_array.get(key).map(|values| (layer, values)))
+                .map(|(layer, values)| values.into_iter().map(|value| (layer, value)).collect());
+        }
+        let mut ret: Option<Vec<(usize, T)>> = None;
+        for (layer, double_array) in self.layers.iter().enumerate() {
+            if let Some(values) = double_array.get(key) {
+                ret.get_or_insert_with(Vec::new).extend(values.into_iter().map(|value| (layer, value)));
+            }
+        }
+        ret
+    }
+
+    /// すべてのレイヤーで共通接頭辞検索を行う
+    /// 接頭辞の短い順に、接頭辞ごとにレイヤーの値をまとめて返す
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - 探索対象の文字列
+    pub fn prefix_search<'a>(&self, key: &'a str) -> Vec<(&'a str, Vec<(usize, T)>)> {
+        self.prefix_search_iter(key).map(|(prefix, values)| (&key[0..prefix.len()], values)).collect()
+    }
+
+    /// すべてのレイヤーで共通接頭辞検索を行うイテレータを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - 探索対象の文字列
+    pub fn prefix_search_iter<'a>(&'a self, key: &'a str) -> LayeredPrefixSearchIter<'a, T> {
+        let mut iters: Vec<PrefixSearchIter<'a, T>> = self.layers.iter().map(|double_array| double_array.prefix_search_iter(key)).collect();
+        LayeredPrefixSearchIter {
+            peeked: iters.iter_mut().map(|iter| iter.next_match()).collect(),
+            iters,
+            mode  : self.mode,
+        }
+    }
+}
+
+/// すべてのレイヤーで共通接頭辞検索を行うイテレータ
+/// 各レイヤーは接頭辞の短い順に返すので、最も短い接頭辞を持つレイヤーから取り出す
+pub struct LayeredPrefixSearchIter<'a, T>
+    where T: Serialize + DeserializeOwned + Debug,
+{
+    iters : Vec<PrefixSearchIter<'a, T>>,
+    /// 各レイヤーで次に一致する接頭辞とdataの開始index。値は必要になるまでデシリアライズしない
+    peeked: Vec<Option<(&'a str, usize)>>,
+    mode  : LayerMode,
+}
+
+impl<'a, T> Iterator for LayeredPrefixSearchIter<'a, T>
+    where T: Serialize + DeserializeOwned + Debug,
+{
+    type Item = (&'a str, Vec<(usize, T)>);
+
+    fn next(&mut self) -> Option<(&'a str, Vec<(usize, T)>)> {
+        // すべて同じキーの接頭辞なので長さで比較できる
+        let prefix: &'a str = self.peeked.iter()
+            .filter_map(|peeked| peeked.map(|(prefix, _)| prefix))
+            .min_by_key(|prefix| prefix.len())?;
+        let matched: Vec<usize> = (0..self.peeked.len())
+            .filter(|&layer| self.peeked[layer].is_some_and(|(p, _)| p.len() == prefix.len()))
+            .collect();
+        // Shadowでは最も優先度の高いレイヤーの値だけをデシリアライズする
+        let decoded = match self.mode {
+            LayerMode::Shadow => &matched[(matched.len() - 1)..],
+            LayerMode::Union  => &matched[..],
+        };
+        let mut ret: Vec<(usize, T)> = Vec::new();
+        for &layer in decoded {
+            let (_, data_idx) = self.peeked[layer].unwrap();
+            ret.extend(self.iters[layer].decode(data_idx).into_iter().map(|value| (layer, value)));
+        }
+        for &layer in matched.iter() {
+            self.peeked[layer] = self.iters[layer].next_match();
+        }
+        Some((prefix, ret))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trie::Trie;
+    use std::cell::Cell;
+    use serde::{Deserialize, Deserializer};
+    use serde_derive::Serialize;
+
+    fn to_double_array(entries: &[(&str, u32)]) -> DoubleArray<u32> {
+        let mut trie: Trie<u32> = Trie::new();
+        for &(key, value) in entries {
+            trie.set(key, value);
+        }
+        trie.to_double_array().unwrap()
+    }
+
+    fn layers() -> Vec<DoubleArray<u32>> {
+        vec![
+            to_double_array(&[("a", 1), ("ab", 2), ("ab", 3), ("abcd", 4)]),
+            to_double_array(&[("ab", 10), ("abc", 11)]),
+            to_double_array(&[("a", 20), ("ab", 21)]),
+        ]
+    }
+
+    #[test]
+    fn test_shadow() {
+        let dictionary = LayeredDictionary::new(layers(), LayerMode::Shadow);
+        assert_eq!(Some(vec![(2, 20)]), dictionary.get("a"));
+        assert_eq!(Some(vec![(2, 21)]), dictionary.get("ab"));
+        assert_eq!(Some(vec![(1, 11)]), dictionary.get("abc"));
+        assert_eq!(Some(vec![(0, 4)]), dictionary.get("abcd"));
+        assert_eq!(None, dictionary.get("abcde"));
+        let expected = vec![
+            ("a"   , vec![(2, 20)]),
+            ("ab"  , vec![(2, 21)]),
+            ("abc" , vec![(1, 11)]),
+            ("abcd", vec![(0, 4)]),
+        ];
+        assert_eq!(expected, dictionary.prefix_search("abcdef"));
+        assert_eq!(expected, dictionary.prefix_search_iter("abcdef").collect::<Vec<_>>());
+    }
+
+    /// デシリアライズした回数を数える値
+    #[derive(Serialize, Debug, PartialEq)]
+    struct Counted(u32);
+
+    thread_local! {
+        static DECODED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    impl<'de> Deserialize<'de> for Counted {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            DECODED.with(|decoded| decoded.set(decoded.get() + 1));
+            u32::deserialize(deserializer).map(Counted)
+        }
+    }
+
+    #[test]
+    fn test_shadow_decodes_top_layer() {
+        let to_double_array = |entries: &[(&str, u32)]| {
+            let mut trie: Trie<Counted> = Trie::new();
+            for &(key, value) in entries {
+                trie.set(key, Counted(value));
+            }
+            trie.to_double_array().unwrap()
+        };
+        let layers = vec![
+            to_double_array(&[("a", 1), ("ab", 2), ("abc", 3)]),
+            to_double_array(&[("ab", 10)]),
+            to_double_array(&[("a", 20), ("ab", 21)]),
+        ];
+        let dictionary = LayeredDictionary::new(layers, LayerMode::Shadow);
+        DECODED.with(|decoded| decoded.set(0));
+        assert_eq!(Some(vec![(2, Counted(21))]), dictionary.get("ab"));
+        // 下のレイヤーの値はデシリアライズしない
+        assert_eq!(1, DECODED.with(Cell::get));
+        let expected = vec![
+            ("a"  , vec![(2, Counted(20))]),
+            ("ab" , vec![(2, Counted(21))]),
+            ("abc", vec![(0, Counted(3))]),
+        ];
+        DECODED.with(|decoded| decoded.set(0));
+        assert_eq!(expected, dictionary.prefix_search("abcd"));
+        assert_eq!(3, DECODED.with(Cell::get));
+    }
+
+    #[test]
+    fn test_union() {
+        let dictionary = LayeredDictionary::new(layers(), LayerMode::Union);
+        assert_eq!(Some(vec![(0, 1), (2, 20)]), dictionary.get("a"));
+        assert_eq!(Some(vec![(0, 2), (0, 3), (1, 10), (2, 21)]), dictionary.get("ab"));
+        assert_eq!(None, dictionary.get("b"));
+        let expected = vec![
+            ("a"  , vec![(0, 1), (2, 20)]),
+            ("ab" , vec![(0, 2), (0, 3), (1, 10), (2, 21)]),
+            ("abc", vec![(1, 11)]),
+        ];
+        assert_eq!(expected, dictionary.prefix_search("abc"));
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut dictionary = LayeredDictionary::new(layers(), LayerMode::Shadow);
+        let old = dictionary.replace(2, to_double_array(&[("abc", 30)]));
+        assert_eq!(Some(vec![21]), old.get("ab"));
+        assert_eq!(Some(vec![(1, 10)]), dictionary.get("ab"));
+        assert_eq!(Some(vec![(2, 30)]), dictionary.get("abc"));
+        dictionary.push(to_double_array(&[("abc", 40)]));
+        assert_eq!(4, dictionary.layers().len());
+        assert_eq!(Some(vec![(3, 40)]), dictionary.get("abc"));
+        // レイヤーがない
+        let dictionary: LayeredDictionary<u32> = LayeredDictionary::new(Vec::new(), LayerMode::Union);
+        assert_eq!(None, dictionary.get("a"));
+        assert_eq!(0, dictionary.prefix_search("a").len());
+    }
+}
@@ -0,0 +1,218 @@
+use std::slice;
+
+/// Compact レイアウトで未使用の要素を表すラベル
+/// 0xFE は UTF-8 に現れないので遷移のラベルと衝突しない
+pub(crate) const EMPTY_LABEL: u8 = 0xFE;
+
+/// ダブル配列のメモリレイアウト
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// base配列とcheck配列を別々の領域に格納する
+    #[default]
+    Separate,
+    /// (base, check) を1要素8バイトのユニットとして交互に格納する
+    /// 遷移先のcheckとbaseが同じキャッシュラインに載るため、探索が速い
+    Interleaved,
+    /// checkを遷移のラベル(8bit)として格納する
+    /// 1要素あたり5バイトで済むが、ノードごとにbase値が一意である必要がある
+    Compact,
+}
+
+impl Layout {
+    /// ヘッダに格納するレイアウトのID
+    pub(crate) fn id(self) -> u32 {
+        match self {
+            Layout::Separate    => 0,
+            Layout::Interleaved => 1,
+            Layout::Compact     => 2,
+        }
+    }
+
+    /// ヘッダに格納されたIDからレイアウトを復元する
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - レイアウトのID
+    pub(crate) fn from_id(id: u32) -> Option<Layout> {
+        match id {
+            0 => Some(Layout::Separate),
+            1 => Some(Layout::Interleaved),
+            2 => Some(Layout::Compact),
+            _ => None,
+        }
+    }
+
+    /// base配列, check配列をレイアウトに従ってリトルエンディアンのバイト列にする
+    /// 戻り値は (base領域, check領域)
+    ///
+    /// # Arguments
+    ///
+    /// * `base_arr`  - base配列
+    /// * `check_arr` - check配列
+    pub(crate) fn encode(self, base_arr: &[u32], check_arr: &[u32]) -> (Vec<u8>, Vec<u8>) {
+        match self {
+            Layout::Separate => {
+                let mut base_bytes: Vec<u8> = Vec::with_capacity(base_arr.len() * 4);
+                base_arr.iter().for_each(|b| base_bytes.extend_from_slice(&b.to_le_bytes()));
+                let mut check_bytes: Vec<u8> = Vec::with_capacity(check_arr.len() * 4);
+                check_arr.iter().for_each(|c| check_bytes.extend_from_slice(&c.to_le_bytes()));
+                (base_bytes, check_bytes)
+            },
+            Layout::Interleaved => {
+                let mut unit_bytes: Vec<u8> = Vec::with_capacity(base_arr.len() * 8);
+                for (b, c) in base_arr.iter().zip(check_arr) {
+                    unit_bytes.extend_from_slice(&b.to_le_bytes());
+                    unit_bytes.extend_from_slice(&c.to_le_bytes());
+                }
+                (unit_bytes, Vec::new())
+            },
+            Layout::Compact => {
+                let mut base_bytes: Vec<u8> = Vec::with_capacity(base_arr.len() * 4);
+                base_arr.iter().for_each(|b| base_bytes.extend_from_slice(&b.to_le_bytes()));
+                // ラベル = 自身のindex - 遷移元のbase値
+                let label_bytes: Vec<u8> = check_arr.iter().enumerate().map(|(i, &check)| {
+                    if check == 0 {
+                        EMPTY_LABEL
+                    } else {
+                        (i - base_arr[check as usize] as usize) as u8
+                    }
+                }).collect();
+                (base_bytes, label_bytes)
+            },
+        }
+    }
+}
+
+/// レイアウトごとのbase/check領域への参照
+pub(crate) enum Units<'a> {
+    Separate    { base: &'a [u32], check: &'a [u32] },
+    Interleaved { units: &'a [u32] },
+    Compact     { base: &'a [u32], label: &'a [u8] },
+}
+
+impl<'a> Units<'a> {
+    /// base/check領域のバイト列から生成する
+    /// バイト列は4バイト境界に揃っている必要がある
+    ///
+    /// # Arguments
+    ///
+    /// * `layout`      - レイアウト
+    /// * `base_bytes`  - base領域
+    /// * `check_bytes` - check領域
+    pub(crate) fn new(layout: Layout, base_bytes: &'a [u8], check_bytes: &'a [u8]) -> Units<'a> {
+        let as_u32 = |bytes: &'a [u8]| -> &'a [u32] {
+            unsafe { slice::from_raw_parts(bytes.as_ptr() as *const u32, bytes.len() / 4) }
+        };
+        match layout {
+            Layout::Separate    => Units::Separate    { base: as_u32(base_bytes), check: as_u32(check_bytes) },
+            Layout::Interleaved => Units::Interleaved { units: as_u32(base_bytes) },
+            Layout::Compact     => Units::Compact     { base: as_u32(base_bytes), label: check_bytes },
+        }
+    }
+
+    /// 要素数
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Units::Separate    { base, .. } => base.len(),
+            Units::Interleaved { units }    => units.len() / 2,
+            Units::Compact     { base, .. } => base.len(),
+        }
+    }
+
+    /// idx番目のbase値
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - 要素のindex
+    #[inline]
+    pub(crate) fn base(&self, idx: usize) -> u32 {
+        match self {
+            Units::Separate    { base, .. } => u32::from_le(base[idx]),
+            Units::Interleaved { units }    => u32::from_le(units[idx * 2]),
+            Units::Compact     { base, .. } => u32::from_le(base[idx]),
+        }
+    }
+
+    /// from から label で to に遷移できるかを判定する
+    ///
+    /// # Arguments
+    ///
+    /// * `from`  - 遷移元のindex
+    /// * `to`    - 遷移先のindex (遷移元のbase値 + label)
+    /// * `label` - 遷移のラベル
+    #[inline]
+    pub(crate) fn is_child(&self, from: usize, to: usize, label: u8) -> bool {
+        match self {
+            Units::Separate    { check, .. } => u32::from_le(check[to]) as usize == from,
+            Units::Interleaved { units }     => u32::from_le(units[to * 2 + 1]) as usize == from,
+            Units::Compact     { label: l, .. } => l[to] == label,
+        }
+    }
+
+    /// idx番目の要素に遷移するラベル
+    /// 使用されていない要素に対しては意味のない値を返す
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - 要素のindex
+    pub(crate) fn label(&self, idx: usize) -> u8 {
+        match self {
+            Units::Separate    { check, .. } => (idx - self.base(u32::from_le(check[idx]) as usize) as usize) as u8,
+            Units::Interleaved { units }     => (idx - self.base(u32::from_le(units[idx * 2 + 1]) as usize) as usize) as u8,
+            Units::Compact     { label, .. } => label[idx],
+        }
+    }
+
+    /// idx番目の要素が使用されているか
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - 要素のindex
+    pub(crate) fn is_used(&self, idx: usize) -> bool {
+        match self {
+            Units::Separate    { check, .. } => check[idx] != 0,
+            Units::Interleaved { units }     => units[idx * 2 + 1] != 0,
+            Units::Compact     { label, .. } => label[idx] != EMPTY_LABEL,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::to_bytes;
+
+    fn aligned(bytes: &[u8]) -> Vec<u32> {
+        bytes.chunks(4).map(|b| {
+            let mut word = [0u8; 4];
+            word[..b.len()].copy_from_slice(b);
+            u32::from_ne_bytes(word)
+        }).collect()
+    }
+
+    #[test]
+    fn test_encode() {
+        // index=3 は 1 -> (base=2) + 1, index=4 は 3 -> (base=1) + 3 の遷移
+        let base_arr: Vec<u32>  = vec![0, 2, 0, 1, 7];
+        let check_arr: Vec<u32> = vec![0, 0, 0, 1, 3];
+        for &layout in &[Layout::Separate, Layout::Interleaved, Layout::Compact] {
+            let (base_bytes, check_bytes) = layout.encode(&base_arr, &check_arr);
+            // u32として読むために4バイト境界に揃える
+            let (base_words, check_words) = (aligned(&base_bytes), aligned(&check_bytes));
+            let base_bytes  = to_bytes(&base_words);
+            let check_bytes = &to_bytes(&check_words)[..check_bytes.len()];
+            let units = Units::new(layout, base_bytes, check_bytes);
+            assert_eq!(5, units.len());
+            for (i, &base) in base_arr.iter().enumerate() {
+                assert_eq!(base, units.base(i));
+            }
+            assert!(units.is_child(1, 3, 1));
+            assert!(units.is_child(3, 4, 3));
+            assert!(!units.is_child(3, 2, 0));
+            assert_eq!(1, units.label(3));
+            assert_eq!(3, units.label(4));
+            assert!(units.is_used(3));
+            assert!(!units.is_used(2));
+        }
+    }
+}
@@ -1,8 +1,35 @@
+// 既存のドキュメントの例は `fn main` を含む形で書かれている
+#![allow(clippy::needless_doctest_main)]
+
 pub mod trie;
+pub mod builder;
+pub mod alphabet;
 pub mod bit_cache;
 pub mod double_array;
+pub mod data;
+pub mod dictionary;
+pub mod format;
+pub mod layout;
+pub mod diff;
+pub mod import;
+pub mod layered;
+pub mod mecab;
+pub mod merge;
+pub mod normalizer;
+pub mod stats;
+pub mod tokenizer;
 pub mod utils;
 
 pub use trie::Trie;
+pub use builder::{DoubleArrayBuilder, BuildProgress};
 pub use double_array::DoubleArray;
-pub use double_array::PrefixSearchIter;
\ No newline at end of file
+pub use double_array::PrefixSearchIter;
+pub use double_array::Iter;
+pub use dictionary::Dictionary;
+pub use diff::{diff, Diff, DiffEntry};
+pub use layered::{LayeredDictionary, LayerMode, LayeredPrefixSearchIter};
+pub use alphabet::Alphabet;
+pub use layout::Layout;
+pub use merge::MergePolicy;
+pub use normalizer::Normalizer;
+pub use stats::BuildStats;
\ No newline at end of file
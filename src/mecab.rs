@@ -0,0 +1,727 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::builder::DoubleArrayBuilder;
+use crate::double_array::DoubleArray;
+use crate::import::{decode, ImportError};
+use crate::trie::Trie;
+
+use bincode;
+use encoding_rs::{Encoding, UTF_8};
+use serde_derive::{Serialize, Deserialize};
+
+/// 構築した辞書を保存するディレクトリでの、システム辞書のファイル名
+pub const SYSTEM_DICTIONARY_FILE: &str = "sys.dic";
+
+/// 構築した辞書を保存するディレクトリでの、連接コスト表のファイル名
+pub const MATRIX_FILE: &str = "matrix.bin";
+
+/// 構築した辞書を保存するディレクトリでの、文字種の定義のファイル名
+pub const CHAR_DEFINITION_FILE: &str = "char.bin";
+
+/// 構築した辞書を保存するディレクトリでの、未知語辞書のファイル名
+pub const UNKNOWN_DICTIONARY_FILE: &str = "unk.dic";
+
+/// 文字種の定義で、文字種が指定されていない文字の文字種の名前
+pub const DEFAULT_CATEGORY: &str = "DEFAULT";
+
+/// 文字種の数の上限 (文字種の集合を64bitで表す)
+const MAX_CATEGORIES: usize = 64;
+
+/// 基本多言語面の文字数
+const BMP_SIZE: usize = 0x10000;
+
+/// 連接コスト表の文脈IDの数の上限 (文脈IDはu16)
+const MAX_CONTEXT_IDS: usize = u16::MAX as usize + 1;
+
+/// 形態素の辞書の1エントリ
+/// IPADIC, UniDic形式のCSVの1行に対応する
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Morpheme {
+    /// 左文脈ID
+    pub left_id: u16,
+    /// 右文脈ID
+    pub right_id: u16,
+    /// 単語コスト
+    pub cost: i16,
+    /// 品詞・活用・読みなどの素性 (CSVの5列目以降をカンマで連結したもの)
+    pub feature: String,
+}
+
+/// 形態素の連接コスト表 (matrix.def)
+/// 前の形態素の右文脈IDと後ろの形態素の左文脈IDの組ごとにコストを持つ
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionMatrix {
+    /// 右文脈IDの数
+    forward_size : usize,
+    /// 左文脈IDの数
+    backward_size: usize,
+    /// index = 右文脈ID + forward_size * 左文脈ID
+    costs        : Vec<i16>,
+}
+
+impl ConnectionMatrix {
+    /// コストがすべて0の連接コスト表を作成する
+    ///
+    /// # Arguments
+    ///
+    /// * `forward_size`  - 右文脈IDの数
+    /// * `backward_size` - 左文脈IDの数
+    pub fn new(forward_size: usize, backward_size: usize) -> Self {
+        ConnectionMatrix { forward_size, backward_size, costs: vec![0; forward_size * backward_size] }
+    }
+
+    /// 右文脈IDの数
+    pub fn forward_size(&self) -> usize {
+        self.forward_size
+    }
+
+    /// 左文脈IDの数
+    pub fn backward_size(&self) -> usize {
+        self.backward_size
+    }
+
+    /// 連接コストを返す
+    /// IDが範囲外の場合の結果は不定 (panicするか、別の組のコストを返す)
+    ///
+    /// # Arguments
+    ///
+    /// * `right_id` - 前の形態素の右文脈ID
+    /// * `left_id`  - 後ろの形態素の左文脈ID
+    pub fn cost(&self, right_id: u16, left_id: u16) -> i16 {
+        self.costs[right_id as usize + self.forward_size * left_id as usize]
+    }
+
+    /// 連接コストを設定する
+    ///
+    /// # Panics
+    /// IDが範囲外の場合にpanicする。
+    ///
+    /// # Arguments
+    ///
+    /// * `right_id` - 前の形態素の右文脈ID
+    /// * `left_id`  - 後ろの形態素の左文脈ID
+    /// * `cost`     - 連接コスト
+    pub fn set_cost(&mut self, right_id: u16, left_id: u16, cost: i16) {
+        assert!((right_id as usize) < self.forward_size && (left_id as usize) < self.backward_size, "context id out of range");
+        self.costs[right_id as usize + self.forward_size * left_id as usize] = cost;
+    }
+
+    /// 形態素の文脈IDが連接コスト表の範囲内かどうか
+    ///
+    /// # Arguments
+    ///
+    /// * `morpheme` - 形態素
+    pub fn contains(&self, morpheme: &Morpheme) -> bool {
+        (morpheme.right_id as usize) < self.forward_size && (morpheme.left_id as usize) < self.backward_size
+    }
+
+    /// 連接コスト表をファイルに保存する
+    ///
+    /// # Arguments
+    ///
+    /// * `output_path` - 保存先のファイルパス
+    pub fn dump(&self, output_path: &str) -> Result<(), io::Error> {
+        let bytes = bincode::serialize(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(output_path, bytes)
+    }
+
+    /// `dump` で保存した連接コスト表を読み込む
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - ファイルパス
+    pub fn from_file(path: &str) -> Result<Self, io::Error> {
+        let bytes = fs::read(path)?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid connection matrix");
+        let matrix: ConnectionMatrix = bincode::deserialize(&bytes).map_err(|_| invalid())?;
+        if matrix.costs.len() != matrix.forward_size * matrix.backward_size {
+            return Err(invalid());
+        }
+        Ok(matrix)
+    }
+}
+
+/// 未知語の文字種 (char.def の文字種の定義の1行)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CharCategory {
+    /// 文字種の名前。未知語辞書のキーになる
+    pub name: String,
+    /// 辞書に形態素が見つかった位置でも未知語の候補を作るかどうか
+    pub invoke: bool,
+    /// 同じ文字種の文字の連続を1つの未知語の候補にするかどうか
+    pub group: bool,
+    /// 1文字から length 文字までの未知語の候補を作る
+    pub length: usize,
+}
+
+/// 文字種の定義 (char.def)
+/// 文字ごとに文字種の集合を持ち、最初に指定された文字種を代表の文字種とする
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CharDefinition {
+    categories: Vec<CharCategory>,
+    /// (文字種の集合のビット, 代表の文字種) のリスト
+    sets      : Vec<(u64, usize)>,
+    /// (最初の文字, 最後の文字, 文字種の集合のindex) のリスト。後ろの範囲を優先する
+    ranges    : Vec<(u32, u32, usize)>,
+    /// 基本多言語面の文字から文字種の集合のindexを引く表。rangesから作る
+    #[serde(skip)]
+    bmp       : Vec<u16>,
+}
+
+impl CharDefinition {
+    /// 文字種の定義を作成する
+    ///
+    /// # Arguments
+    ///
+    /// * `categories` - 文字種。`DEFAULT` を含まなければならない
+    /// * `ranges`     - (最初の文字, 最後の文字, 文字種の名前のリスト) のリスト。後ろの範囲を優先する
+    pub fn new(categories: Vec<CharCategory>, ranges: &[(char, char, Vec<&str>)]) -> Result<Self, io::Error> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        if categories.len() > MAX_CATEGORIES {
+            return Err(invalid(format!("too many categories (max {MAX_CATEGORIES})")));
+        }
+        let default = categories.iter().position(|category| category.name == DEFAULT_CATEGORY)
+            .ok_or_else(|| invalid(format!("category {DEFAULT_CATEGORY} is not defined")))?;
+        let mut sets: Vec<(u64, usize)> = vec![(1 << default, default)];
+        let mut char_ranges: Vec<(u32, u32, usize)> = Vec::new();
+        for (first, last, names) in ranges.iter() {
+            let mut ids: Vec<usize> = Vec::new();
+            for name in names.iter() {
+                ids.push(categories.iter().position(|category| category.name == *name)
+                    .ok_or_else(|| invalid(format!("category {name} is not defined")))?);
+            }
+            let set = (ids.iter().fold(0, |bits, id| bits | (1 << id)), *ids.first().ok_or_else(|| invalid(String::from("missing category")))?);
+            let set_idx = sets.iter().position(|s| *s == set).unwrap_or_else(|| {
+                sets.push(set);
+                sets.len() - 1
+            });
+            char_ranges.push((*first as u32, *last as u32, set_idx));
+        }
+        let mut char_definition = CharDefinition { categories, sets, ranges: char_ranges, bmp: Vec::new() };
+        char_definition.build_bmp();
+        Ok(char_definition)
+    }
+
+    /// 基本多言語面の文字の表を作る
+    fn build_bmp(&mut self) {
+        self.bmp = vec![0; BMP_SIZE];
+        for &(first, last, set_idx) in self.ranges.iter() {
+            for c in (first as usize)..=(last as usize).min(BMP_SIZE - 1) {
+                self.bmp[c] = set_idx as u16;
+            }
+        }
+    }
+
+    /// 文字種
+    pub fn categories(&self) -> &[CharCategory] {
+        &self.categories
+    }
+
+    /// 文字の (文字種の集合のビット, 代表の文字種のindex)
+    fn set_of(&self, c: char) -> (u64, usize) {
+        let code = c as u32;
+        let set_idx = if (code as usize) < BMP_SIZE {
+            self.bmp[code as usize] as usize
+        } else {
+            self.ranges.iter().rev().find(|&&(first, last, _)| first <= code && code <= last).map_or(0, |&(_, _, set_idx)| set_idx)
+        };
+        self.sets[set_idx]
+    }
+
+    /// 文字の代表の文字種
+    ///
+    /// # Arguments
+    ///
+    /// * `c` - 文字
+    pub fn category_of(&self, c: char) -> &CharCategory {
+        &self.categories[self.set_of(c).1]
+    }
+
+    /// 文字が文字種に含まれるかどうか
+    ///
+    /// # Arguments
+    ///
+    /// * `c`        - 文字
+    /// * `category` - 文字種の名前
+    pub fn is_category(&self, c: char, category: &str) -> bool {
+        self.categories.iter().position(|cat| cat.name == category).is_some_and(|id| self.set_of(c).0 & (1 << id) != 0)
+    }
+
+    /// 文字列の先頭の文字から作る未知語の候補の、文字種と長さ(バイト)のリストを返す
+    /// 先頭の文字の代表の文字種を含む文字が続く範囲で、文字種の group と length に従って候補を作る
+    ///
+    /// # Arguments
+    ///
+    /// * `text`            - 未知語の候補を作る位置から始まる文字列
+    /// * `max_group_chars` - group で1つの候補にする文字数の上限
+    pub(crate) fn unknown_candidates(&self, text: &str, max_group_chars: usize) -> Option<(&CharCategory, Vec<usize>)> {
+        let first = text.chars().next()?;
+        let (_, id) = self.set_of(first);
+        let category = &self.categories[id];
+        // 同じ文字種の文字が続く位置 (i文字目の終わりのバイト位置)
+        let run: Vec<usize> = text.char_indices()
+            .take_while(|&(_, c)| self.set_of(c).0 & (1 << id) != 0)
+            .take(max_group_chars + 1)
+            .map(|(i, c)| i + c.len_utf8())
+            .collect();
+        let mut lengths: Vec<usize> = run.iter().take(category.length).copied().collect();
+        if category.group && run.len() <= max_group_chars {
+            lengths.push(run[run.len() - 1]);
+        }
+        lengths.sort_unstable();
+        lengths.dedup();
+        Some((category, lengths))
+    }
+
+    /// 文字種の定義をファイルに保存する
+    ///
+    /// # Arguments
+    ///
+    /// * `output_path` - 保存先のファイルパス
+    pub fn dump(&self, output_path: &str) -> Result<(), io::Error> {
+        let bytes = bincode::serialize(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(output_path, bytes)
+    }
+
+    /// `dump` で保存した文字種の定義を読み込む
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - ファイルパス
+    pub fn from_file(path: &str) -> Result<Self, io::Error> {
+        let bytes = fs::read(path)?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid char definition");
+        let mut char_definition: CharDefinition = bincode::deserialize(&bytes).map_err(|_| invalid())?;
+        let valid = !char_definition.sets.is_empty()
+            && char_definition.sets.iter().all(|&(_, id)| id < char_definition.categories.len())
+            && char_definition.ranges.iter().all(|&(first, last, set_idx)| first <= last && set_idx < char_definition.sets.len());
+        if !valid {
+            return Err(invalid());
+        }
+        char_definition.build_bmp();
+        Ok(char_definition)
+    }
+}
+
+/// 未知語の文字種の定義と、文字種の名前をキーとする未知語の形態素の辞書 (unk.def)
+pub struct UnknownDictionary {
+    pub char_definition: CharDefinition,
+    pub double_array   : DoubleArray<Morpheme>,
+}
+
+/// MeCabの辞書のソースファイル (IPADIC, UniDic形式) を読み込む
+#[derive(Debug, Clone)]
+pub struct MecabImporter {
+    encoding: &'static Encoding,
+}
+
+impl MecabImporter {
+    pub fn new() -> Self {
+        MecabImporter { encoding: UTF_8 }
+    }
+
+    /// ソースファイルの文字コードを指定する (デフォルトはUTF-8)
+    /// IPADICは `euc-jp` を指定する。
+    ///
+    /// # Arguments
+    ///
+    /// * `label` - 文字コードのラベル
+    pub fn encoding(mut self, label: &str) -> Result<Self, ImportError> {
+        self.encoding = Encoding::for_label(label.as_bytes())
+            .ok_or_else(|| ImportError::new(None, format!("unknown encoding '{label}'")))?;
+        Ok(self)
+    }
+
+    /// 語彙のCSVファイルを読み込んでトライ木に追加し、追加した形態素の数を返す
+    /// 各行は `表層形,左文脈ID,右文脈ID,コスト,素性...` の形式
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - 語彙のCSVファイル
+    /// * `trie`   - 形態素を追加するトライ木
+    pub fn read_lexicon<R: Read>(&self, reader: R, trie: &mut Trie<Morpheme>) -> Result<usize, ImportError> {
+        self.read_lexicon_with(reader, trie, None)
+    }
+
+    /// 語彙のCSVファイルを読み込んでトライ木に追加する
+    /// 連接コスト表を指定した場合は、文脈IDが範囲内であることを検証する
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - 語彙のCSVファイル
+    /// * `trie`   - 形態素を追加するトライ木
+    /// * `matrix` - 文脈IDを検証する連接コスト表
+    fn read_lexicon_with<R: Read>(&self, reader: R, trie: &mut Trie<Morpheme>, matrix: Option<&ConnectionMatrix>) -> Result<usize, ImportError> {
+        let text = read_text(reader, self.encoding)?;
+        let mut csv_reader = csv::ReaderBuilder::new().has_headers(false).flexible(true).from_reader(text.as_bytes());
+        let mut count = 0;
+        for record in csv_reader.records() {
+            let record = record.map_err(|e| ImportError::new(e.position().map(|p| p.line()), e.to_string()))?;
+            let line = record.position().map(|position| position.line());
+            if record.len() < 5 {
+                return Err(ImportError::new(line, format!("expected at least 5 columns, found {}", record.len())));
+            }
+            let parse_error = |i: usize, column: &str| ImportError::new(line, format!("invalid {} '{}'", column, &record[i]));
+            let morpheme = Morpheme {
+                left_id : record[1].parse().map_err(|_| parse_error(1, "left id"))?,
+                right_id: record[2].parse().map_err(|_| parse_error(2, "right id"))?,
+                cost    : record[3].parse().map_err(|_| parse_error(3, "cost"))?,
+                feature : record.iter().skip(4).map(quote_field).collect::<Vec<String>>().join(","),
+            };
+            if matrix.is_some_and(|matrix| !matrix.contains(&morpheme)) {
+                return Err(ImportError::new(line, String::from("context id out of range of matrix.def")));
+            }
+            trie.set(&record[0], morpheme);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// 連接コスト表 (matrix.def) を読み込む
+    /// 1行目は `右文脈IDの数 左文脈IDの数`、2行目以降は `右文脈ID 左文脈ID コスト` の形式
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - matrix.def
+    pub fn read_matrix<R: Read>(&self, reader: R) -> Result<ConnectionMatrix, ImportError> {
+        let text = read_text(reader, self.encoding)?;
+        let mut lines = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+        let (i, header) = lines.next().ok_or_else(|| ImportError::new(Some(1), String::from("missing matrix size")))?;
+        let size: Vec<usize> = parse_columns(header, i + 1)?;
+        if size.len() != 2 {
+            return Err(ImportError::new(Some(i as u64 + 1), String::from("expected 2 columns")));
+        }
+        // 表を確保する前に、信頼できない1行目の大きさを検証する
+        if size.iter().any(|&size| size > MAX_CONTEXT_IDS) || size[0].checked_mul(size[1]).is_none() {
+            return Err(ImportError::new(Some(i as u64 + 1), format!("matrix size out of range (max {MAX_CONTEXT_IDS})")));
+        }
+        let mut matrix = ConnectionMatrix::new(size[0], size[1]);
+        for (i, line) in lines {
+            let columns: Vec<i64> = parse_columns(line, i + 1)?;
+            let error = |message: &str| ImportError::new(Some(i as u64 + 1), message.to_string());
+            if columns.len() != 3 {
+                return Err(error("expected 3 columns"));
+            }
+            let (right_id, left_id, cost) = (columns[0], columns[1], columns[2]);
+            if right_id < 0 || right_id as usize >= matrix.forward_size || left_id < 0 || left_id as usize >= matrix.backward_size {
+                return Err(error("context id out of range"));
+            }
+            if cost < i16::MIN as i64 || cost > i16::MAX as i64 {
+                return Err(error("cost out of range"));
+            }
+            matrix.set_cost(right_id as u16, left_id as u16, cost as i16);
+        }
+        Ok(matrix)
+    }
+
+    /// 文字種の定義 (char.def) を読み込む
+    /// 文字種の定義は `名前 invoke group length`、文字の範囲は `0x3041..0x3096 文字種...` の形式。`#` 以降はコメント
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - char.def
+    pub fn read_char_definition<R: Read>(&self, reader: R) -> Result<CharDefinition, ImportError> {
+        let text = read_text(reader, self.encoding)?;
+        let mut categories: Vec<CharCategory> = Vec::new();
+        let mut ranges: Vec<(char, char, Vec<&str>, u64)> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let number = i as u64 + 1;
+            let error = |message: String| ImportError::new(Some(number), message);
+            let columns: Vec<&str> = line.split('#').next().unwrap_or_default().split_whitespace().collect();
+            if columns.is_empty() {
+                continue;
+            }
+            if columns[0].starts_with("0x") {
+                let mut bounds = columns[0].split("..").map(|code| {
+                    u32::from_str_radix(&code[2.min(code.len())..], 16).ok().and_then(char::from_u32)
+                        .ok_or_else(|| error(format!("invalid code point '{code}'")))
+                });
+                let first = bounds.next().unwrap()?;
+                let last = bounds.next().transpose()?.unwrap_or(first);
+                if columns.len() < 2 || first > last {
+                    return Err(error(String::from("expected a code point range and categories")));
+                }
+                ranges.push((first, last, columns[1..].to_vec(), number));
+            } else {
+                if columns.len() != 4 {
+                    return Err(error(String::from("expected 4 columns")));
+                }
+                let flag = |column: &str| match column {
+                    "0" => Ok(false),
+                    "1" => Ok(true),
+                    _   => Err(error(format!("invalid flag '{column}'"))),
+                };
+                categories.push(CharCategory {
+                    name  : columns[0].to_string(),
+                    invoke: flag(columns[1])?,
+                    group : flag(columns[2])?,
+                    length: columns[3].parse().map_err(|_| error(format!("invalid length '{}'", columns[3])))?,
+                });
+            }
+        }
+        // 未定義の文字種は、その文字種を指定した行のエラーにする
+        for (_, _, names, number) in ranges.iter() {
+            if let Some(name) = names.iter().find(|name| categories.iter().all(|category| category.name != **name)) {
+                return Err(ImportError::new(Some(*number), format!("category {name} is not defined")));
+            }
+        }
+        let ranges: Vec<(char, char, Vec<&str>)> = ranges.into_iter().map(|(first, last, names, _)| (first, last, names)).collect();
+        CharDefinition::new(categories, &ranges).map_err(|e| ImportError::new(None, e.to_string()))
+    }
+
+    /// ソースディレクトリの char.def と unk.def を読み込む
+    /// char.def がない場合はNoneを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `source_dir` - ソースディレクトリ
+    /// * `matrix`     - 文脈IDを検証する連接コスト表
+    pub fn read_unknown_dir(&self, source_dir: &str, matrix: &ConnectionMatrix) -> Result<Option<(CharDefinition, Trie<Morpheme>)>, io::Error> {
+        let dir = Path::new(source_dir);
+        let char_def_path = dir.join("char.def");
+        if !char_def_path.exists() {
+            return Ok(None);
+        }
+        let char_definition = self.read_char_definition(File::open(&char_def_path)?).map_err(|e| file_error(&char_def_path, e))?;
+        let unk_def_path = dir.join("unk.def");
+        let mut trie = Trie::new();
+        self.read_lexicon_with(File::open(&unk_def_path)?, &mut trie, Some(matrix)).map_err(|e| file_error(&unk_def_path, e))?;
+        Ok(Some((char_definition, trie)))
+    }
+
+    /// ソースディレクトリの語彙のCSVファイル (拡張子が .csv のファイル) と matrix.def を読み込む
+    /// CSVファイルはファイル名の順に読み込む
+    ///
+    /// # Arguments
+    ///
+    /// * `source_dir` - ソースディレクトリ
+    pub fn read_dir(&self, source_dir: &str) -> Result<(Trie<Morpheme>, ConnectionMatrix), io::Error> {
+        let dir = Path::new(source_dir);
+        let matrix_path = dir.join("matrix.def");
+        let matrix = self.read_matrix(File::open(&matrix_path)?).map_err(|e| file_error(&matrix_path, e))?;
+        let mut lexicon_paths: Vec<PathBuf> = fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<PathBuf>, io::Error>>()?;
+        lexicon_paths.retain(|path| path.extension().is_some_and(|extension| extension == "csv"));
+        lexicon_paths.sort();
+        let mut trie = Trie::new();
+        for path in lexicon_paths.iter() {
+            self.read_lexicon_with(File::open(path)?, &mut trie, Some(&matrix)).map_err(|e| file_error(path, e))?;
+        }
+        Ok((trie, matrix))
+    }
+}
+
+impl Default for MecabImporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 形態素のダブル配列と連接コスト表、未知語辞書
+pub struct MecabDictionary {
+    pub double_array: DoubleArray<Morpheme>,
+    pub matrix      : ConnectionMatrix,
+    /// ソースディレクトリに char.def がない場合はNone
+    pub unknown     : Option<UnknownDictionary>,
+}
+
+impl MecabDictionary {
+    /// MeCabの辞書のソースディレクトリから辞書を構築する
+    /// char.def と unk.def がある場合は未知語辞書も構築する
+    ///
+    /// # Arguments
+    ///
+    /// * `source_dir` - ソースディレクトリ
+    /// * `importer`   - ソースファイルの読み込みのオプション
+    /// * `builder`    - ダブル配列の構築のオプション
+    pub fn from_source_dir(source_dir: &str, importer: &MecabImporter, builder: &DoubleArrayBuilder) -> Result<Self, io::Error> {
+        let (trie, matrix) = importer.read_dir(source_dir)?;
+        let double_array = builder.build(trie)?;
+        let unknown = match importer.read_unknown_dir(source_dir, &matrix)? {
+            Some((char_definition, trie)) => Some(UnknownDictionary { char_definition, double_array: builder.build(trie)? }),
+            None => None,
+        };
+        Ok(MecabDictionary { double_array, matrix, unknown })
+    }
+
+    /// `dump` で保存した辞書を読み込む
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - 辞書を保存したディレクトリ
+    pub fn from_dir(dir: &str) -> Result<Self, io::Error> {
+        let dir = Path::new(dir);
+        let double_array = DoubleArray::from_file(&path_str(&dir.join(SYSTEM_DICTIONARY_FILE))?)?;
+        let matrix = ConnectionMatrix::from_file(&path_str(&dir.join(MATRIX_FILE))?)?;
+        let char_def_path = dir.join(CHAR_DEFINITION_FILE);
+        let unknown = if char_def_path.exists() {
+            Some(UnknownDictionary {
+                char_definition: CharDefinition::from_file(&path_str(&char_def_path)?)?,
+                double_array   : DoubleArray::from_file(&path_str(&dir.join(UNKNOWN_DICTIONARY_FILE))?)?,
+            })
+        } else {
+            None
+        };
+        Ok(MecabDictionary { double_array, matrix, unknown })
+    }
+
+    /// ディレクトリにダブル配列 (sys.dic) と連接コスト表 (matrix.bin) を保存する
+    /// 未知語辞書がある場合は文字種の定義 (char.bin) と未知語辞書 (unk.dic) も保存する
+    ///
+    /// # Arguments
+    ///
+    /// * `output_dir` - 保存先のディレクトリ。存在しない場合は作成する
+    pub fn dump(self, output_dir: &str) -> Result<Self, io::Error> {
+        let dir = Path::new(output_dir);
+        fs::create_dir_all(dir)?;
+        let double_array = self.double_array.dump(&path_str(&dir.join(SYSTEM_DICTIONARY_FILE))?)?;
+        self.matrix.dump(&path_str(&dir.join(MATRIX_FILE))?)?;
+        let unknown = match self.unknown {
+            Some(unknown) => {
+                unknown.char_definition.dump(&path_str(&dir.join(CHAR_DEFINITION_FILE))?)?;
+                let double_array = unknown.double_array.dump(&path_str(&dir.join(UNKNOWN_DICTIONARY_FILE))?)?;
+                Some(UnknownDictionary { char_definition: unknown.char_definition, double_array })
+            },
+            None => None,
+        };
+        Ok(MecabDictionary { double_array, matrix: self.matrix, unknown })
+    }
+}
+
+/// ソースファイルを読み込んでUTF-8の文字列に変換する
+fn read_text<R: Read>(mut reader: R, encoding: &'static Encoding) -> Result<String, ImportError> {
+    let mut bytes: Vec<u8> = Vec::new();
+    reader.read_to_end(&mut bytes).map_err(|e| ImportError::new(None, e.to_string()))?;
+    decode(&bytes, encoding).map(|text| text.into_owned())
+}
+
+/// 空白区切りの数値の列を読み込む
+///
+/// # Arguments
+///
+/// * `line`   - 行
+/// * `number` - 行番号
+fn parse_columns<N: std::str::FromStr>(line: &str, number: usize) -> Result<Vec<N>, ImportError> {
+    line.split_whitespace()
+        .map(|column| column.parse().map_err(|_| ImportError::new(Some(number as u64), format!("invalid number '{column}'"))))
+        .collect()
+}
+
+/// 素性の列を、カンマや引用符を含む場合は引用符で囲んで返す
+fn quote_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// 読み込みのエラーにファイルパスを付ける
+fn file_error(path: &Path, e: ImportError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+}
+
+/// パスを文字列に変換する
+fn path_str(path: &Path) -> Result<String, io::Error> {
+    path.to_str()
+        .map(|path| path.to_string())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid path: {}", path.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_lexicon() {
+        let source = "東京,1,2,3000,名詞,固有名詞,地域,一般,*,*,東京,トウキョウ,トーキョー\n\",\",5,5,-100,補助記号,読点,*,*,*,*,\",\",\",\"\n";
+        let mut trie: Trie<Morpheme> = Trie::new();
+        assert_eq!(2, MecabImporter::new().read_lexicon(source.as_bytes(), &mut trie).unwrap());
+        let expected = Morpheme {
+            left_id : 1,
+            right_id: 2,
+            cost    : 3000,
+            feature : String::from("名詞,固有名詞,地域,一般,*,*,東京,トウキョウ,トーキョー"),
+        };
+        assert_eq!(Some(&[expected][..]), trie.get("東京"));
+        // カンマを含む素性は引用符で囲む
+        assert_eq!("補助記号,読点,*,*,*,*,\",\",\",\"", trie.get(",").unwrap()[0].feature);
+
+        // EUC-JP
+        let (bytes, _, _) = encoding_rs::EUC_JP.encode("京都,1,1,3000,名詞\n");
+        MecabImporter::new().encoding("euc-jp").unwrap().read_lexicon(&bytes[..], &mut trie).unwrap();
+        assert_eq!("名詞", trie.get("京都").unwrap()[0].feature);
+
+        // 不正な行
+        let e = MecabImporter::new().read_lexicon("a,1,1,1,名詞\nb,1,x,1,名詞\n".as_bytes(), &mut trie).unwrap_err();
+        assert_eq!(Some(2), e.line());
+        let e = MecabImporter::new().read_lexicon("a,1,1,1\n".as_bytes(), &mut trie).unwrap_err();
+        assert_eq!(Some(1), e.line());
+    }
+
+    #[test]
+    fn test_read_matrix() {
+        let matrix = MecabImporter::new().read_matrix("2 3\n0 0 10\n1 2 -20\n\n".as_bytes()).unwrap();
+        assert_eq!(2, matrix.forward_size());
+        assert_eq!(3, matrix.backward_size());
+        assert_eq!(10, matrix.cost(0, 0));
+        assert_eq!(-20, matrix.cost(1, 2));
+        assert_eq!(0, matrix.cost(1, 0));
+
+        let e = MecabImporter::new().read_matrix("2 3\n0 0 10\n2 0 1\n".as_bytes()).unwrap_err();
+        assert_eq!(Some(3), e.line());
+        let e = MecabImporter::new().read_matrix("2 3\n0 0 40000\n".as_bytes()).unwrap_err();
+        assert_eq!(Some(2), e.line());
+        assert!(MecabImporter::new().read_matrix("".as_bytes()).is_err());
+        // 文脈IDの数が上限を超える場合は表を確保しない
+        let e = MecabImporter::new().read_matrix("65537 1\n".as_bytes()).unwrap_err();
+        assert_eq!(Some(1), e.line());
+        let e = MecabImporter::new().read_matrix("18446744073709551615 2\n".as_bytes()).unwrap_err();
+        assert_eq!(Some(1), e.line());
+    }
+
+    #[test]
+    fn test_read_char_definition() {
+        let source = "\
+# 文字種の定義
+DEFAULT  0 1 0
+KANJI    0 0 2
+KATAKANA 1 1 2
+NUMERIC  1 1 0  # 数字
+
+0x0030..0x0039 NUMERIC
+0x30A1..0x30FF KATAKANA
+0x30FC         KATAKANA NUMERIC
+0x4E00..0x9FFF KANJI
+";
+        let char_definition = MecabImporter::new().read_char_definition(source.as_bytes()).unwrap();
+        assert_eq!(4, char_definition.categories().len());
+        assert_eq!("KANJI", char_definition.category_of('東').name);
+        assert_eq!("DEFAULT", char_definition.category_of('あ').name);
+        assert_eq!("DEFAULT", char_definition.category_of('😀').name);
+        assert!(char_definition.is_category('ー', "NUMERIC"));
+        assert!(!char_definition.is_category('ス', "NUMERIC"));
+
+        // 長さ1, 2文字と、同じ文字種の連続 (ー は KATAKANA を含む)
+        let (category, lengths) = char_definition.unknown_candidates("スーパー1", 24).unwrap();
+        assert_eq!("KATAKANA", category.name);
+        assert_eq!(vec![3, 6, 12], lengths);
+        let (_, lengths) = char_definition.unknown_candidates("123ー", 24).unwrap();
+        assert_eq!(vec![6], lengths);
+        // 連続が上限を超える場合はまとめない
+        let (_, lengths) = char_definition.unknown_candidates("12345", 3).unwrap();
+        assert_eq!(Vec::<usize>::new(), lengths);
+        assert!(char_definition.unknown_candidates("", 24).is_none());
+
+        let e = MecabImporter::new().read_char_definition("DEFAULT 0 1 0\n0x0030 NUMERIC\n".as_bytes()).unwrap_err();
+        assert_eq!(Some(2), e.line());
+        let e = MecabImporter::new().read_char_definition("DEFAULT 0 2 0\n".as_bytes()).unwrap_err();
+        assert_eq!(Some(1), e.line());
+        assert!(MecabImporter::new().read_char_definition("KANJI 0 0 2\n".as_bytes()).is_err());
+    }
+}
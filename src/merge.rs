@@ -0,0 +1,110 @@
+use std::fmt::Debug;
+use std::iter::Peekable;
+
+use crate::double_array::{DoubleArray, Iter};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// 複数の辞書をまとめるときに、同じキーに複数の辞書の値がある場合の扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// すべての辞書の値を、辞書を渡した順に連結する
+    Concatenate,
+    /// 値を持つ辞書のうち最初の辞書の値を使う
+    PreferFirst,
+    /// 値を持つ辞書のうち最後の辞書の値を使う
+    PreferLast,
+}
+
+/// 複数の辞書のキーと値を、キーの辞書順に併合しながら列挙する
+///
+/// # Arguments
+///
+/// * `inputs` - まとめる辞書
+/// * `policy` - 同じキーの値の扱い
+pub(crate) fn merge_entries<'a, T>(inputs: &[&'a DoubleArray<T>], policy: MergePolicy) -> MergeIter<'a, T>
+    where T: Serialize + DeserializeOwned + Debug,
+{
+    MergeIter {
+        iters: inputs.iter().map(|double_array| double_array.iter().peekable()).collect(),
+        policy,
+    }
+}
+
+/// 複数の辞書のキーと値を併合するイテレータ
+/// 各辞書はキーの辞書順に列挙されるので、先頭のキーが最小の辞書から取り出せば全体も辞書順になる
+pub(crate) struct MergeIter<'a, T>
+    where T: Serialize + DeserializeOwned + Debug,
+{
+    iters : Vec<Peekable<Iter<'a, T>>>,
+    policy: MergePolicy,
+}
+
+impl<'a, T> Iterator for MergeIter<'a, T>
+    where T: Serialize + DeserializeOwned + Debug,
+{
+    type Item = (String, Vec<T>);
+
+    fn next(&mut self) -> Option<(String, Vec<T>)> {
+        let key = self.iters.iter_mut()
+            .filter_map(|iter| iter.peek().map(|(key, _)| key.clone()))
+            .min()?;
+        // 最小のキーを持つ辞書の値を、辞書を渡した順に取り出す
+        let mut found: Vec<Vec<T>> = Vec::new();
+        for iter in self.iters.iter_mut() {
+            if iter.peek().is_some_and(|(k, _)| *k == key) {
+                found.push(iter.next().unwrap().1);
+            }
+        }
+        let values = match self.policy {
+            MergePolicy::Concatenate => found.into_iter().flatten().collect(),
+            MergePolicy::PreferFirst => found.into_iter().next().unwrap(),
+            MergePolicy::PreferLast  => found.into_iter().next_back().unwrap(),
+        };
+        Some((key, values))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trie::Trie;
+
+    fn to_double_array(entries: &[(&str, u32)]) -> DoubleArray<u32> {
+        let mut trie: Trie<u32> = Trie::new();
+        for &(key, value) in entries {
+            trie.set(key, value);
+        }
+        trie.to_double_array().unwrap()
+    }
+
+    #[test]
+    fn test_merge_entries() {
+        let first = to_double_array(&[("abc", 1), ("abc", 2), ("b", 3), ("合沢", 4)]);
+        let second = to_double_array(&[("ab", 10), ("abc", 11), ("合沢", 12)]);
+        let third = to_double_array(&[("abc", 20), ("z", 21)]);
+        let inputs = [&first, &second, &third];
+
+        let entries: Vec<(String, Vec<u32>)> = merge_entries(&inputs, MergePolicy::Concatenate).collect();
+        let expected: Vec<(String, Vec<u32>)> = vec![
+            (String::from("ab")  , vec![10]),
+            (String::from("abc") , vec![1, 2, 11, 20]),
+            (String::from("b")   , vec![3]),
+            (String::from("z")   , vec![21]),
+            (String::from("合沢"), vec![4, 12]),
+        ];
+        assert_eq!(expected, entries);
+
+        let entries: Vec<(String, Vec<u32>)> = merge_entries(&inputs, MergePolicy::PreferFirst).collect();
+        assert_eq!((String::from("abc"), vec![1, 2]), entries[1]);
+        assert_eq!((String::from("合沢"), vec![4]), entries[4]);
+
+        let entries: Vec<(String, Vec<u32>)> = merge_entries(&inputs, MergePolicy::PreferLast).collect();
+        assert_eq!((String::from("abc"), vec![20]), entries[1]);
+        assert_eq!((String::from("合沢"), vec![12]), entries[4]);
+
+        // 辞書がない
+        assert_eq!(0, merge_entries::<u32>(&[], MergePolicy::Concatenate).count());
+    }
+}
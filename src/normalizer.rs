@@ -0,0 +1,277 @@
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use crate::format::invalid_data;
+
+use caseless::Caseless;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::{canonical_combining_class, decompose_compatible};
+
+/// メタデータに格納する、正規化の種類の名前
+const NFKC: &str = "nfkc";
+const CASE_FOLD: &str = "case_fold";
+const WIDTH_FOLD: &str = "width_fold";
+const KANA_FOLD: &str = "kana_fold";
+
+/// キーの正規化
+/// 構築時に登録するキーを、検索時にクエリを正規化するので、表記の揺れがあっても同じキーとして検索できる。
+/// 正規化は NFKC, 幅の統一, 大文字小文字の統一, カタカナのひらがなへの統一 の順に行う。
+///
+/// # Examples
+///
+/// ```
+/// use dary::{DoubleArrayBuilder, Normalizer, Trie};
+///
+/// let mut trie: Trie<u32> = Trie::new();
+/// trie.set("ＡＢＣ", 1);
+/// trie.set("ｶﾞｽ", 2);
+///
+/// let normalizer = Normalizer::new().width_fold(true).case_fold(true).kana_fold(true);
+/// let double_array = DoubleArrayBuilder::new().normalizer(normalizer).build(trie).unwrap();
+/// assert_eq!(vec![1], double_array.get("abc").unwrap());
+/// assert_eq!(vec![2], double_array.get("がす").unwrap());
+/// // 一致した長さは元のクエリのバイト位置で返す
+/// assert_eq!("ｶﾞｽ", double_array.prefix_search("ｶﾞｽ管")[0].0);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Normalizer {
+    nfkc      : bool,
+    case_fold : bool,
+    width_fold: bool,
+    kana_fold : bool,
+}
+
+impl Normalizer {
+    /// 何も変換しない正規化を作る
+    pub fn new() -> Self {
+        Normalizer::default()
+    }
+
+    /// Unicode の NFKC 正規化を行う
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - NFKC 正規化を行うかどうか
+    pub fn nfkc(mut self, enabled: bool) -> Self {
+        self.nfkc = enabled;
+        self
+    }
+
+    /// Unicode の大文字小文字の統一 (case folding) を行う
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - 大文字小文字を統一するかどうか
+    pub fn case_fold(mut self, enabled: bool) -> Self {
+        self.case_fold = enabled;
+        self
+    }
+
+    /// 全角英数記号を半角に、半角カタカナを全角に統一する
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - 文字幅を統一するかどうか
+    pub fn width_fold(mut self, enabled: bool) -> Self {
+        self.width_fold = enabled;
+        self
+    }
+
+    /// カタカナをひらがなに統一する
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - カタカナをひらがなに統一するかどうか
+    pub fn kana_fold(mut self, enabled: bool) -> Self {
+        self.kana_fold = enabled;
+        self
+    }
+
+    /// 何も変換しないかどうか
+    pub fn is_identity(&self) -> bool {
+        !(self.nfkc || self.case_fold || self.width_fold || self.kana_fold)
+    }
+
+    /// 文字列を正規化する
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - 正規化する文字列
+    pub fn normalize(&self, text: &str) -> String {
+        let mut text: String = if self.nfkc { text.nfkc().collect() } else { text.to_string() };
+        if self.width_fold {
+            text = fold_width(&text);
+        }
+        if self.case_fold {
+            text = text.chars().default_case_fold().collect();
+        }
+        if self.kana_fold {
+            text = text.chars().map(fold_kana).collect();
+        }
+        text
+    }
+
+    /// 文字列のstartから始まり、前後と独立に正規化できる最短の区間の終わりを返す
+    /// 区間ごとに正規化した結果を連結すると、文字列全体を正規化した結果と一致する。
+    /// 結合文字や半角の濁点は前の文字と同じ区間になる。
+    ///
+    /// # Arguments
+    ///
+    /// * `text`  - 文字列
+    /// * `start` - 区間の始まり (文字の境界)
+    pub(crate) fn next_segment(&self, text: &str, start: usize) -> usize {
+        let mut end = next_starter(text, start);
+        while end < text.len() {
+            // 区間の後ろの文字と合成される場合は区間を延ばす
+            let next_end = next_starter(text, end);
+            let mut separate = self.normalize(&text[start..end]);
+            separate.push_str(&self.normalize(&text[end..next_end]));
+            if separate == self.normalize(&text[start..next_end]) {
+                break;
+            }
+            end = next_end;
+        }
+        end
+    }
+}
+
+/// 正規化の種類の名前 (`nfkc`, `case_fold`, `width_fold`, `kana_fold`) をカンマで区切った文字列
+/// 辞書ファイルのメタデータにはこの形式で記録する
+impl fmt::Display for Normalizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = [(self.nfkc, NFKC), (self.case_fold, CASE_FOLD), (self.width_fold, WIDTH_FOLD), (self.kana_fold, KANA_FOLD)]
+            .iter()
+            .filter(|&&(enabled, _)| enabled)
+            .map(|&(_, name)| name)
+            .collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+impl FromStr for Normalizer {
+    type Err = io::Error;
+
+    fn from_str(value: &str) -> Result<Self, io::Error> {
+        let mut normalizer = Normalizer::new();
+        for name in value.split(',').filter(|name| !name.is_empty()) {
+            match name {
+                NFKC       => normalizer.nfkc = true,
+                CASE_FOLD  => normalizer.case_fold = true,
+                WIDTH_FOLD => normalizer.width_fold = true,
+                KANA_FOLD  => normalizer.kana_fold = true,
+                _ => return Err(invalid_data(&format!("unknown normalizer '{name}'"))),
+            }
+        }
+        Ok(normalizer)
+    }
+}
+
+/// startの次の文字から、結合文字などの前の文字に続く文字を読み飛ばした位置を返す
+///
+/// # Arguments
+///
+/// * `text`  - 文字列
+/// * `start` - 読み始める位置 (文字の境界)
+fn next_starter(text: &str, start: usize) -> usize {
+    let mut chars = text[start..].char_indices().skip(1);
+    for (i, c) in &mut chars {
+        // 互換分解の先頭が結合文字なら、前の文字に続く
+        let mut combining = false;
+        let mut first = true;
+        decompose_compatible(c, |d| {
+            if first {
+                combining = canonical_combining_class(d) != 0;
+                first = false;
+            }
+        });
+        if !combining {
+            return start + i;
+        }
+    }
+    text.len()
+}
+
+/// 全角英数記号と和字間隔を半角に、半角カタカナを全角にする
+///
+/// # Arguments
+///
+/// * `text` - 文字列
+fn fold_width(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    let mut voiced = false;
+    for c in text.chars() {
+        match c {
+            '\u{3000}' | '\u{FF01}'..='\u{FFEE}' => {
+                voiced |= c == '\u{FF9E}' || c == '\u{FF9F}';
+                folded.extend(std::iter::once(c).nfkc());
+            },
+            _ => folded.push(c),
+        }
+    }
+    // 半角の濁点・半濁点は結合文字になるので、前の文字と合成する
+    if voiced { folded.nfc().collect() } else { folded }
+}
+
+/// カタカナをひらがなにする
+///
+/// # Arguments
+///
+/// * `c` - 文字
+fn fold_kana(c: char) -> char {
+    match c {
+        'ァ'..='ヶ' | 'ヽ' | 'ヾ' => std::char::from_u32(c as u32 - 0x60).unwrap(),
+        _ => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let nfkc = Normalizer::new().nfkc(true);
+        assert_eq!("ガABC1", nfkc.normalize("ｶﾞＡＢＣ①"));
+        assert_eq!("平成", nfkc.normalize("㍻"));
+
+        let width = Normalizer::new().width_fold(true);
+        assert_eq!("ガス ABC①", width.normalize("ｶﾞｽ　ＡＢＣ①"));
+
+        let case_fold = Normalizer::new().case_fold(true);
+        assert_eq!("strasse", case_fold.normalize("STRAßE"));
+
+        let kana = Normalizer::new().kana_fold(true);
+        assert_eq!("すもも ゞ", kana.normalize("スモモ ヾ"));
+
+        assert!(Normalizer::new().is_identity());
+        assert_eq!("ＡＢＣ", Normalizer::new().normalize("ＡＢＣ"));
+    }
+
+    #[test]
+    fn test_next_segment() {
+        let normalizer = Normalizer::new().width_fold(true).kana_fold(true);
+        let text = "ｶﾞｽか\u{3099}A";
+        let mut ends: Vec<usize> = Vec::new();
+        let mut start = 0;
+        while start < text.len() {
+            start = normalizer.next_segment(text, start);
+            ends.push(start);
+        }
+        // ｶﾞ, ｽ, か+濁点, A
+        assert_eq!(vec![6, 9, 15, 16], ends);
+
+        // ハングルの字母は合成されるので1つの区間になる
+        let nfkc = Normalizer::new().nfkc(true);
+        assert_eq!(6, nfkc.next_segment("\u{1100}\u{1161}", 0));
+    }
+
+    #[test]
+    fn test_parse() {
+        let normalizer = Normalizer::new().nfkc(true).kana_fold(true);
+        assert_eq!("nfkc,kana_fold", normalizer.to_string());
+        assert_eq!(normalizer, normalizer.to_string().parse().unwrap());
+        assert_eq!(Normalizer::new(), "".parse().unwrap());
+        assert!("nfkc,unknown".parse::<Normalizer>().is_err());
+    }
+}
@@ -0,0 +1,24 @@
+use std::time::Duration;
+
+/// ダブル配列の統計情報
+/// `DoubleArrayBuilder::build_with_stats` で構築時に、`DoubleArray::stats` で構築済みの辞書から取得する
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildStats {
+    /// base/check配列で使用している要素数 (ルートを含む)
+    pub node_count: usize,
+    /// 値を持つキーの数
+    pub key_count: usize,
+    /// 値の数
+    pub value_count: usize,
+    /// base配列, check配列の長さ
+    pub array_len: usize,
+    /// base/check配列の充填率 (node_count / array_len)
+    pub fill_ratio: f64,
+    /// tail配列のバイト数
+    pub tail_size: usize,
+    /// data配列のバイト数
+    pub data_size: usize,
+    /// base値の探索に費やした時間。複数スレッドで構築した場合は各スレッドの合計
+    /// ファイルから読み込んだ辞書ではNone
+    pub find_base_time: Option<Duration>,
+}
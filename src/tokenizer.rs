@@ -0,0 +1,528 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io;
+use std::sync::{Arc, RwLock};
+
+use crate::double_array::DoubleArray;
+use crate::format::invalid_data;
+use crate::mecab::{ConnectionMatrix, MecabDictionary, Morpheme, UnknownDictionary};
+use crate::trie::Trie;
+
+/// 未知語辞書で候補を作れない文字の単語コスト
+/// この場合の未知語は1文字ずつ、文脈ID 0 (文頭・文末と同じ) の形態素として扱う
+pub const UNKNOWN_WORD_COST: i16 = 10000;
+
+/// 文字種の group で1つの未知語にする文字数の上限
+const MAX_GROUPING_CHARS: usize = 24;
+
+/// 文頭・文末の文脈ID
+const BOS_EOS_ID: u16 = 0;
+
+/// 形態素の出どころ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// システム辞書の形態素
+    System,
+    /// ユーザー辞書の形態素
+    User,
+    /// 辞書に見つからなかった未知語
+    Unknown,
+}
+
+/// 形態素解析の結果の1形態素
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token<'t> {
+    /// 表層形
+    pub surface: &'t str,
+    /// 入力文字列での開始位置 (バイト)
+    pub start: usize,
+    /// 入力文字列での終了位置 (バイト)
+    pub end: usize,
+    /// 形態素
+    pub morpheme: Morpheme,
+    /// 形態素の出どころ
+    pub kind: TokenKind,
+}
+
+/// 形態素の列とそのコスト (単語コストと連接コストの合計)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segmentation<'t> {
+    pub tokens: Vec<Token<'t>>,
+    pub cost  : i64,
+}
+
+/// システム辞書と一緒に引くユーザー辞書
+/// 別に構築した小さなダブル配列か、メモリ上のトライ木を使う
+pub enum UserDictionary {
+    DoubleArray(DoubleArray<Morpheme>),
+    Trie(Trie<Morpheme>),
+}
+
+impl UserDictionary {
+    /// 文字列の先頭に一致する形態素を、一致した長さ(バイト)とともに返す
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - 探索する文字列
+    fn prefix_search(&self, text: &str) -> Vec<(usize, Vec<Morpheme>)> {
+        match self {
+            UserDictionary::DoubleArray(double_array) => {
+                double_array.prefix_search_iter(text).map(|(prefix, morphemes)| (prefix.len(), morphemes)).collect()
+            },
+            UserDictionary::Trie(trie) => {
+                trie.prefix_search_iter(text).map(|(prefix, morphemes)| (prefix.len(), morphemes.to_vec())).collect()
+            },
+        }
+    }
+}
+
+impl UserDictionary {
+    /// すべての形態素の文脈IDが連接コスト表の範囲に収まっているかを検証する
+    /// 範囲外の文脈IDは連接コストの参照でpanicするか、別の組のコストを返すので、辞書を設定する前に拒否する
+    ///
+    /// # Arguments
+    ///
+    /// * `matrix` - システム辞書の連接コスト表
+    fn validate(&self, matrix: &ConnectionMatrix) -> Result<(), io::Error> {
+        let invalid = |key: &str| invalid_data(&format!("context id of user dictionary entry '{key}' is out of range of the connection matrix"));
+        match self {
+            UserDictionary::DoubleArray(double_array) => {
+                for (key, morphemes) in double_array.iter() {
+                    if !morphemes.iter().all(|morpheme| matrix.contains(morpheme)) {
+                        return Err(invalid(&key));
+                    }
+                }
+            },
+            UserDictionary::Trie(trie) => {
+                for (key, morphemes) in trie.iter() {
+                    if !morphemes.iter().all(|morpheme| matrix.contains(morpheme)) {
+                        return Err(invalid(&key));
+                    }
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+impl From<DoubleArray<Morpheme>> for UserDictionary {
+    fn from(double_array: DoubleArray<Morpheme>) -> Self {
+        UserDictionary::DoubleArray(double_array)
+    }
+}
+
+impl From<Trie<Morpheme>> for UserDictionary {
+    fn from(trie: Trie<Morpheme>) -> Self {
+        UserDictionary::Trie(trie)
+    }
+}
+
+/// ラティスのノード
+struct Node {
+    start   : usize,
+    end     : usize,
+    morpheme: Morpheme,
+    kind    : TokenKind,
+    /// 文頭からこのノードまでの最小コスト (このノードの単語コストを含む)
+    total   : i64,
+    /// 最小コストの経路での前のノード
+    prev    : usize,
+}
+
+/// 入力文字列のラティス
+/// nodes[0] は文頭、最後のノードは文末
+struct Lattice {
+    nodes: Vec<Node>,
+    /// ends[i] は位置 i で終わるノードのindex
+    ends : Vec<Vec<usize>>,
+}
+
+/// ダブル配列の辞書と連接コスト表で、Viterbiアルゴリズムによる形態素解析を行う
+///
+/// # Examples
+///
+/// ```
+/// use dary::Trie;
+/// use dary::mecab::{ConnectionMatrix, Morpheme};
+/// use dary::tokenizer::Tokenizer;
+///
+/// let mut trie: Trie<Morpheme> = Trie::new();
+/// trie.set("東京", Morpheme { left_id: 1, right_id: 1, cost: 3000, feature: String::from("名詞") });
+/// trie.set("京都", Morpheme { left_id: 1, right_id: 1, cost: 3000, feature: String::from("名詞") });
+/// trie.set("都", Morpheme { left_id: 1, right_id: 1, cost: 5000, feature: String::from("名詞") });
+///
+/// let tokenizer = Tokenizer::new(trie.to_double_array().unwrap(), ConnectionMatrix::new(2, 2));
+/// let tokens = tokenizer.tokenize("東京都");
+/// assert_eq!(vec!["東京", "都"], tokens.iter().map(|token| token.surface).collect::<Vec<&str>>());
+/// assert_eq!((6, 9), (tokens[1].start, tokens[1].end));
+/// ```
+pub struct Tokenizer {
+    double_array: DoubleArray<Morpheme>,
+    matrix      : ConnectionMatrix,
+    unknown     : Option<UnknownDictionary>,
+    /// 解析中の他のスレッドに影響せずに差し替えられるように、Arcで持つ
+    user        : RwLock<Option<Arc<UserDictionary>>>,
+}
+
+impl Tokenizer {
+    /// # Arguments
+    ///
+    /// * `double_array` - 形態素の辞書
+    /// * `matrix`       - 連接コスト表
+    pub fn new(double_array: DoubleArray<Morpheme>, matrix: ConnectionMatrix) -> Self {
+        Tokenizer { double_array, matrix, unknown: None, user: RwLock::new(None) }
+    }
+
+    /// # Arguments
+    ///
+    /// * `dictionary` - MeCab形式の辞書
+    pub fn from_dictionary(dictionary: MecabDictionary) -> Self {
+        let tokenizer = Tokenizer::new(dictionary.double_array, dictionary.matrix);
+        match dictionary.unknown {
+            Some(unknown) => tokenizer.unknown(unknown),
+            None => tokenizer,
+        }
+    }
+
+    /// 文字種による未知語の候補を作る未知語辞書を指定する
+    /// 指定しない場合は、辞書に見つからない文字を1文字ずつ未知語にする
+    ///
+    /// # Arguments
+    ///
+    /// * `unknown` - 未知語辞書
+    pub fn unknown(mut self, unknown: UnknownDictionary) -> Self {
+        self.unknown = Some(unknown);
+        self
+    }
+
+    /// ユーザー辞書を指定する
+    /// 連接コスト表の範囲外の文脈IDを持つ形態素がある場合はエラーを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - ユーザー辞書
+    pub fn user(self, user: UserDictionary) -> Result<Self, io::Error> {
+        self.set_user_dictionary(Some(Arc::new(user)))?;
+        Ok(self)
+    }
+
+    /// ユーザー辞書を差し替え、差し替える前のユーザー辞書を返す
+    /// 解析中の呼び出しは、呼び出し開始時のユーザー辞書で解析を続ける。
+    /// 連接コスト表の範囲外の文脈IDを持つ形態素がある場合はエラーを返し、ユーザー辞書は差し替えない
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - 新しいユーザー辞書。Noneの場合はユーザー辞書を使わない
+    pub fn set_user_dictionary(&self, user: Option<Arc<UserDictionary>>) -> Result<Option<Arc<UserDictionary>>, io::Error> {
+        if let Some(user) = user.as_ref() {
+            user.validate(&self.matrix)?;
+        }
+        let mut current = self.user.write().unwrap_or_else(|e| e.into_inner());
+        Ok(std::mem::replace(&mut *current, user))
+    }
+
+    /// 現在のユーザー辞書
+    pub fn user_dictionary(&self) -> Option<Arc<UserDictionary>> {
+        self.user.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// コストが最小の形態素の列を返す
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - 解析する文字列
+    pub fn tokenize<'t>(&self, text: &'t str) -> Vec<Token<'t>> {
+        let lattice = self.build_lattice(text);
+        let mut path: Vec<usize> = Vec::new();
+        let mut idx = lattice.nodes[lattice.nodes.len() - 1].prev;
+        while idx != 0 {
+            path.push(idx);
+            idx = lattice.nodes[idx].prev;
+        }
+        path.iter().rev().map(|&idx| to_token(text, &lattice.nodes[idx])).collect()
+    }
+
+    /// コストの小さい順に最大n個の形態素の列を返す
+    /// 前向きのViterbiで求めた各ノードまでの最小コストを見積もりとして、文末から後ろ向きにA*探索する
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - 解析する文字列
+    /// * `n`    - 返す形態素の列の最大数
+    pub fn tokenize_nbest<'t>(&self, text: &'t str, n: usize) -> Vec<Segmentation<'t>> {
+        let lattice = self.build_lattice(text);
+        let nodes = &lattice.nodes;
+        let eos = nodes.len() - 1;
+        // (ノード, 経路で次のノードの探索状態のindex, 文末までのコスト)
+        let mut states: Vec<(usize, Option<usize>, i64)> = vec![(eos, None, 0)];
+        let mut heap: BinaryHeap<Reverse<(i64, usize)>> = BinaryHeap::new();
+        heap.push(Reverse((nodes[eos].total, 0)));
+        let mut ret: Vec<Segmentation<'t>> = Vec::new();
+        while ret.len() < n {
+            let Reverse((cost, state_idx)) = match heap.pop() {
+                Some(entry) => entry,
+                None => break,
+            };
+            let (node_idx, _, backward) = states[state_idx];
+            if node_idx == 0 {
+                // 文頭に達したので、文末に向かって経路をたどる
+                let mut tokens: Vec<Token<'t>> = Vec::new();
+                let mut next = states[state_idx].1;
+                while let Some(state) = next {
+                    if states[state].0 != eos {
+                        tokens.push(to_token(text, &nodes[states[state].0]));
+                    }
+                    next = states[state].1;
+                }
+                ret.push(Segmentation { tokens, cost });
+                continue;
+            }
+            let node = &nodes[node_idx];
+            for &prev_idx in lattice.ends[node.start].iter() {
+                let prev = &nodes[prev_idx];
+                let backward = backward + node.morpheme.cost as i64 + self.connection_cost(prev, node);
+                states.push((prev_idx, Some(state_idx), backward));
+                heap.push(Reverse((prev.total + backward, states.len() - 1)));
+            }
+        }
+        ret
+    }
+
+    /// ラティスを構築し、各ノードまでの最小コストを求める
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - 解析する文字列
+    fn build_lattice(&self, text: &str) -> Lattice {
+        let bos = Node { start: 0, end: 0, morpheme: bos_eos(), kind: TokenKind::System, total: 0, prev: 0 };
+        let mut lattice = Lattice { nodes: vec![bos], ends: vec![Vec::new(); text.len() + 1] };
+        lattice.ends[0].push(0);
+        let user = self.user_dictionary();
+        for (start, c) in text.char_indices() {
+            // 到達できない位置から始まるノードは作らない
+            if lattice.ends[start].is_empty() {
+                continue;
+            }
+            let mut found = false;
+            for (prefix, morphemes) in self.double_array.prefix_search_iter(&text[start..]) {
+                for morpheme in morphemes {
+                    self.add_node(&mut lattice, start, start + prefix.len(), morpheme, TokenKind::System);
+                    found = true;
+                }
+            }
+            if let Some(user) = user.as_ref() {
+                for (len, morphemes) in user.prefix_search(&text[start..]) {
+                    for morpheme in morphemes {
+                        self.add_node(&mut lattice, start, start + len, morpheme, TokenKind::User);
+                        found = true;
+                    }
+                }
+            }
+            if let Some(unknown) = self.unknown.as_ref() {
+                found |= self.add_unknown_nodes(&mut lattice, text, start, unknown, found);
+            }
+            if !found {
+                let morpheme = Morpheme { left_id: BOS_EOS_ID, right_id: BOS_EOS_ID, cost: UNKNOWN_WORD_COST, feature: String::new() };
+                self.add_node(&mut lattice, start, start + c.len_utf8(), morpheme, TokenKind::Unknown);
+            }
+        }
+        self.add_node(&mut lattice, text.len(), text.len(), bos_eos(), TokenKind::System);
+        lattice
+    }
+
+    /// 位置 start から始まる、文字種による未知語のノードを追加する
+    /// 辞書に形態素が見つかった位置では、文字種の invoke が有効な場合だけ追加する
+    /// ノードを追加した場合はtrueを返す
+    fn add_unknown_nodes(&self, lattice: &mut Lattice, text: &str, start: usize, unknown: &UnknownDictionary, found: bool) -> bool {
+        let (category, lengths) = match unknown.char_definition.unknown_candidates(&text[start..], MAX_GROUPING_CHARS) {
+            Some(candidates) => candidates,
+            None => return false,
+        };
+        if found && !category.invoke {
+            return false;
+        }
+        let morphemes = match unknown.double_array.get(&category.name) {
+            Some(morphemes) => morphemes,
+            None => return false,
+        };
+        let mut added = false;
+        for len in lengths {
+            for morpheme in morphemes.iter() {
+                self.add_node(lattice, start, start + len, morpheme.clone(), TokenKind::Unknown);
+                added = true;
+            }
+        }
+        added
+    }
+
+    /// ノードを追加し、位置 start で終わるノードから最小コストの経路を選ぶ
+    /// start で終わるノードはすべて start より前から始まるので、追加済みで最小コストも確定している
+    fn add_node(&self, lattice: &mut Lattice, start: usize, end: usize, morpheme: Morpheme, kind: TokenKind) {
+        let mut node = Node { start, end, morpheme, kind, total: i64::MAX, prev: 0 };
+        for &prev_idx in lattice.ends[start].iter() {
+            let prev = &lattice.nodes[prev_idx];
+            let total = prev.total + self.connection_cost(prev, &node);
+            if total < node.total {
+                node.total = total;
+                node.prev = prev_idx;
+            }
+        }
+        node.total += node.morpheme.cost as i64;
+        lattice.nodes.push(node);
+        // 文末のノードは長さ0なので、どのノードの前にもならない
+        if end > start {
+            lattice.ends[end].push(lattice.nodes.len() - 1);
+        }
+    }
+
+    /// 2つのノードの連接コスト
+    fn connection_cost(&self, prev: &Node, next: &Node) -> i64 {
+        self.matrix.cost(prev.morpheme.right_id, next.morpheme.left_id) as i64
+    }
+}
+
+/// 文頭・文末の形態素
+fn bos_eos() -> Morpheme {
+    Morpheme { left_id: BOS_EOS_ID, right_id: BOS_EOS_ID, cost: 0, feature: String::new() }
+}
+
+fn to_token<'t>(text: &'t str, node: &Node) -> Token<'t> {
+    Token {
+        surface : &text[node.start..node.end],
+        start   : node.start,
+        end     : node.end,
+        morpheme: node.morpheme.clone(),
+        kind    : node.kind,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mecab::MecabImporter;
+
+    fn tokenizer() -> Tokenizer {
+        let importer = MecabImporter::new();
+        let matrix = importer.read_matrix("3 3\n0 1 -100\n1 0 -100\n1 1 2000\n1 2 -500\n2 1 -500\n2 2 2000\n".as_bytes()).unwrap();
+        let lexicon = "\
+すもも,1,1,7000,名詞
+もも,1,1,7000,名詞
+うち,1,1,7000,名詞
+も,2,2,4000,助詞
+の,2,2,4000,助詞
+";
+        let mut trie = crate::trie::Trie::new();
+        importer.read_lexicon(lexicon.as_bytes(), &mut trie).unwrap();
+        Tokenizer::new(trie.to_double_array().unwrap(), matrix)
+    }
+
+    fn surfaces<'t>(tokens: &[Token<'t>]) -> Vec<&'t str> {
+        tokens.iter().map(|token| token.surface).collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let tokenizer = tokenizer();
+        let tokens = tokenizer.tokenize("すもももももももものうち");
+        assert_eq!(vec!["すもも", "も", "もも", "も", "もも", "の", "うち"], surfaces(&tokens));
+        assert_eq!((9, 12), (tokens[1].start, tokens[1].end));
+        assert_eq!("助詞", tokens[1].morpheme.feature);
+        assert!(tokens.iter().all(|token| token.kind == TokenKind::System));
+        assert_eq!(0, tokenizer.tokenize("").len());
+    }
+
+    #[test]
+    fn test_unknown_word() {
+        let tokenizer = tokenizer();
+        let tokens = tokenizer.tokenize("すももとももA");
+        assert_eq!(vec!["すもも", "と", "もも", "A"], surfaces(&tokens));
+        assert_eq!(TokenKind::Unknown, tokens[1].kind);
+        assert_eq!(UNKNOWN_WORD_COST, tokens[3].morpheme.cost);
+    }
+
+    #[test]
+    fn test_tokenize_nbest() {
+        let tokenizer = tokenizer();
+        let results = tokenizer.tokenize_nbest("すもももも", 10);
+        assert_eq!(2, results.len());
+        assert_eq!(vec!["すもも", "もも"], surfaces(&results[0].tokens));
+        assert_eq!(vec!["すもも", "も", "も"], surfaces(&results[1].tokens));
+        assert_eq!(surfaces(&tokenizer.tokenize("すもももも")), surfaces(&results[0].tokens));
+        // コストは単語コストと連接コストの合計
+        assert_eq!(-100 + 7000 + 2000 + 7000 - 100, results[0].cost);
+        assert_eq!(-100 + 7000 - 500 + 4000 + 2000 + 4000, results[1].cost);
+        assert_eq!(1, tokenizer.tokenize_nbest("すももも", 1).len());
+        assert_eq!(1, tokenizer.tokenize_nbest("", 3).len());
+    }
+
+    #[test]
+    fn test_user_dictionary() {
+        let tokenizer = tokenizer();
+        assert_eq!(TokenKind::Unknown, tokenizer.tokenize("すももとももも")[1].kind);
+
+        let mut trie = Trie::new();
+        MecabImporter::new().read_lexicon("とももも,1,1,1000,名詞\n".as_bytes(), &mut trie).unwrap();
+        let tokenizer = tokenizer.user(UserDictionary::from(trie)).unwrap();
+        let tokens = tokenizer.tokenize("すももとももも");
+        assert_eq!(vec!["すもも", "とももも"], surfaces(&tokens));
+        assert_eq!(TokenKind::User, tokens[1].kind);
+
+        // ダブル配列のユーザー辞書に差し替える
+        let mut trie = Trie::new();
+        MecabImporter::new().read_lexicon("すももと,1,1,1000,名詞\n".as_bytes(), &mut trie).unwrap();
+        let old = tokenizer.set_user_dictionary(Some(Arc::new(UserDictionary::from(trie.to_double_array().unwrap())))).unwrap();
+        assert!(matches!(*old.unwrap(), UserDictionary::Trie(_)));
+        let tokens = tokenizer.tokenize("すももとももも");
+        assert_eq!(vec!["すももと", "も", "もも"], surfaces(&tokens));
+        assert_eq!(TokenKind::System, tokens[1].kind);
+
+        tokenizer.set_user_dictionary(None).unwrap();
+        assert!(tokenizer.user_dictionary().is_none());
+        assert_eq!(TokenKind::Unknown, tokenizer.tokenize("すももとももも")[1].kind);
+    }
+
+    #[test]
+    fn test_user_dictionary_out_of_matrix() {
+        let tokenizer = tokenizer();
+        let mut trie = Trie::new();
+        MecabImporter::new().read_lexicon("とももも,1,1,1000,名詞\n".as_bytes(), &mut trie).unwrap();
+        tokenizer.set_user_dictionary(Some(Arc::new(UserDictionary::from(trie)))).unwrap();
+
+        // 連接コスト表の範囲外の文脈IDを持つユーザー辞書は設定できず、元のユーザー辞書を使い続ける
+        let mut trie = Trie::new();
+        trie.set("もも", Morpheme { left_id: 1, right_id: 999, cost: 0, feature: String::from("名詞") });
+        let error = tokenizer.set_user_dictionary(Some(Arc::new(UserDictionary::from(trie.build().unwrap())))).err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert!(matches!(*tokenizer.user_dictionary().unwrap(), UserDictionary::Trie(_)));
+        assert_eq!(TokenKind::User, tokenizer.tokenize("すももとももも")[1].kind);
+        assert!(self::tokenizer().user(UserDictionary::from(trie)).is_err());
+    }
+
+    #[test]
+    fn test_reload_user_dictionary() {
+        let tokenizer = Arc::new(tokenizer());
+        let user = |cost: i16| {
+            let mut trie = Trie::new();
+            trie.set("ももも", Morpheme { left_id: 1, right_id: 1, cost, feature: String::from("名詞") });
+            Some(Arc::new(UserDictionary::from(trie)))
+        };
+        let threads: Vec<_> = (0..4).map(|_| {
+            let tokenizer = Arc::clone(&tokenizer);
+            std::thread::spawn(move || {
+                for _ in 0..100 {
+                    let tokens = tokenizer.tokenize("すもももももも");
+                    assert_eq!("すもも", tokens[0].surface);
+                }
+            })
+        }).collect();
+        for i in 0..100 {
+            tokenizer.set_user_dictionary(user(i * 100)).unwrap();
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let tokens = tokenizer.tokenize("すもももももも");
+        assert_eq!(vec!["すもも", "も", "ももも"], surfaces(&tokens));
+        assert_eq!(TokenKind::User, tokens[2].kind);
+    }
+}
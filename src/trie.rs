@@ -1,16 +1,25 @@
 use std::fmt::Debug;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::iter::FromIterator;
+use std::ops::Index;
 
-use super::bit_cache::BitCache;
+use crate::builder::DoubleArrayBuilder;
 use crate::double_array::DoubleArray;
+use crate::format::invalid_data;
+use crate::normalizer::Normalizer;
 
 use bincode;
-use serde::Serialize;
-use serde::de::DeserializeOwned;
-
-struct Node<T> {
-    key   : u8,
-    values: Vec<T>,
-    nexts : Vec<Node<T>>,
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{DeserializeOwned, Error};
+use serde::ser::{SerializeSeq, SerializeStruct};
+use serde_derive::Deserialize;
+
+pub(crate) struct Node<T> {
+    pub(crate) key   : u8,
+    pub(crate) values: Vec<T>,
+    pub(crate) nexts : Vec<Node<T>>,
 }
 
 /// トライ木の実装。
@@ -53,8 +62,12 @@ struct Node<T> {
 /// }
 /// ```
 pub struct Trie<T: Serialize + DeserializeOwned + Debug> {
-    root: Node<T>,
-    len: usize,
+    pub(crate) root: Node<T>,
+    /// 登録された値の数
+    pub(crate) len: usize,
+    /// 値が登録されたキーの数
+    pub(crate) keys: usize,
+    pub(crate) metadata: BTreeMap<String, String>,
 }
 
 impl<T: Serialize + DeserializeOwned + Debug> Trie<T> {
@@ -62,6 +75,8 @@ impl<T: Serialize + DeserializeOwned + Debug> Trie<T> {
         Trie {
             root: Node { key: 0, values: Vec::new(), nexts: Vec::new() },
             len: 0,
+            keys: 0,
+            metadata: BTreeMap::new(),
         }
     }
 
@@ -86,6 +101,9 @@ impl<T: Serialize + DeserializeOwned + Debug> Trie<T> {
                 }
             }
         }
+        if node.values.is_empty() {
+            self.keys += 1;
+        }
         self.len += 1;
         node.values.push(value);
     }
@@ -97,17 +115,7 @@ impl<T: Serialize + DeserializeOwned + Debug> Trie<T> {
     ///
     /// * `key` - 探索するkey
     pub fn get(&self, key: &str) -> Option<&[T]> {
-        let mut node = &self.root;
-        for &k in key.as_bytes() {
-            match node.nexts.binary_search_by(|probe| probe.key.cmp(&k)) {
-                Ok(i) => {
-                    node = &node.nexts[i];
-                },
-                Err(_) => {
-                    return None;
-                }
-            }
-        }
+        let node = self.find(key)?;
         if node.values.is_empty() {
             None
         } else {
@@ -115,110 +123,399 @@ impl<T: Serialize + DeserializeOwned + Debug> Trie<T> {
         }
     }
 
+    /// キーに値が登録されているかどうか
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - 探索するkey
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
 
-    /// トライ木をダブル配列に変換する
+    /// 値が登録されたキーの数
+    pub fn len_keys(&self) -> usize {
+        self.keys
+    }
+
+    /// 登録された値の数。同じキーに複数の値を登録した場合はそれぞれ数える
+    pub fn len_values(&self) -> usize {
+        self.len
+    }
+
+    /// キーが1つも登録されていないかどうか
+    pub fn is_empty(&self) -> bool {
+        self.keys == 0
+    }
+
+    /// キーと値をキーの辞書順に列挙するイテレータを返す
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { stack: vec![(&self.root, 0)], key: Vec::new() }
+    }
+
+    /// 共通接頭辞検索を行う
+    /// textの接頭辞になっている登録済みのキーを、短い順に返す。空のキーは返さない
     ///
-    /// # Panics
-    /// dataをバイト列に変換できなかった場合にpanicする。
+    /// # Arguments
     ///
-    /// # Errors
+    /// * `text` - 探索対象の文字列
+    pub fn prefix_search<'a>(&self, text: &'a str) -> Vec<(&'a str, &[T])> {
+        self.prefix_search_iter(text).collect()
+    }
+
+    /// 共通接頭辞検索を行うイテレータを返す
     ///
+    /// # Arguments
+    ///
+    /// * `text` - 探索対象の文字列
+    pub fn prefix_search_iter<'a, 'b>(&'a self, text: &'b str) -> PrefixSearchIter<'a, 'b, T> {
+        PrefixSearchIter { node: Some(&self.root), text, pos: 0 }
+    }
+
+    /// textの接頭辞になっている登録済みのキーのうち、最も長いものと値を返す
     ///
     /// # Arguments
     ///
-    /// * `len` - ダブル配列の初期サイズ
-    pub fn to_double_array(self) -> Result<DoubleArray<T>, std::io::Error> {
-        let max_key = u8::max_value() as usize + 1;      // keyが取りうる値のパターン
-        let mut len = if max_key > (4 * self.len) { max_key } else { 4 * self.len };
-        let mut base_arr: Vec<u32>  = vec![0; len];
-        let mut check_arr: Vec<u32> = vec![0; len];
-        let mut data_arr: Vec<u8>   = Vec::with_capacity(self.len);
-        let mut bit_cache: BitCache = BitCache::new();
-        bit_cache.set(0);
-        bit_cache.set(1);
-        let mut stack: Vec<(usize, Node<T>)> = Vec::with_capacity(self.len);
-        if !self.root.nexts.is_empty() {
-            stack.push((1, self.root));
+    /// * `text` - 探索対象の文字列
+    pub fn longest_prefix<'a>(&self, text: &'a str) -> Option<(&'a str, &[T])> {
+        self.prefix_search_iter(text).last()
+    }
+
+    /// prefixで始まるすべてのキーと値をキーの辞書順に返す (前方一致検索)
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - 探索対象の接頭辞
+    pub fn predictive_search(&self, prefix: &str) -> Vec<(String, &[T])> {
+        self.predictive_search_iter(prefix).collect()
+    }
+
+    /// prefixで始まるすべてのキーと値をキーの辞書順に列挙するイテレータを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - 探索対象の接頭辞
+    pub fn predictive_search_iter(&self, prefix: &str) -> Iter<'_, T> {
+        let stack = self.find(prefix).map(|node| vec![(node, prefix.len())]).unwrap_or_default();
+        Iter { stack, key: prefix.as_bytes().to_vec() }
+    }
+
+    /// keyまで遷移したノードを返す
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - 探索するkey
+    fn find(&self, key: &str) -> Option<&Node<T>> {
+        let mut node = &self.root;
+        for &k in key.as_bytes() {
+            let i = node.nexts.binary_search_by(|probe| probe.key.cmp(&k)).ok()?;
+            node = &node.nexts[i];
         }
+        Some(node)
+    }
 
-        while !stack.is_empty() {
-            let (curr_idx, mut node) = stack.pop().unwrap();
-            bit_cache.update_start();
+    /// ダブル配列の辞書ファイルに格納するメタデータを設定する
+    /// 同じkeyを設定した場合は上書きする
+    ///
+    /// # Arguments
+    ///
+    /// * `key`   - メタデータのキー
+    /// * `value` - メタデータの値
+    pub fn set_metadata(&mut self, key: &str, value: &str) {
+        self.metadata.insert(key.to_string(), value.to_string());
+    }
 
-            // base値を探索・セット
-            if !node.values.is_empty() {
-                // valuesが存在する場合はkey=255のノードとして計算する
-                node.nexts.push(Node { key: u8::max_value(), values: vec![], nexts: vec![] });
+    /// すべてのキーを正規化したトライ木を返す
+    /// 正規化で同じキーになった値は、元のキーの辞書順に連結する
+    ///
+    /// # Arguments
+    ///
+    /// * `normalizer` - キーの正規化
+    pub(crate) fn normalize(mut self, normalizer: &Normalizer) -> Trie<T> {
+        let mut trie: Trie<T> = Trie::new();
+        trie.metadata = std::mem::take(&mut self.metadata);
+        for (key, values) in self {
+            let key = normalizer.normalize(&key);
+            for value in values {
+                trie.set(&key, value);
             }
+        }
+        trie
+    }
+
+    /// 構築途中のトライ木をファイルに保存する
+    /// 別のプロセスで `from_file` で読み込んで、キーの追加を続けられる
+    ///
+    /// # Arguments
+    ///
+    /// * `output_path` - 保存先のファイルパス
+    pub fn dump(&self, output_path: &str) -> Result<(), io::Error> {
+        let bytes = bincode::serialize(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(output_path, bytes)
+    }
 
-            let base: usize = Self::find_base(&node.nexts, &bit_cache);
-            base_arr[curr_idx] = base as u32;
+    /// `dump` で保存したトライ木を読み込む
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - ファイルパス
+    pub fn from_file(path: &str) -> Result<Self, io::Error> {
+        let bytes = fs::read(path)?;
+        bincode::deserialize(&bytes).map_err(|e| invalid_data(&format!("invalid trie: {e}")))
+    }
 
-            // 配列の長さが足りなければ配列を拡張
-            if base + max_key >= len {
-                len = len * 2;
-                base_arr.resize(len, 0);
-                check_arr.resize(len, 0);
+    /// ダブル配列のすべてのキーと値から、編集できるトライ木を作る
+    /// メタデータも引き継ぐので、キーを追加して構築し直すと正規化の指定も引き継がれる。
+    /// レイアウトなどの構築オプションは引き継がないので、構築し直す際に `DoubleArrayBuilder` で指定する
+    ///
+    /// # Arguments
+    ///
+    /// * `double_array` - 元のダブル配列
+    pub fn from_double_array(double_array: &DoubleArray<T>) -> Trie<T> {
+        let mut trie = Trie::new();
+        for (key, values) in double_array.iter() {
+            for value in values {
+                trie.set(&key, value);
             }
+        }
+        trie.metadata = double_array.metadata();
+        trie
+    }
+
+    /// トライ木を消費せずにダブル配列に変換する
+    /// ノードをたどりながら値をシリアライズするので、トライ木は複製しない
+    pub fn build(&self) -> Result<DoubleArray<T>, io::Error> {
+        DoubleArrayBuilder::new().build_ref(self)
+    }
+
+    /// トライ木をダブル配列に変換する
+    /// 構築オプションを指定する場合は `DoubleArrayBuilder` を利用する
+    pub fn to_double_array(self) -> Result<DoubleArray<T>, std::io::Error> {
+        DoubleArrayBuilder::new().build(self)
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Debug> Default for Trie<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// シリアライズしたトライ木のノード。ノードを行きがけ順に並べ、子の数で木の形を表す
+/// (遷移のラベル, 値, 子の数)
+type PreorderNode<V> = (u8, V, u32);
+
+/// ノードを行きがけ順にシリアライズする
+struct Preorder<'a, T>(&'a Node<T>);
+
+impl<'a, T: Serialize> Serialize for Preorder<'a, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // bincode は列の長さを先に書くので、ノードの数を数えておく
+        let mut count = 0;
+        let mut stack: Vec<&Node<T>> = vec![self.0];
+        while let Some(node) = stack.pop() {
+            count += 1;
+            stack.extend(node.nexts.iter());
+        }
+        let mut seq = serializer.serialize_seq(Some(count))?;
+        stack.push(self.0);
+        while let Some(node) = stack.pop() {
+            let serialized: PreorderNode<&[T]> = (node.key, &node.values, node.nexts.len() as u32);
+            seq.serialize_element(&serialized)?;
+            stack.extend(node.nexts.iter().rev());
+        }
+        seq.end()
+    }
+}
+
+/// メタデータと、行きがけ順のノードの列としてシリアライズする
+impl<T: Serialize + DeserializeOwned + Debug> Serialize for Trie<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Trie", 2)?;
+        state.serialize_field("metadata", &self.metadata)?;
+        state.serialize_field("nodes", &Preorder(&self.root))?;
+        state.end()
+    }
+}
+
+/// デシリアライズしたトライ木
+#[derive(Deserialize)]
+#[serde(rename = "Trie")]
+struct SerializedTrie<T> {
+    metadata: BTreeMap<String, String>,
+    nodes   : Vec<PreorderNode<Vec<T>>>,
+}
 
-            // 新しいノードをダブル配列に登録
-            for n in node.nexts {
-                let i = base + (n.key as usize);
-                bit_cache.set(i);
-                check_arr[i] = curr_idx as u32;
-                if n.key == u8::max_value() {
-                    // valueノードの登録
-                    // base には data の開始 index を格納する
-                    base_arr[i]  = data_arr.len() as u32;
-                    // data には末尾に values を追加する
-                    let data = bincode::serialize(&node.values).unwrap();
-                    data_arr.extend_from_slice(&data);
-                } else {
-                    // 通常ノードの登録
-                    stack.push((i, n));
+impl<'de, T: Serialize + DeserializeOwned + Debug> Deserialize<'de> for Trie<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let serialized: SerializedTrie<T> = SerializedTrie::deserialize(deserializer)?;
+        let mut nodes = serialized.nodes.into_iter();
+        // 子を読み終えていないノードと、残りの子の数
+        let (_, values, count) = nodes.next().ok_or_else(|| D::Error::custom("missing root node"))?;
+        let mut len = values.len();
+        let mut keys = usize::from(!values.is_empty());
+        let mut stack: Vec<(Node<T>, u32)> = vec![(Node { key: 0, values, nexts: Vec::new() }, count)];
+        // ルートから各ノードまでの遷移のラベル
+        let mut key: Vec<u8> = Vec::new();
+        loop {
+            if stack.last().unwrap().1 > 0 {
+                let (label, values, count) = nodes.next().ok_or_else(|| D::Error::custom("missing child node"))?;
+                stack.last_mut().unwrap().1 -= 1;
+                key.push(label);
+                // 値も子も持たないノードは構築時に配置できない
+                if values.is_empty() && count == 0 {
+                    return Err(D::Error::custom("empty leaf node"));
+                }
+                if !values.is_empty() && std::str::from_utf8(&key).is_err() {
+                    return Err(D::Error::custom("key is not valid UTF-8"));
                 }
+                len += values.len();
+                keys += usize::from(!values.is_empty());
+                stack.push((Node { key: label, values, nexts: Vec::new() }, count));
+                continue;
+            }
+            let (node, _) = stack.pop().unwrap();
+            let parent = match stack.last_mut() {
+                Some((parent, _)) => parent,
+                None => {
+                    if nodes.next().is_some() {
+                        return Err(D::Error::custom("trailing nodes"));
+                    }
+                    return Ok(Trie { root: node, len, keys, metadata: serialized.metadata });
+                },
+            };
+            key.pop();
+            // 子は遷移のラベルの順に並んでいる必要がある
+            if parent.nexts.last().is_some_and(|last| last.key >= node.key) {
+                return Err(D::Error::custom("child nodes are not sorted"));
             }
+            parent.nexts.push(node);
         }
+    }
+}
 
-        // 配列のりサイズ
-        let new_len = match bit_cache.last_index_of_one() {
-            None          => max_key,
-            Some(new_len) => new_len + max_key,
-        };
-        base_arr.resize(new_len, 0);
-        check_arr.resize(new_len, 0);
-        DoubleArray::from_arrays(&base_arr, &check_arr, &data_arr)
+/// キーが登録されていない場合はpanicする
+impl<T: Serialize + DeserializeOwned + Debug> Index<&str> for Trie<T> {
+    type Output = [T];
+
+    fn index(&self, key: &str) -> &[T] {
+        self.get(key).unwrap_or_else(|| panic!("key not found: {:?}", key))
     }
+}
 
-    /// 新しいbase値を探索するメソッド
-    ///
-    /// # Arguments
-    ///
-    /// * `nodes`     - 追加対象のノード
-    /// * `bit_cache` - BitCacheのインスタンス
-    /// * `with_zero` - key=0のノードも考慮してbase値を探す
-    fn find_base(nodes: &[Node<T>], bit_cache: &BitCache) -> usize {
-        if nodes.is_empty() {
-                panic!("探索すべきノードがありません");
+impl<K: AsRef<str>, T: Serialize + DeserializeOwned + Debug> FromIterator<(K, T)> for Trie<T> {
+    fn from_iter<I: IntoIterator<Item = (K, T)>>(iter: I) -> Self {
+        let mut trie = Trie::new();
+        trie.extend(iter);
+        trie
+    }
+}
+
+impl<K: AsRef<str>, T: Serialize + DeserializeOwned + Debug> Extend<(K, T)> for Trie<T> {
+    fn extend<I: IntoIterator<Item = (K, T)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.set(key.as_ref(), value);
         }
-        let first_key = nodes[0].key as usize;
-        let mut offset = 0;
-        'outer: loop {
-            let empty_idx = bit_cache.find_empty_idx(offset);
-            let new_base = empty_idx - first_key;
-            if empty_idx < 256 {
-                panic!("empty_idx={}, first_key={}", empty_idx, first_key);
+    }
+}
+
+impl<'a, T: Serialize + DeserializeOwned + Debug> IntoIterator for &'a Trie<T> {
+    type Item = (String, &'a [T]);
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Debug> IntoIterator for Trie<T> {
+    type Item = (String, Vec<T>);
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { stack: vec![(self.root, 0)], key: Vec::new() }
+    }
+}
+
+/// トライ木のキーと値を辞書順に列挙するイテレータ
+pub struct Iter<'a, T> {
+    /// 未訪問のノードと、ルートからそのノードまでのキーの長さ
+    stack: Vec<(&'a Node<T>, usize)>,
+    /// 直前に訪問したノードまでのキー
+    key  : Vec<u8>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (String, &'a [T]);
+
+    fn next(&mut self) -> Option<(String, &'a [T])> {
+        while let Some((node, depth)) = self.stack.pop() {
+            if depth > 0 {
+                self.key.truncate(depth - 1);
+                self.key.push(node.key);
             }
-            // すべてのノードが重複せずに配置できるかをチェック
-            'inner: for next in nodes {
-                if bit_cache.get(new_base + next.key as usize) != 0 {
-                    // 空じゃなかった場合はnew_baseを探すとこからやり直し
-                    offset += 1;
-                    continue 'outer;
-                }
+            self.stack.extend(node.nexts.iter().rev().map(|next| (next, depth + 1)));
+            if !node.values.is_empty() {
+                // キーは &str として登録されているので UTF-8 として正しい
+                return Some((String::from_utf8(self.key.clone()).unwrap(), &node.values));
             }
-            return new_base;
         }
+        None
+    }
+}
+
+/// トライ木で共通接頭辞検索を行うイテレータ
+pub struct PrefixSearchIter<'a, 'b, T> {
+    /// 直前に一致した位置のノード。遷移できなくなったらNone
+    node: Option<&'a Node<T>>,
+    text: &'b str,
+    /// textのうち遷移済みのバイト数
+    pos : usize,
+}
+
+impl<'a, 'b, T> Iterator for PrefixSearchIter<'a, 'b, T> {
+    type Item = (&'b str, &'a [T]);
+
+    fn next(&mut self) -> Option<(&'b str, &'a [T])> {
+        let bytes = self.text.as_bytes();
+        while let Some(node) = self.node {
+            let next = bytes.get(self.pos)
+                .and_then(|&k| node.nexts.binary_search_by(|probe| probe.key.cmp(&k)).ok())
+                .map(|i| &node.nexts[i]);
+            self.node = next;
+            let next = next?;
+            self.pos += 1;
+            if !next.values.is_empty() {
+                // キーは UTF-8 として正しいので、一致した位置は文字の境界になる
+                return Some((&self.text[..self.pos], &next.values));
+            }
+        }
+        None
+    }
+}
+
+/// トライ木を消費して、キーと値を辞書順に列挙するイテレータ
+pub struct IntoIter<T> {
+    /// 未訪問のノードと、ルートからそのノードまでのキーの長さ
+    stack: Vec<(Node<T>, usize)>,
+    /// 直前に訪問したノードまでのキー
+    key  : Vec<u8>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = (String, Vec<T>);
+
+    fn next(&mut self) -> Option<(String, Vec<T>)> {
+        while let Some((node, depth)) = self.stack.pop() {
+            if depth > 0 {
+                self.key.truncate(depth - 1);
+                self.key.push(node.key);
+            }
+            self.stack.extend(node.nexts.into_iter().rev().map(|next| (next, depth + 1)));
+            if !node.values.is_empty() {
+                return Some((String::from_utf8(self.key.clone()).unwrap(), node.values));
+            }
+        }
+        None
     }
 }
 
@@ -281,54 +578,6 @@ mod tests {
         assert_eq!(14, trie.get(&s5).unwrap()[0]);
     }
 
-    #[test]
-    fn test_find_base_1() {
-        let nodes: Vec<Node<u32>> = vec![
-            Node::<u32> { key: 2  , values: vec![], nexts: vec![] },
-            Node::<u32> { key: 5  , values: vec![], nexts: vec![] },
-            Node::<u32> { key: 255, values: vec![], nexts: vec![] },
-        ];
-        let mut bit_cache = BitCache::new();
-
-        // 探索開始位置 = 256。空きindex = 256
-        // base値 = 空きindex - 先頭ノードのkey = 256 - 2 = 254
-        assert_eq!(254, Trie::find_base(&nodes, &bit_cache));
-
-        // 0 ~ 399, 500 ~ 999 を埋める
-        (256..400).for_each(|i| bit_cache.set(i));
-        (500..1000).for_each(|i| bit_cache.set(i));
-
-        // 探索開始位置 = 256。空きindex = 1000
-        // base値 = 空きindex - 先頭ノードのkey = 1000 - 2 = 998
-        assert_eq!(998, Trie::find_base(&nodes, &bit_cache));
-
-        //1000..1002, 1003..1005, 1006..1255 を埋める
-        (1000..1002).for_each(|i| bit_cache.set(i));
-        (1003..1005).for_each(|i| bit_cache.set(i));
-        (1006..1255).for_each(|i| bit_cache.set(i));
-
-        // 探索開始位置 = 256。空きindex = 1002
-        // base値 = 空きindex - 先頭ノードのkey = 1002 - 2 = 1000
-        assert_eq!(1000, Trie::find_base(&nodes, &bit_cache));
-
-        // 400 ~ 500 を埋める
-        (400..500).for_each(|i| bit_cache.set(i));
-
-        // 探索開始位置=1216。空きindex = 1255
-        // base値 = 空きindex - 先頭ノードのkey = 1255 - 2 = 1253
-        bit_cache.update_start();
-        assert_eq!(1253, Trie::find_base(&nodes, &bit_cache));
-    }
-
-    #[test]
-    #[should_panic(expected = "探索すべきノードがありません")]
-    fn test_find_base_2() {
-        let nodes: Vec<Node<u32>> = vec![];
-        let bit_cache = BitCache::new();
-        // nodesが空でwith_zero=falseの場合は、base値を求められないのでpanic
-        Trie::find_base(&nodes, &bit_cache);
-    }
-
     #[test]
     fn test_to_double_array_1() {
         let mut trie: Trie<u32> = Trie::new();
@@ -381,4 +630,139 @@ mod tests {
         // 登録されているが、data_arrに値が存在しないkeyはNoneを返す
         assert_eq!(None, double_array.get("お寿"));
     }
+
+    #[test]
+    fn test_to_double_array_metadata() {
+        let mut trie: Trie<u32> = Trie::new();
+        trie.set("abc", 1);
+        trie.set_metadata("source", "a.csv");
+        trie.set_metadata("source", "b.csv");
+        let double_array = trie.to_double_array().ok().unwrap();
+        let metadata = double_array.metadata();
+        assert_eq!("b.csv", metadata["source"]);
+        assert_eq!("1", metadata["dary.key_count"]);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let mut trie: Trie<u32> = Trie::new();
+        trie.set("abc", 1);
+        trie.set("ab", 2);
+        trie.set("abc", 3);
+        trie.set("東京", 4);
+        trie.set("", 5);
+        trie.set_metadata("source", "a.csv");
+        let bytes = bincode::serialize(&trie).unwrap();
+        let mut restored: Trie<u32> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(trie.len, restored.len);
+        assert_eq!(trie.keys, restored.keys);
+        assert_eq!(trie.metadata, restored.metadata);
+        assert_eq!(Some(&[1, 3][..]), restored.get("abc"));
+        assert_eq!(Some(&[5][..]), restored.get(""));
+        // 読み込んだトライ木にキーを追加できる
+        restored.set("abd", 6);
+        let double_array = restored.to_double_array().unwrap();
+        assert_eq!(vec![4], double_array.get("東京").unwrap());
+        assert_eq!(vec![6], double_array.get("abd").unwrap());
+
+        // 行きがけ順のノードの列が木の形になっていない
+        let nodes: Vec<(u8, Vec<u32>, u32)> = vec![(0, vec![], 2), (b'a', vec![1], 0)];
+        let bytes = bincode::serialize(&(BTreeMap::<String, String>::new(), nodes)).unwrap();
+        assert!(bincode::deserialize::<Trie<u32>>(&bytes).is_err());
+        // 子がラベルの順に並んでいない
+        let nodes: Vec<(u8, Vec<u32>, u32)> = vec![(0, vec![], 2), (b'b', vec![1], 0), (b'a', vec![2], 0)];
+        let bytes = bincode::serialize(&(BTreeMap::<String, String>::new(), nodes)).unwrap();
+        assert!(bincode::deserialize::<Trie<u32>>(&bytes).is_err());
+        // 値も子も持たないノード
+        let nodes: Vec<(u8, Vec<u32>, u32)> = vec![(0, vec![], 2), (b'a', vec![1], 0), (b'b', vec![], 0)];
+        let bytes = bincode::serialize(&(BTreeMap::<String, String>::new(), nodes)).unwrap();
+        let error = bincode::deserialize::<Trie<u32>>(&bytes).err().unwrap();
+        assert!(error.to_string().contains("empty leaf node"));
+        // 空のトライ木のルートは値も子も持たない
+        let bytes = bincode::serialize(&Trie::<u32>::new()).unwrap();
+        assert!(bincode::deserialize::<Trie<u32>>(&bytes).unwrap().is_empty());
+        // 値を持つノードのキーがUTF-8ではない
+        let nodes: Vec<(u8, Vec<u32>, u32)> = vec![(0, vec![], 1), (0xE3, vec![1], 0)];
+        let bytes = bincode::serialize(&(BTreeMap::<String, String>::new(), nodes)).unwrap();
+        assert!(bincode::deserialize::<Trie<u32>>(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_collection() {
+        let mut trie: Trie<u32> = vec![("東京", 1), ("abc", 2), ("ab", 3)].into_iter().collect();
+        trie.extend(vec![(String::from("abc"), 4), (String::from(""), 5)]);
+        assert_eq!(4, trie.len_keys());
+        assert_eq!(5, trie.len_values());
+        assert!(!trie.is_empty());
+        assert!(Trie::<u32>::new().is_empty());
+        assert!(trie.contains_key("ab"));
+        assert!(!trie.contains_key("a"));
+        assert_eq!([2, 4], trie["abc"]);
+
+        // キーの辞書順 (UTF-8のバイト列の順) に列挙する
+        let entries: Vec<(String, &[u32])> = trie.iter().collect();
+        assert_eq!(vec![
+            (String::from(""), &[5][..]),
+            (String::from("ab"), &[3][..]),
+            (String::from("abc"), &[2, 4][..]),
+            (String::from("東京"), &[1][..]),
+        ], entries);
+        let keys: Vec<String> = (&trie).into_iter().map(|(key, _)| key).collect();
+        let entries: Vec<(String, Vec<u32>)> = trie.into_iter().collect();
+        assert_eq!(keys, entries.iter().map(|(key, _)| key.clone()).collect::<Vec<String>>());
+        assert_eq!((String::from("abc"), vec![2, 4]), entries[2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "key not found")]
+    fn test_index_not_found() {
+        let trie: Trie<u32> = vec![("abc", 1)].into_iter().collect();
+        let _ = &trie["ab"];
+    }
+
+    #[test]
+    fn test_search() {
+        let trie: Trie<u32> = vec![("", 0), ("東", 1), ("東京", 2), ("東京", 3), ("東京都庁", 4), ("京都", 5)].into_iter().collect();
+        assert_eq!(vec![("東", &[1][..]), ("東京", &[2, 3][..])], trie.prefix_search("東京都"));
+        assert_eq!(Some(("東京", &[2, 3][..])), trie.longest_prefix("東京都"));
+        assert_eq!(None, trie.longest_prefix("大阪"));
+        assert!(trie.prefix_search("").is_empty());
+
+        let keys: Vec<String> = trie.predictive_search("東京").into_iter().map(|(key, _)| key).collect();
+        assert_eq!(vec!["東京", "東京都庁"], keys);
+        assert_eq!(5, trie.predictive_search("").len());
+        assert!(trie.predictive_search("大").is_empty());
+        // 登録されていない接頭辞でも、続くキーを返す
+        assert_eq!(vec![(String::from("東京都庁"), &[4][..])], trie.predictive_search("東京都"));
+    }
+
+    #[test]
+    fn test_build_and_from_double_array() {
+        let mut trie: Trie<u32> = vec![("東京", 1), ("東京", 2), ("京都", 3)].into_iter().collect();
+        trie.set_metadata("source", "a.csv");
+        let double_array = trie.build().unwrap();
+        // 変換後もトライ木を使える
+        trie.set("大阪", 4);
+        assert_eq!(None, double_array.get("大阪"));
+        assert_eq!(vec![4], trie.build().unwrap().get("大阪").unwrap());
+
+        let mut restored = Trie::from_double_array(&double_array);
+        assert_eq!((2, 3), (restored.len_keys(), restored.len_values()));
+        assert_eq!([1, 2], restored["東京"]);
+        assert_eq!("a.csv", restored.metadata["source"]);
+        restored.set("京都", 5);
+        let rebuilt = restored.to_double_array().unwrap();
+        assert_eq!(vec![3, 5], rebuilt.get("京都").unwrap());
+        assert_eq!("a.csv", rebuilt.metadata()["source"]);
+
+        // 消費して変換した場合と同じ辞書になる。メタデータに記録された正規化も引き継ぐ
+        let trie = || {
+            let mut trie: Trie<u32> = vec![("ＡＢＣ", 1), ("abc", 2), ("Abc", 3), ("東京", 4)].into_iter().collect();
+            trie.set_metadata("dary.normalizer", "nfkc,case_fold");
+            trie
+        };
+        let built = trie().build().unwrap();
+        assert_eq!(trie().to_double_array().unwrap().as_bytes(), built.as_bytes());
+        assert_eq!(vec![3, 2, 1], built.get("ABC").unwrap());
+    }
 }
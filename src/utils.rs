@@ -10,7 +10,60 @@ pub fn to_bytes<U>(slice: &[U]) -> &[u8] {
     unsafe {
         slice::from_raw_parts(
             slice.as_ptr() as *const u8,
-            mem::size_of::<U>() * slice.len()
+            mem::size_of_val(slice)
         )
     }
 }
+
+/// 符号なし整数を可変長(LEB128)でバイト列の末尾に追加する
+///
+/// # Arguments
+///
+/// * `buf`   - 書き込み先のバイト列
+/// * `value` - 書き込む値
+pub fn write_varint(buf: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// 可変長(LEB128)で書き込まれた符号なし整数を読み取る
+/// 読み取った値と消費したバイト数を返す
+///
+/// # Arguments
+///
+/// * `bytes` - 読み取り対象のバイト列
+pub fn read_varint(bytes: &[u8]) -> (usize, usize) {
+    let mut value: usize = 0;
+    let mut shift = 0;
+    for (i, &b) in bytes.iter().enumerate() {
+        value |= ((b & 0x7f) as usize) << shift;
+        if b & 0x80 == 0 {
+            return (value, i + 1);
+        }
+        shift += 7;
+    }
+    (value, bytes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint() {
+        let mut buf: Vec<u8> = Vec::new();
+        write_varint(&mut buf, 0);
+        write_varint(&mut buf, 127);
+        write_varint(&mut buf, 128);
+        write_varint(&mut buf, 300000);
+        // 127以下は1バイト、128は2バイト、300000は3バイト
+        assert_eq!(1 + 1 + 2 + 3, buf.len());
+        assert_eq!((0, 1)     , read_varint(&buf[0..]));
+        assert_eq!((127, 1)   , read_varint(&buf[1..]));
+        assert_eq!((128, 2)   , read_varint(&buf[2..]));
+        assert_eq!((300000, 3), read_varint(&buf[4..]));
+    }
+}
@@ -0,0 +1,124 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use dary::Trie;
+
+/// 辞書を作成してファイルに保存し、パスを返す
+fn dump_dictionary(name: &str, entries: &[(&str, u32)]) -> String {
+	let mut trie: Trie<u32> = Trie::new();
+	for &(key, value) in entries {
+		trie.set(key, value);
+	}
+	let path = current_path(name);
+	trie.to_double_array().unwrap().dump(&path).unwrap();
+	path
+}
+
+/// カレントディレクトリのファイルパスを返す
+fn current_path(name: &str) -> String {
+	let mut path: PathBuf = env::current_dir().unwrap();
+	path.push(name);
+	path.to_str().unwrap().to_string()
+}
+
+/// daryコマンドを実行して標準出力を返す
+fn run(args: &[&str]) -> String {
+	let output = Command::new(env!("CARGO_BIN_EXE_dary")).args(args).output().unwrap();
+	assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+	String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn cli_diff() {
+	let old = dump_dictionary("test_cli_diff_old.dic", &[("abc", 1), ("def", 2), ("xyz", 3)]);
+	let new = dump_dictionary("test_cli_diff_new.dic", &[("abc", 1), ("def", 4), ("ghi", 5)]);
+
+	let text = run(&["diff", &old, &new]);
+	assert_eq!("~\tdef\t[2]\t[4]\n+\tghi\t[5]\n-\txyz\t[3]\n", text);
+
+	let json = run(&["diff", "--format", "json", &old, &new]);
+	let lines: Vec<serde_json::Value> = json.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+	assert_eq!(serde_json::json!({ "op": "changed", "key": "def", "old": [2], "new": [4] }), lines[0]);
+	assert_eq!(serde_json::json!({ "op": "added", "key": "ghi", "values": [5] }), lines[1]);
+	assert_eq!(serde_json::json!({ "op": "removed", "key": "xyz", "values": [3] }), lines[2]);
+
+	// 不正な引数
+	let output = Command::new(env!("CARGO_BIN_EXE_dary")).args(["diff", &old]).output().unwrap();
+	assert!(!output.status.success());
+
+	fs::remove_file(old).unwrap();
+	fs::remove_file(new).unwrap();
+}
+
+#[test]
+fn cli_build_and_query() {
+	let source = current_path("test_cli_build.tsv");
+	let dictionary = current_path("test_cli_build.dic");
+	fs::write(&source, "東京\t1\n東京都\t2\n東京\t3\n\n京都\t4\n").unwrap();
+	run(&["build", "--type", "u32", "--layout", "compact", "--tail", &source, &dictionary]);
+
+	assert_eq!("[1,3]\n", run(&["get", &dictionary, "東京"]));
+	assert_eq!("東京\t[1,3]\n東京都\t[2]\n", run(&["prefix", &dictionary, "東京都庁"]));
+	assert_eq!("東京\t[1,3]\n", run(&["predict", "--limit", "1", &dictionary, "東"]));
+	assert_eq!("京都\t[4]\n東京\t[1,3]\n東京都\t[2]\n", run(&["dump", &dictionary]));
+	assert!(run(&["dump", "--format", "json", &dictionary]).starts_with("{\"key\":\"京都\",\"values\":[4]}\n"));
+	let stats = run(&["stats", &dictionary]);
+	assert!(stats.contains("layout\tCompact\n"));
+	assert!(stats.contains("key_count\t3\n"));
+	assert!(stats.contains("value_count\t4\n"));
+	assert!(stats.contains("metadata.dary.value_type\tu32\n"));
+	assert!(run(&["verify", &dictionary]).ends_with(": OK\n"));
+	let dumped = run(&["dump", &dictionary]);
+
+	// 文字単位の遷移でも同じ結果になる
+	run(&["build", "--type", "u32", "--alphabet", "codepoint", &source, &dictionary]);
+	assert!(run(&["stats", &dictionary]).contains("alphabet\tCodePoint\n"));
+	assert_eq!(dumped, run(&["dump", &dictionary]));
+	assert_eq!("東京\t[1,3]\n東京都\t[2]\n", run(&["prefix", &dictionary, "東京都庁"]));
+
+	// 正規化した辞書はクエリも正規化する
+	fs::write(&source, "ＡＢＣ\t1\n").unwrap();
+	run(&["build", "--type", "u32", "--normalize", "nfkc,case_fold", &source, &dictionary]);
+	assert_eq!("[1]\n", run(&["get", &dictionary, "Abc"]));
+	assert!(run(&["stats", &dictionary]).contains("metadata.dary.normalizer\tnfkc,case_fold\n"));
+
+	// 見つからないキー
+	let output = Command::new(env!("CARGO_BIN_EXE_dary")).args(["get", &dictionary, "大阪"]).output().unwrap();
+	assert!(!output.status.success());
+
+	// 壊れた辞書は検証に失敗する
+	let mut bytes = fs::read(&dictionary).unwrap();
+	bytes[200] ^= 0xFF;
+	fs::write(&dictionary, bytes).unwrap();
+	let output = Command::new(env!("CARGO_BIN_EXE_dary")).args(["verify", &dictionary]).output().unwrap();
+	assert!(!output.status.success());
+
+	fs::remove_file(source).unwrap();
+	fs::remove_file(dictionary).unwrap();
+}
+
+#[test]
+fn cli_build_csv_and_json_lines() {
+	let csv = current_path("test_cli_build.csv");
+	let jsonl = current_path("test_cli_build.jsonl");
+	let dictionary = current_path("test_cli_build_csv.dic");
+	fs::write(&csv, "abc,\"x, y\"\nabd,z\n").unwrap();
+	run(&["build", &csv, &dictionary]);
+	assert_eq!("[\"x, y\"]\n", run(&["get", &dictionary, "abc"]));
+
+	fs::write(&jsonl, "{\"key\": \"abc\", \"value\": -1.5}\n").unwrap();
+	run(&["build", "--type", "f64", &jsonl, &dictionary]);
+	assert_eq!("[-1.5]\n", run(&["get", &dictionary, "abc"]));
+
+	// 値を変換できない行は行番号を含むエラーになる
+	fs::write(&csv, "abc,1\nabd,x\n").unwrap();
+	let output = Command::new(env!("CARGO_BIN_EXE_dary")).args(["build", "--type", "u32", &csv, &dictionary]).output().unwrap();
+	assert!(!output.status.success());
+	assert!(String::from_utf8_lossy(&output.stderr).contains("line 2"));
+
+	fs::remove_file(csv).unwrap();
+	fs::remove_file(jsonl).unwrap();
+	fs::remove_file(dictionary).unwrap();
+}
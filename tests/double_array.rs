@@ -1,3 +1,5 @@
+#![allow(clippy::redundant_field_names, clippy::needless_borrow)]
+
 use std::env;
 use std::fs;
 use std::path::PathBuf;
@@ -5,8 +7,14 @@ use std::fmt::Debug;
 
 use dary::DoubleArray;
 use dary::Trie;
+use dary::DoubleArrayBuilder;
+use dary::Alphabet;
+use dary::Layout;
+use dary::MergePolicy;
+use dary::Normalizer;
 
-use rand::{Rng, thread_rng};
+use rand::{Rng, SeedableRng, thread_rng};
+use rand::rngs::StdRng;
 use rand::distributions::Alphanumeric;
 use serde_derive::{Serialize, Deserialize};
 
@@ -99,4 +107,416 @@ fn double_array_3() {
 	}
 
 	fs::remove_file(path).unwrap();
-}
\ No newline at end of file
+}
+#[test]
+fn double_array_tail() {
+	// 日本語の語彙に近いキー: ひらがなと漢字からなる2〜6文字の語
+	let mut rng = StdRng::seed_from_u64(26);
+	let mut keys: Vec<String> = Vec::new();
+	for _ in 0..10000 {
+		let len = rng.gen_range(2, 7);
+		keys.push((0..len).map(|_| {
+			let code = if rng.gen_bool(0.5) { 0x3041 + rng.gen_range(0, 83) } else { 0x4E00 + rng.gen_range(0, 2000) };
+			std::char::from_u32(code).unwrap()
+		}).collect());
+	}
+
+	let mut trie: Trie<u32> = Trie::new();
+	let mut tail_trie: Trie<u32> = Trie::new();
+	for (i, key) in keys.iter().enumerate() {
+		trie.set(key, i as u32);
+		tail_trie.set(key, i as u32);
+	}
+
+	let double_array: DoubleArray<u32> = trie.to_double_array().unwrap();
+	let tail_double_array: DoubleArray<u32> = DoubleArrayBuilder::new().tail(true).build(tail_trie).unwrap();
+
+	let mut path: PathBuf = env::current_dir().unwrap();
+	path.push("test_double_array_tail_1.dic");
+	let mut tail_path: PathBuf = env::current_dir().unwrap();
+	tail_path.push("test_double_array_tail_2.dic");
+	double_array.dump(path.to_str().unwrap()).unwrap();
+	let tail_double_array = tail_double_array.dump(tail_path.to_str().unwrap()).unwrap();
+
+	for (i, key) in keys.iter().enumerate() {
+		assert!(tail_double_array.get(key).unwrap().contains(&(i as u32)));
+	}
+	// tail圧縮した辞書は2倍以上小さい
+	let ratio = fs::metadata(&path).unwrap().len() as f64 / fs::metadata(&tail_path).unwrap().len() as f64;
+	assert!(ratio >= 2.0, "ratio: {}", ratio);
+
+	fs::remove_file(path).unwrap();
+	fs::remove_file(tail_path).unwrap();
+}
+
+#[test]
+fn double_array_layout() {
+	let mut keys: Vec<String> = Vec::new();
+	for _ in 0..1000 {
+		keys.push(thread_rng().sample_iter(Alphanumeric).take(10).collect::<String>());
+	}
+
+	for (n, &layout) in [Layout::Separate, Layout::Interleaved, Layout::Compact].iter().enumerate() {
+		let mut trie: Trie<u32> = Trie::new();
+		for (i, key) in keys.iter().enumerate() {
+			trie.set(key, i as u32);
+		}
+
+		let double_array: DoubleArray<u32> = DoubleArrayBuilder::new().layout(layout).build(trie).unwrap();
+
+		let mut path: PathBuf = env::current_dir().unwrap();
+		path.push(format!("test_double_array_layout_{n}.dic"));
+		let double_array = double_array.dump(path.to_str().unwrap()).unwrap();
+
+		// ファイルから読み込んでもレイアウトが保持されている
+		assert_eq!(layout, double_array.layout());
+		for (i, key) in keys.iter().enumerate() {
+			assert!(double_array.get(key).unwrap().contains(&(i as u32)));
+		}
+
+		fs::remove_file(path).unwrap();
+	}
+}
+
+#[test]
+fn double_array_deterministic() {
+	let mut entries: Vec<(String, usize)> = Vec::new();
+	for i in 0..1000 {
+		entries.push((thread_rng().sample_iter(Alphanumeric).take(10).collect::<String>(), i));
+	}
+
+	let mut paths: Vec<PathBuf> = Vec::new();
+	let mut hashes: Vec<u64> = Vec::new();
+	for n in 0..2 {
+		// 登録順序を入れ替えても同じ辞書になる
+		let mut trie: Trie<MorphemeData> = Trie::new();
+		for (key, i) in entries.iter() {
+			trie.set(key, MorphemeData::new(key, *i));
+		}
+		entries.reverse();
+
+		let double_array: DoubleArray<MorphemeData> = DoubleArrayBuilder::new().tail(true).build(trie).unwrap();
+		let mut path: PathBuf = env::current_dir().unwrap();
+		path.push(format!("test_double_array_deterministic_{n}.dic"));
+		let double_array = double_array.dump(path.to_str().unwrap()).unwrap();
+		hashes.push(double_array.content_hash());
+		paths.push(path);
+	}
+
+	assert_eq!(hashes[0], hashes[1]);
+	assert_eq!(fs::read(&paths[0]).unwrap(), fs::read(&paths[1]).unwrap());
+	paths.iter().for_each(|path| fs::remove_file(path).unwrap());
+}
+
+#[test]
+fn double_array_verified() {
+	let mut keys: Vec<String> = Vec::new();
+	for _ in 0..1000 {
+		keys.push(thread_rng().sample_iter(Alphanumeric).take(10).collect::<String>());
+	}
+
+	let mut trie: Trie<u32> = Trie::new();
+	for (i, key) in keys.iter().enumerate() {
+		trie.set(key, i as u32);
+	}
+	let double_array: DoubleArray<u32> = trie.to_double_array().unwrap();
+	let mut path: PathBuf = env::current_dir().unwrap();
+	path.push("test_double_array_verified.dic");
+	let path_str = path.to_str().unwrap();
+	double_array.dump(path_str).unwrap();
+	let bytes = fs::read(&path).unwrap();
+	assert!(DoubleArray::<u32>::from_file_verified(path_str).is_ok());
+
+	// 途中で切れたファイルは from_file でエラーになる
+	fs::write(&path, &bytes[..(bytes.len() - 1)]).unwrap();
+	assert!(DoubleArray::<u32>::from_file(path_str).is_err());
+
+	// 内容が壊れたファイルは from_file_verified でエラーになる
+	// base配列の途中のバイトを書き換える
+	let mut corrupted = bytes.clone();
+	corrupted[200] ^= 0xff;
+	fs::write(&path, &corrupted).unwrap();
+	assert!(DoubleArray::<u32>::from_file(path_str).is_ok());
+	assert!(DoubleArray::<u32>::from_file_verified(path_str).is_err());
+
+	fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn double_array_metadata() {
+	let mut trie: Trie<MorphemeData> = Trie::new();
+	trie.set("abc", MorphemeData::new("abc", 1));
+	trie.set_metadata("source", "sample.csv");
+
+	let double_array = DoubleArrayBuilder::new().metadata("name", "sample").build(trie).unwrap();
+	let mut path: PathBuf = env::current_dir().unwrap();
+	path.push("test_double_array_metadata.dic");
+	let double_array = double_array.dump(path.to_str().unwrap()).unwrap();
+
+	// ファイルから読み込んでもメタデータが保持されている
+	let metadata = double_array.metadata();
+	assert_eq!("sample.csv", metadata["source"]);
+	assert_eq!("sample", metadata["name"]);
+	assert_eq!("1", metadata["dary.key_count"]);
+	assert!(metadata["dary.value_type"].ends_with("MorphemeData"));
+
+	fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn double_array_value_type() {
+	let mut trie: Trie<MorphemeData> = Trie::new();
+	trie.set("abc", MorphemeData::new("abc", 1));
+
+	let double_array = DoubleArrayBuilder::new().schema_version(2).build(trie).unwrap();
+	let mut path: PathBuf = env::current_dir().unwrap();
+	path.push("test_double_array_value_type.dic");
+	let path_str = path.to_str().unwrap();
+	double_array.dump(path_str).unwrap();
+
+	// 値の型が違う場合はエラーになる
+	let err = DoubleArray::<u32>::from_file(path_str).unwrap_err();
+	assert_eq!(std::io::ErrorKind::InvalidData, err.kind());
+	assert!(DoubleArray::<u32>::from_file_unchecked(path_str).is_ok());
+	assert!(DoubleArray::<MorphemeData>::from_file(path_str).is_ok());
+
+	// スキーマのバージョンが違う場合はエラーになる
+	let double_array = DoubleArray::<MorphemeData>::from_file_with_schema(path_str, 2).unwrap();
+	assert_eq!(Some(2), double_array.schema_version().unwrap());
+	assert!(DoubleArray::<MorphemeData>::from_file_with_schema(path_str, 3).is_err());
+
+	// 同じバージョンの辞書をまとめるとバージョンを引き継ぎ、異なるバージョンの辞書は指定しないとまとめられない
+	let mut trie: Trie<MorphemeData> = Trie::new();
+	trie.set("xyz", MorphemeData::new("xyz", 2));
+	let other = DoubleArrayBuilder::new().schema_version(2).build(trie).unwrap();
+	let merged = DoubleArray::merge(&[&double_array, &other], MergePolicy::Concatenate).unwrap();
+	assert_eq!(Some(2), merged.schema_version().unwrap());
+	let plain = Trie::<MorphemeData>::new().to_double_array().unwrap();
+	assert_eq!(std::io::ErrorKind::InvalidInput, DoubleArray::merge(&[&double_array, &plain], MergePolicy::Concatenate).err().unwrap().kind());
+	let merged = DoubleArrayBuilder::new().schema_version(3).merge(&[&double_array, &plain], MergePolicy::Concatenate).unwrap();
+	assert_eq!(Some(3), merged.schema_version().unwrap());
+
+	fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn double_array_compress_data() {
+	let mut keys: Vec<String> = Vec::new();
+	for _ in 0..1000 {
+		keys.push(thread_rng().sample_iter(Alphanumeric).take(10).collect::<String>());
+	}
+
+	let mut trie: Trie<MorphemeData> = Trie::new();
+	for (i, key) in keys.iter().enumerate() {
+		trie.set(key, MorphemeData::new(&key.repeat(20), i));
+	}
+	let double_array = DoubleArrayBuilder::new().compress_data(true).data_block_size(1024).build(trie).unwrap();
+	let mut path: PathBuf = env::current_dir().unwrap();
+	path.push("test_double_array_compress_data.dic");
+	let double_array = double_array.dump(path.to_str().unwrap()).unwrap();
+
+	// ファイルから読み込んでも圧縮したdata配列から値を取得できる
+	for (i, key) in keys.iter().enumerate() {
+		assert_eq!(vec![MorphemeData::new(&key.repeat(20), i)], double_array.get(key).unwrap());
+	}
+	assert!(double_array.verify().is_ok());
+
+	fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn double_array_merge() {
+	let mut system: Trie<u32> = Trie::new();
+	let mut user: Trie<u32> = Trie::new();
+	for i in 0..1000 {
+		system.set(&format!("key{i}"), i);
+	}
+	for i in 500..1500 {
+		user.set(&format!("key{i}"), i + 10000);
+	}
+	system.set_metadata("source", "system");
+	user.set_metadata("source", "user");
+	let system = system.to_double_array().unwrap();
+	let user = DoubleArrayBuilder::new().compress_data(true).build(user).unwrap();
+
+	let merged = DoubleArray::merge(&[&system, &user], MergePolicy::Concatenate).unwrap();
+	assert_eq!(vec![1], merged.get("key1").unwrap());
+	assert_eq!(vec![700, 10700], merged.get("key700").unwrap());
+	assert_eq!(vec![11200], merged.get("key1200").unwrap());
+	assert_eq!(None, merged.get("key1500"));
+	assert_eq!("1500", merged.metadata()["dary.key_count"]);
+	// 後ろの辞書のメタデータが優先される
+	assert_eq!("user", merged.metadata()["source"]);
+
+	let merged = DoubleArrayBuilder::new().tail(true).merge(&[&system, &user], MergePolicy::PreferFirst).unwrap();
+	assert_eq!(vec![700], merged.get("key700").unwrap());
+	let merged = DoubleArrayBuilder::new().layout(Layout::Compact).merge(&[&system, &user], MergePolicy::PreferLast).unwrap();
+	assert_eq!(vec![10700], merged.get("key700").unwrap());
+	assert_eq!(vec![400], merged.get("key400").unwrap());
+
+	// まとめた辞書は同じキーと値から構築した辞書と同じ
+	let mut trie: Trie<u32> = Trie::new();
+	for (key, values) in merged.iter() {
+		for value in values {
+			trie.set(&key, value);
+		}
+	}
+	trie.set_metadata("source", "user");
+	assert_eq!(
+		DoubleArrayBuilder::new().layout(Layout::Compact).build(trie).unwrap().content_hash(),
+		merged.content_hash()
+	);
+}
+
+#[test]
+fn double_array_code_point() {
+	let keys = ["東京", "東京都", "京都", "京都府", "すもも", "もも", "abc"];
+	let mut trie: Trie<u32> = Trie::new();
+	for (i, key) in keys.iter().enumerate() {
+		trie.set(key, i as u32);
+	}
+	let double_array = DoubleArrayBuilder::new().tail(true).alphabet(Alphabet::CodePoint).build(trie).unwrap();
+	let mut path: PathBuf = env::current_dir().unwrap();
+	path.push("test_double_array_code_point.dic");
+	let double_array = double_array.dump(path.to_str().unwrap()).unwrap();
+
+	// ファイルから読み込んでも符号表でキーを変換する
+	assert_eq!(Alphabet::CodePoint, double_array.alphabet());
+	assert!(double_array.verify().is_ok());
+	for (i, key) in keys.iter().enumerate() {
+		assert_eq!(vec![i as u32], double_array.get(key).unwrap());
+	}
+	let prefixes: Vec<&str> = double_array.prefix_search("東京都庁").into_iter().map(|(prefix, _)| prefix).collect();
+	assert_eq!(vec!["東京", "東京都"], prefixes);
+
+	// Byte の辞書とまとめても、ビルダーの指定に従う
+	let mut trie: Trie<u32> = Trie::new();
+	trie.set("大阪", 10);
+	let other = trie.to_double_array().unwrap();
+	assert_eq!(Alphabet::Byte, other.alphabet());
+	let merged = DoubleArrayBuilder::new().alphabet(Alphabet::CodePoint).merge(&[&double_array, &other], MergePolicy::Concatenate).unwrap();
+	assert_eq!(Alphabet::CodePoint, merged.alphabet());
+	assert_eq!(vec![10], merged.get("大阪").unwrap());
+	let merged = DoubleArray::merge(&[&double_array, &other], MergePolicy::Concatenate).unwrap();
+	assert_eq!(Alphabet::Byte, merged.alphabet());
+	assert_eq!(double_array.iter().count() + 1, merged.iter().count());
+
+	fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn double_array_normalizer() {
+	let mut trie: Trie<u32> = Trie::new();
+	trie.set("ＴＯＫＹＯ", 1);
+	trie.set("tokyo", 2);
+	trie.set("ｶﾞｽ", 3);
+	let normalizer = Normalizer::new().nfkc(true).case_fold(true).kana_fold(true);
+	let double_array = DoubleArrayBuilder::new().normalizer(normalizer).build(trie).unwrap();
+	let mut path: PathBuf = env::current_dir().unwrap();
+	path.push("test_double_array_normalizer.dic");
+	let double_array = double_array.dump(path.to_str().unwrap()).unwrap();
+
+	// ファイルから読み込んでも正規化を引き継ぎ、正規化で重なったキーの値はまとめる
+	assert_eq!(Some(normalizer), double_array.normalizer());
+	assert!(double_array.verify().is_ok());
+	assert_eq!(vec![2, 1], double_array.get("Tokyo").unwrap());
+	assert_eq!(vec![3], double_array.get("がす").unwrap());
+	let keys: Vec<String> = double_array.iter().map(|(key, _)| key).collect();
+	assert_eq!(vec!["tokyo", "がす"], keys);
+	let prefixes: Vec<&str> = double_array.prefix_search("ｶﾞｽ管").into_iter().map(|(prefix, _)| prefix).collect();
+	assert_eq!(vec!["ｶﾞｽ"], prefixes);
+
+	// 同じ正規化の辞書をまとめると正規化を引き継ぐ
+	let mut trie: Trie<u32> = Trie::new();
+	trie.set("OSAKA", 4);
+	let other = DoubleArrayBuilder::new().normalizer(normalizer).build(trie).unwrap();
+	let merged = DoubleArray::merge(&[&double_array, &other], MergePolicy::Concatenate).unwrap();
+	assert_eq!(Some(normalizer), merged.normalizer());
+	assert_eq!(vec![4], merged.get("Osaka").unwrap());
+	// 正規化の異なる辞書は、正規化を指定しないとまとめられない
+	let plain = Trie::<u32>::new().to_double_array().unwrap();
+	assert_eq!(std::io::ErrorKind::InvalidInput, DoubleArray::merge(&[&double_array, &plain], MergePolicy::Concatenate).err().unwrap().kind());
+	let merged = DoubleArrayBuilder::new().normalizer(normalizer).merge(&[&double_array, &plain], MergePolicy::Concatenate).unwrap();
+	assert_eq!(Some(normalizer), merged.normalizer());
+
+	fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn trie_dump_and_resume() {
+	let mut trie: Trie<u32> = Trie::new();
+	trie.set("東京", 1);
+	trie.set("東京都", 2);
+	trie.set_metadata("source", "a.csv");
+	let mut path: PathBuf = env::current_dir().unwrap();
+	path.push("test_trie_dump_and_resume.trie");
+	let path = path.to_str().unwrap().to_string();
+	trie.dump(&path).unwrap();
+
+	// 読み込んだトライ木にキーを追加してから変換する
+	let mut trie: Trie<u32> = Trie::from_file(&path).unwrap();
+	trie.set("東京", 3);
+	trie.set("京都", 4);
+	let double_array = trie.to_double_array().unwrap();
+	assert_eq!(vec![1, 3], double_array.get("東京").unwrap());
+	assert_eq!(vec![2], double_array.get("東京都").unwrap());
+	assert_eq!(vec![4], double_array.get("京都").unwrap());
+	assert_eq!("a.csv", double_array.metadata()["source"]);
+	assert_eq!("3", double_array.metadata()["dary.key_count"]);
+
+	// 壊れたファイルは読み込めない
+	let bytes = fs::read(&path).unwrap();
+	fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+	assert!(Trie::<u32>::from_file(&path).is_err());
+
+	fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn double_array_load_edit_rebuild() {
+	let mut trie: Trie<u32> = Trie::new();
+	trie.set("ＴＯＫＹＯ", 1);
+	trie.set("京都", 2);
+	let normalizer = Normalizer::new().nfkc(true).case_fold(true);
+	let double_array = DoubleArrayBuilder::new().normalizer(normalizer).layout(Layout::Compact).build(trie).unwrap();
+	let mut path: PathBuf = env::current_dir().unwrap();
+	path.push("test_double_array_load_edit_rebuild.dic");
+	double_array.dump(path.to_str().unwrap()).unwrap();
+	let double_array: DoubleArray<u32> = DoubleArray::from_file(path.to_str().unwrap()).unwrap();
+
+	// 読み込んだ辞書をトライ木に戻して編集し、構築し直すと正規化も引き継ぐ
+	let mut trie = Trie::from_double_array(&double_array);
+	assert_eq!(Some(&[1][..]), trie.get("tokyo"));
+	trie.set("ＯＳＡＫＡ", 3);
+	let rebuilt = trie.build().unwrap();
+	assert_eq!(Some(normalizer), rebuilt.normalizer());
+	assert_eq!(vec![3], rebuilt.get("Osaka").unwrap());
+	assert_eq!(vec![1], rebuilt.get("Tokyo").unwrap());
+	// 構築オプションは引き継がない
+	assert_eq!(Layout::Separate, rebuilt.layout());
+	let rebuilt = DoubleArrayBuilder::new().layout(Layout::Compact).build(trie).unwrap();
+	assert_eq!(Layout::Compact, rebuilt.layout());
+	assert_eq!(vec![2], rebuilt.get("京都").unwrap());
+
+	fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn double_array_legacy_file() {
+	// TAIL圧縮の導入前の形式: usizeのヘッダ (base, check, dataの開始位置とbase, checkの長さ) と配列を連結したもの
+	let base: Vec<u32> = vec![0, 1, 0, 0];
+	let check: Vec<u32> = vec![0, 0, 0, 0];
+	let header: [u64; 5] = [40, 56, 72, 4, 4];
+	let mut bytes: Vec<u8> = header.iter().flat_map(|n| n.to_le_bytes()).collect();
+	bytes.extend(base.iter().chain(check.iter()).flat_map(|n| n.to_le_bytes()));
+	let mut path: PathBuf = env::current_dir().unwrap();
+	path.push("test_double_array_legacy_file.dic");
+	fs::write(&path, &bytes).unwrap();
+
+	// 誤って読み込まずに InvalidData で拒否する
+	let error = DoubleArray::<u32>::from_file(path.to_str().unwrap()).unwrap_err();
+	assert_eq!(std::io::ErrorKind::InvalidData, error.kind());
+	assert_eq!(std::io::ErrorKind::InvalidData, DoubleArray::<u32>::from_slice(&bytes).unwrap_err().kind());
+
+	fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,107 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use dary::DoubleArrayBuilder;
+use dary::mecab::{MecabDictionary, MecabImporter, Morpheme};
+use dary::tokenizer::{Tokenizer, TokenKind};
+
+/// テスト用の小さな辞書のソースディレクトリ
+fn fixture_dir() -> String {
+	format!("{}/tests/fixtures/mecab", env!("CARGO_MANIFEST_DIR"))
+}
+
+#[test]
+fn mecab_dictionary() {
+	let dictionary = MecabDictionary::from_source_dir(&fixture_dir(), &MecabImporter::new(), &DoubleArrayBuilder::new().tail(true)).unwrap();
+	let expected = Morpheme {
+		left_id : 1,
+		right_id: 1,
+		cost    : 7000,
+		feature : String::from("名詞,一般,*,*,*,*,すもも,スモモ,スモモ"),
+	};
+	assert_eq!(vec![expected.clone()], dictionary.double_array.get("すもも").unwrap());
+	assert_eq!(2, dictionary.double_array.get("も").unwrap()[0].left_id);
+	assert_eq!(4, dictionary.matrix.forward_size());
+	assert_eq!(-500, dictionary.matrix.cost(1, 2));
+
+	// 保存したディレクトリから読み込む
+	let mut dir: PathBuf = env::current_dir().unwrap();
+	dir.push("test_mecab_dictionary");
+	let dir = dir.to_str().unwrap().to_string();
+	let dictionary = dictionary.dump(&dir).unwrap();
+	let loaded = MecabDictionary::from_dir(&dir).unwrap();
+	assert_eq!(vec![expected], loaded.double_array.get("すもも").unwrap());
+	assert_eq!(dictionary.matrix, loaded.matrix);
+	assert_eq!(dictionary.double_array.content_hash(), loaded.double_array.content_hash());
+	let (unknown, loaded_unknown) = (dictionary.unknown.unwrap(), loaded.unknown.unwrap());
+	assert_eq!(unknown.char_definition, loaded_unknown.char_definition);
+	assert_eq!("KATAKANA", loaded_unknown.char_definition.category_of('ス').name);
+	assert_eq!(6000, loaded_unknown.double_array.get("KATAKANA").unwrap()[0].cost);
+
+	fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn mecab_dictionary_invalid_context_id() {
+	let mut dir: PathBuf = env::current_dir().unwrap();
+	dir.push("test_mecab_invalid_source");
+	fs::create_dir_all(&dir).unwrap();
+	fs::copy(format!("{}/matrix.def", fixture_dir()), dir.join("matrix.def")).unwrap();
+	fs::write(dir.join("lex.csv"), "すもも,1,1,7000,名詞\nもも,1,4,7000,名詞\n").unwrap();
+
+	let result = MecabDictionary::from_source_dir(dir.to_str().unwrap(), &MecabImporter::new(), &DoubleArrayBuilder::new());
+	let message = result.err().unwrap().to_string();
+	assert!(message.contains("lex.csv: line 2"), "{}", message);
+
+	fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn mecab_tokenize() {
+	let dictionary = MecabDictionary::from_source_dir(&fixture_dir(), &MecabImporter::new(), &DoubleArrayBuilder::new()).unwrap();
+	let tokenizer = Tokenizer::from_dictionary(dictionary);
+	let tokens = tokenizer.tokenize("すもももももももものうち");
+	assert_eq!(vec!["すもも", "も", "もも", "も", "もも", "の", "うち"], surfaces(&tokens));
+	assert!(tokens[5].morpheme.feature.starts_with("助詞,連体化"));
+	assert_eq!((27, 30), (tokens[5].start, tokens[5].end));
+
+	// 位置0から始まる辞書の形態素は 東京 だけなので、東/京都 は候補にならない
+	let results = tokenizer.tokenize_nbest("東京都", 3);
+	assert_eq!(1, results.len());
+	assert_eq!(vec!["東京", "都"], surfaces(&results[0].tokens));
+}
+
+#[test]
+fn mecab_tokenize_unknown_words() {
+	let dictionary = MecabDictionary::from_source_dir(&fixture_dir(), &MecabImporter::new(), &DoubleArrayBuilder::new()).unwrap();
+	let tokenizer = Tokenizer::from_dictionary(dictionary);
+	// 同じ文字種の連続を1つの未知語にする
+	let tokens = tokenizer.tokenize("すももとスモモ123");
+	assert_eq!(vec!["すもも", "と", "スモモ", "123"], surfaces(&tokens));
+	assert_eq!(TokenKind::Unknown, tokens[2].kind);
+	assert!(tokens[2].morpheme.feature.starts_with("名詞,一般"));
+	assert!(tokens[3].morpheme.feature.starts_with("名詞,数"));
+
+	// KANJI は group しないので、最大2文字ずつの未知語になる
+	let tokens = tokenizer.tokenize("大阪府");
+	assert_eq!(2, tokens.len());
+	assert!(tokens.iter().all(|token| token.kind == TokenKind::Unknown && token.surface.chars().count() <= 2));
+
+	// char.def がない場合は1文字ずつの未知語になる
+	let mut dir: PathBuf = env::current_dir().unwrap();
+	dir.push("test_mecab_without_unknown");
+	fs::create_dir_all(&dir).unwrap();
+	for file in ["matrix.def", "Noun.csv"].iter() {
+		fs::copy(format!("{}/{}", fixture_dir(), file), dir.join(file)).unwrap();
+	}
+	let dictionary = MecabDictionary::from_source_dir(dir.to_str().unwrap(), &MecabImporter::new(), &DoubleArrayBuilder::new()).unwrap();
+	assert!(dictionary.unknown.is_none());
+	let tokens = Tokenizer::from_dictionary(dictionary).tokenize("スモモ");
+	assert_eq!(vec!["ス", "モ", "モ"], surfaces(&tokens));
+	fs::remove_dir_all(dir).unwrap();
+}
+
+fn surfaces<'t>(tokens: &[dary::tokenizer::Token<'t>]) -> Vec<&'t str> {
+	tokens.iter().map(|token| token.surface).collect()
+}